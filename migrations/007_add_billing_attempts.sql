@@ -0,0 +1,13 @@
+-- Track route failover on billing transactions
+-- Migration: 007
+-- Description: Number of routes attempted before the request was served
+
+ALTER TABLE billing_transactions
+    ADD COLUMN attempts INTEGER NOT NULL DEFAULT 1;
+
+ALTER TABLE billing_transactions
+    ADD CONSTRAINT positive_attempts CHECK (attempts >= 1);
+
+-- Comment
+COMMENT ON COLUMN billing_transactions.attempts IS 'Routes tried in the fallback chain (1 = served by primary)';
+COMMENT ON COLUMN billing_transactions.provider IS 'Provider of the route that actually served the request';
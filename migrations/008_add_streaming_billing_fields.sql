@@ -0,0 +1,16 @@
+-- Bill streamed responses when the stream ends
+-- Migration: 008
+-- Description: Time to first token, and a status for streams the client abandoned
+
+ALTER TABLE billing_transactions
+    ADD COLUMN time_to_first_token_ms INTEGER;
+
+ALTER TABLE billing_transactions
+    DROP CONSTRAINT valid_status;
+
+ALTER TABLE billing_transactions
+    ADD CONSTRAINT valid_status CHECK (status IN ('success', 'error', 'timeout', 'cancelled'));
+
+-- Comment
+COMMENT ON COLUMN billing_transactions.time_to_first_token_ms IS 'Streaming only: milliseconds until the first content chunk';
+COMMENT ON COLUMN billing_transactions.response_time_ms IS 'Milliseconds until the response (or stream) completed';
@@ -0,0 +1,14 @@
+-- Distinguish provider-reported usage from local estimates
+-- Migration: 009
+-- Description: usage_source is 'estimated' when tokens were counted locally because the provider reported none
+
+ALTER TABLE billing_transactions
+    ADD COLUMN usage_source VARCHAR(16) NOT NULL DEFAULT 'provider';
+
+ALTER TABLE billing_transactions
+    ADD CONSTRAINT valid_usage_source CHECK (usage_source IN ('provider', 'estimated'));
+
+CREATE INDEX idx_billing_usage_source ON billing_transactions(usage_source) WHERE usage_source = 'estimated';
+
+-- Comment
+COMMENT ON COLUMN billing_transactions.usage_source IS 'provider = usage reported upstream; estimated = counted with the route tokenizer';
@@ -0,0 +1,52 @@
+-- Resale rate cards and billed cost per transaction
+-- Migration: 010
+-- Description: Per-tenant / per-API-key markup, request fees, model price overrides and free tokens
+
+CREATE TABLE rate_cards (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+
+    -- Scope: tenant-wide when api_key_id is NULL, otherwise a single key
+    tenant_id VARCHAR(255) NOT NULL,
+    api_key_id UUID REFERENCES api_keys(id) ON DELETE CASCADE,
+
+    -- Cards are never updated; a change inserts the next version
+    version INTEGER NOT NULL,
+
+    -- Terms
+    markup_percent DECIMAL(8, 4) NOT NULL DEFAULT 0,
+    request_fee DECIMAL(12, 8) NOT NULL DEFAULT 0,
+    -- Format: {"claude-sonnet-4.5": {"prompt": 0.000004, "completion": 0.00002}, ...}
+    model_prices JSONB NOT NULL DEFAULT '{}',
+    free_tokens_per_month BIGINT NOT NULL DEFAULT 0,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT positive_version CHECK (version > 0),
+    CONSTRAINT non_negative_markup CHECK (markup_percent >= -100),
+    CONSTRAINT non_negative_request_fee CHECK (request_fee >= 0),
+    CONSTRAINT non_negative_free_tokens CHECK (free_tokens_per_month >= 0)
+);
+
+-- One row per version and scope; NULL api_key_id counts as a scope of its own
+CREATE UNIQUE INDEX idx_rate_cards_scope_version ON rate_cards(
+    tenant_id,
+    COALESCE(api_key_id, '00000000-0000-0000-0000-000000000000'::uuid),
+    version
+);
+
+ALTER TABLE billing_transactions RENAME COLUMN total_cost TO upstream_cost;
+
+ALTER TABLE billing_transactions
+    ADD COLUMN billed_cost DECIMAL(12, 8) NOT NULL DEFAULT 0;
+
+-- Transactions before rate cards were billed at cost
+UPDATE billing_transactions SET billed_cost = upstream_cost;
+
+ALTER TABLE billing_transactions
+    ADD CONSTRAINT positive_billed_cost CHECK (billed_cost >= 0);
+
+-- Comment
+COMMENT ON TABLE rate_cards IS 'Versioned resale terms; the highest version of a scope is active';
+COMMENT ON COLUMN rate_cards.model_prices IS 'Per-model USD-per-token prices keyed by logical model or provider_model_id; replace upstream cost and markup';
+COMMENT ON COLUMN billing_transactions.upstream_cost IS 'Cost charged by the provider (CostCalculator)';
+COMMENT ON COLUMN billing_transactions.billed_cost IS 'Cost charged to the tenant after its rate card';
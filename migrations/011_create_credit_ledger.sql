@@ -0,0 +1,72 @@
+-- Prepaid credit balances
+-- Migration: 011
+-- Description: Per-tenant / per-API-key balances, a ledger of top-ups, debits and adjustments, and in-flight reservations
+
+CREATE TABLE credit_accounts (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+
+    -- Scope: tenant-wide when api_key_id is NULL, otherwise a single key
+    tenant_id VARCHAR(255) NOT NULL,
+    api_key_id UUID REFERENCES api_keys(id) ON DELETE CASCADE,
+
+    -- USD; balance is what is left after settled debits
+    balance DECIMAL(16, 8) NOT NULL DEFAULT 0,
+    -- Held for requests still in flight
+    reserved DECIMAL(16, 8) NOT NULL DEFAULT 0,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT non_negative_reserved CHECK (reserved >= 0)
+);
+
+CREATE UNIQUE INDEX idx_credit_accounts_scope ON credit_accounts(
+    tenant_id,
+    COALESCE(api_key_id, '00000000-0000-0000-0000-000000000000'::uuid)
+);
+
+CREATE TABLE credit_ledger (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    account_id UUID NOT NULL REFERENCES credit_accounts(id) ON DELETE CASCADE,
+
+    entry_type VARCHAR(20) NOT NULL,
+    -- Signed: top-ups are positive, debits negative
+    amount DECIMAL(16, 8) NOT NULL,
+    balance_after DECIMAL(16, 8) NOT NULL,
+
+    -- Debits point at the billed request
+    request_id VARCHAR(255),
+    note TEXT,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT valid_entry_type CHECK (entry_type IN ('top_up', 'debit', 'adjustment')),
+    CONSTRAINT positive_top_up CHECK (entry_type != 'top_up' OR amount > 0),
+    CONSTRAINT non_positive_debit CHECK (entry_type != 'debit' OR amount <= 0)
+);
+
+-- A request is debited at most once
+CREATE UNIQUE INDEX idx_credit_ledger_debit_request ON credit_ledger(request_id) WHERE entry_type = 'debit';
+CREATE INDEX idx_credit_ledger_account_time ON credit_ledger(account_id, created_at DESC);
+
+CREATE TABLE credit_reservations (
+    request_id VARCHAR(255) PRIMARY KEY,
+    account_id UUID NOT NULL REFERENCES credit_accounts(id) ON DELETE CASCADE,
+    amount DECIMAL(16, 8) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT non_negative_reservation CHECK (amount >= 0)
+);
+
+CREATE INDEX idx_credit_reservations_created_at ON credit_reservations(created_at);
+
+CREATE TRIGGER trigger_update_credit_account_timestamp
+    BEFORE UPDATE ON credit_accounts
+    FOR EACH ROW
+    EXECUTE FUNCTION update_billing_summary_timestamp();
+
+-- Comment
+COMMENT ON TABLE credit_accounts IS 'Prepaid balances; tenants or keys without an account are not limited';
+COMMENT ON COLUMN credit_accounts.reserved IS 'Sum of credit_reservations: estimated cost of requests in flight';
+COMMENT ON TABLE credit_ledger IS 'Every balance change: top_up, debit (one per billed request) or adjustment';
+COMMENT ON TABLE credit_reservations IS 'Estimated cost held per in-flight request until its transaction settles';
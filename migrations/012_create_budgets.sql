@@ -0,0 +1,51 @@
+-- Soft spend budgets and threshold alerts
+-- Migration: 012
+-- Description: Daily/monthly budgets per tenant or API key; each threshold alerts once per period
+
+CREATE TABLE budgets (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+
+    -- Scope: tenant-wide when api_key_id is NULL, otherwise a single key
+    tenant_id VARCHAR(255) NOT NULL,
+    api_key_id UUID REFERENCES api_keys(id) ON DELETE CASCADE,
+
+    period VARCHAR(20) NOT NULL,
+    -- USD of billed_cost per period
+    amount DECIMAL(16, 8) NOT NULL,
+    -- Percentages of amount that trigger an alert
+    thresholds INTEGER[] NOT NULL DEFAULT '{50, 80, 100}',
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT valid_budget_period CHECK (period IN ('daily', 'monthly')),
+    CONSTRAINT positive_budget CHECK (amount > 0)
+);
+
+CREATE UNIQUE INDEX idx_budgets_scope_period ON budgets(
+    tenant_id,
+    COALESCE(api_key_id, '00000000-0000-0000-0000-000000000000'::uuid),
+    period
+);
+
+CREATE TABLE budget_alerts (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    budget_id UUID NOT NULL REFERENCES budgets(id) ON DELETE CASCADE,
+    period_start TIMESTAMPTZ NOT NULL,
+    threshold INTEGER NOT NULL,
+    -- Spend when the threshold was crossed
+    spend DECIMAL(16, 8) NOT NULL,
+    request_id VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    UNIQUE(budget_id, period_start, threshold)
+);
+
+CREATE TRIGGER trigger_update_budget_timestamp
+    BEFORE UPDATE ON budgets
+    FOR EACH ROW
+    EXECUTE FUNCTION update_billing_summary_timestamp();
+
+-- Comment
+COMMENT ON TABLE budgets IS 'Soft spend limits: crossing a threshold sends an alert but does not block requests';
+COMMENT ON TABLE budget_alerts IS 'Thresholds already alerted, one row per budget, period and threshold';
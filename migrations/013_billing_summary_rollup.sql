@@ -0,0 +1,30 @@
+-- Rollup of billing_transactions into tenant_billing_summary
+-- Migration: 013
+-- Description: Track when transactions were recorded so late arrivals re-aggregate their periods;
+--              split summary cost into upstream and billed cost
+
+-- When the row was written; created_at is when the request started
+ALTER TABLE billing_transactions ADD COLUMN recorded_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+UPDATE billing_transactions SET recorded_at = created_at;
+CREATE INDEX idx_billing_recorded_at ON billing_transactions(recorded_at);
+
+ALTER TABLE tenant_billing_summary RENAME COLUMN total_cost TO upstream_cost;
+ALTER TABLE tenant_billing_summary ALTER COLUMN upstream_cost TYPE DECIMAL(16, 8);
+ALTER TABLE tenant_billing_summary ADD COLUMN billed_cost DECIMAL(16, 8) NOT NULL DEFAULT 0;
+
+-- Transactions recorded before the watermark are reflected in the summary
+CREATE TABLE billing_rollup_state (
+    name VARCHAR(64) PRIMARY KEY,
+    watermark TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TRIGGER trigger_update_rollup_state_timestamp
+    BEFORE UPDATE ON billing_rollup_state
+    FOR EACH ROW
+    EXECUTE FUNCTION update_billing_summary_timestamp();
+
+-- Comment
+COMMENT ON COLUMN billing_transactions.recorded_at IS 'Insert time; rollups re-aggregate periods of transactions recorded after their watermark';
+COMMENT ON COLUMN tenant_billing_summary.model_breakdown IS 'Per-model usage: {"model": {"requests", "tokens", "upstream_cost", "billed_cost"}}';
+COMMENT ON COLUMN tenant_billing_summary.provider_breakdown IS 'Per-provider usage: {"provider": {"requests", "tokens", "upstream_cost", "billed_cost"}}';
@@ -0,0 +1,65 @@
+-- Finalized invoices
+-- Migration: 014
+-- Description: Immutable invoices with line items per logical model and API key
+
+CREATE TABLE invoices (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    invoice_number VARCHAR(64) NOT NULL UNIQUE,
+
+    tenant_id VARCHAR(255) NOT NULL,
+    period_start TIMESTAMPTZ NOT NULL,
+    period_end TIMESTAMPTZ NOT NULL,
+
+    -- Totals over the line items
+    total_requests BIGINT NOT NULL,
+    total_tokens BIGINT NOT NULL,
+    upstream_cost DECIMAL(16, 8) NOT NULL,
+    billed_cost DECIMAL(16, 8) NOT NULL,
+
+    finalized_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT valid_invoice_period CHECK (period_end > period_start),
+    UNIQUE(tenant_id, period_start, period_end)
+);
+
+CREATE INDEX idx_invoices_tenant_period ON invoices(tenant_id, period_start DESC);
+
+CREATE TABLE invoice_line_items (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    invoice_id UUID NOT NULL REFERENCES invoices(id),
+
+    -- No foreign key: the invoice outlives the key
+    api_key_id UUID NOT NULL,
+    logical_model VARCHAR(255) NOT NULL,
+
+    requests BIGINT NOT NULL,
+    prompt_tokens BIGINT NOT NULL,
+    completion_tokens BIGINT NOT NULL,
+    total_tokens BIGINT NOT NULL,
+    upstream_cost DECIMAL(16, 8) NOT NULL,
+    billed_cost DECIMAL(16, 8) NOT NULL,
+
+    UNIQUE(invoice_id, api_key_id, logical_model)
+);
+
+-- Finalized invoices can't be changed or removed
+CREATE OR REPLACE FUNCTION reject_invoice_change()
+RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'finalized invoices are immutable';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trigger_invoices_immutable
+    BEFORE UPDATE OR DELETE ON invoices
+    FOR EACH ROW
+    EXECUTE FUNCTION reject_invoice_change();
+
+CREATE TRIGGER trigger_invoice_line_items_immutable
+    BEFORE UPDATE OR DELETE ON invoice_line_items
+    FOR EACH ROW
+    EXECUTE FUNCTION reject_invoice_change();
+
+-- Comment
+COMMENT ON TABLE invoices IS 'Finalized invoices; rows are immutable once inserted';
+COMMENT ON TABLE invoice_line_items IS 'Invoice lines grouped by API key and logical model';
@@ -0,0 +1,9 @@
+-- Keyset pagination of billing transactions
+-- Migration: 015
+-- Description: Order pages by (created_at, id) so rows with equal timestamps are neither skipped nor repeated
+
+DROP INDEX IF EXISTS idx_billing_tenant_time;
+DROP INDEX IF EXISTS idx_billing_api_key_time;
+
+CREATE INDEX idx_billing_tenant_time ON billing_transactions(tenant_id, created_at DESC, id DESC);
+CREATE INDEX idx_billing_api_key_time ON billing_transactions(api_key_id, created_at DESC, id DESC);
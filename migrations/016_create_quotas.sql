@@ -0,0 +1,84 @@
+-- Daily and monthly quotas
+-- Migration: 016
+-- Description: Request/token quotas per tenant or API key, with usage counters that survive restarts
+
+CREATE TABLE quotas (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+
+    -- Scope: tenant-wide when api_key_id is NULL, otherwise a single key
+    tenant_id VARCHAR(255) NOT NULL,
+    api_key_id UUID REFERENCES api_keys(id) ON DELETE CASCADE,
+
+    -- NULL = unlimited; for a key, requests_per_day replaces api_keys.rate_limit_rpd
+    requests_per_day BIGINT,
+    tokens_per_day BIGINT,
+    tokens_per_month BIGINT,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT non_negative_quotas CHECK (
+        COALESCE(requests_per_day, 0) >= 0
+        AND COALESCE(tokens_per_day, 0) >= 0
+        AND COALESCE(tokens_per_month, 0) >= 0
+    )
+);
+
+CREATE UNIQUE INDEX idx_quotas_scope ON quotas(
+    tenant_id,
+    COALESCE(api_key_id, '00000000-0000-0000-0000-000000000000'::uuid)
+);
+
+CREATE TRIGGER trigger_update_quota_timestamp
+    BEFORE UPDATE ON quotas
+    FOR EACH ROW
+    EXECUTE FUNCTION update_billing_summary_timestamp();
+
+-- Usage per scope and UTC day/month
+CREATE TABLE quota_usage (
+    tenant_id VARCHAR(255) NOT NULL,
+    api_key_id UUID,
+    period VARCHAR(20) NOT NULL,
+    period_start TIMESTAMPTZ NOT NULL,
+
+    -- Requests admitted
+    requests BIGINT NOT NULL DEFAULT 0,
+    -- Tokens of recorded transactions
+    tokens BIGINT NOT NULL DEFAULT 0,
+
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT valid_quota_period CHECK (period IN ('daily', 'monthly'))
+);
+
+CREATE UNIQUE INDEX idx_quota_usage_scope ON quota_usage(
+    tenant_id,
+    COALESCE(api_key_id, '00000000-0000-0000-0000-000000000000'::uuid),
+    period,
+    period_start
+);
+
+-- Count the tokens of every stored transaction for its key and tenant
+CREATE OR REPLACE FUNCTION count_quota_tokens()
+RETURNS TRIGGER AS $$
+BEGIN
+    INSERT INTO quota_usage (tenant_id, api_key_id, period, period_start, tokens)
+    VALUES
+        (NEW.tenant_id, NEW.api_key_id, 'daily', date_trunc('day', NEW.created_at, 'UTC'), NEW.total_tokens),
+        (NEW.tenant_id, NEW.api_key_id, 'monthly', date_trunc('month', NEW.created_at, 'UTC'), NEW.total_tokens),
+        (NEW.tenant_id, NULL, 'daily', date_trunc('day', NEW.created_at, 'UTC'), NEW.total_tokens),
+        (NEW.tenant_id, NULL, 'monthly', date_trunc('month', NEW.created_at, 'UTC'), NEW.total_tokens)
+    ON CONFLICT (tenant_id, COALESCE(api_key_id, '00000000-0000-0000-0000-000000000000'::uuid), period, period_start)
+    DO UPDATE SET tokens = quota_usage.tokens + EXCLUDED.tokens, updated_at = NOW();
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trigger_count_quota_tokens
+    AFTER INSERT ON billing_transactions
+    FOR EACH ROW
+    EXECUTE FUNCTION count_quota_tokens();
+
+-- Comment
+COMMENT ON TABLE quotas IS 'Request and token quotas per tenant or API key; NULL limits are unlimited';
+COMMENT ON TABLE quota_usage IS 'Requests admitted and tokens billed per scope and UTC day/month';
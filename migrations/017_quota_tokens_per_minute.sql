@@ -0,0 +1,10 @@
+-- Tokens-per-minute limits
+-- Migration: 017
+-- Description: Per-minute token budget per tenant or API key, enforced in memory by each gateway instance
+
+ALTER TABLE quotas ADD COLUMN tokens_per_minute BIGINT;
+
+ALTER TABLE quotas ADD CONSTRAINT non_negative_tokens_per_minute
+    CHECK (COALESCE(tokens_per_minute, 0) >= 0);
+
+COMMENT ON COLUMN quotas.tokens_per_minute IS 'Token bucket refilled over a minute; requests are charged an estimate up front and settled with actual usage';
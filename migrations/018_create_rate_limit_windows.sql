@@ -0,0 +1,14 @@
+-- Shared rate limit counters
+-- Migration: 018
+-- Description: Per-minute request counters of each API key, shared by all gateway replicas
+
+-- Counters are only meaningful for about two minutes, so they skip the WAL
+CREATE UNLOGGED TABLE rate_limit_windows (
+    api_key_id UUID NOT NULL,
+    window_start TIMESTAMPTZ NOT NULL,
+    requests BIGINT NOT NULL DEFAULT 0,
+
+    PRIMARY KEY (api_key_id, window_start)
+);
+
+COMMENT ON TABLE rate_limit_windows IS 'Requests per API key and minute for the postgres rate limit backend (sliding window)';
@@ -0,0 +1,14 @@
+-- Concurrency limits and fair queuing
+-- Migration: 019
+-- Description: Requests in flight per tenant or API key, and each tenant's share when the gateway is saturated
+
+ALTER TABLE quotas ADD COLUMN max_concurrent_requests INTEGER;
+ALTER TABLE quotas ADD COLUMN scheduling_weight INTEGER;
+
+ALTER TABLE quotas ADD CONSTRAINT positive_concurrency CHECK (
+    COALESCE(max_concurrent_requests, 1) > 0
+    AND COALESCE(scheduling_weight, 1) > 0
+);
+
+COMMENT ON COLUMN quotas.max_concurrent_requests IS 'Requests in flight at once per gateway instance; further requests wait in the queue';
+COMMENT ON COLUMN quotas.scheduling_weight IS 'Tenant rows only: share of capacity when requests queue for it (default 1)';
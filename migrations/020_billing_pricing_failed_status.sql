@@ -0,0 +1,11 @@
+-- Requests that could not be priced
+-- Migration: 020
+-- Description: pricing_failed marks a request billed at the credit held up front because no price resolved
+
+ALTER TABLE billing_transactions
+    DROP CONSTRAINT valid_status;
+
+ALTER TABLE billing_transactions
+    ADD CONSTRAINT valid_status CHECK (status IN ('success', 'error', 'timeout', 'cancelled', 'pricing_failed'));
+
+CREATE INDEX idx_billing_pricing_failed ON billing_transactions(created_at) WHERE status = 'pricing_failed';
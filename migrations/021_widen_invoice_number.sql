@@ -0,0 +1,5 @@
+-- Collision-free invoice numbers
+-- Migration: 021
+-- Description: Invoice numbers carry the full SHA-256 of the tenant id; existing invoices keep their numbers
+
+ALTER TABLE invoices ALTER COLUMN invoice_number TYPE VARCHAR(128);
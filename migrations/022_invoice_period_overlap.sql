@@ -0,0 +1,29 @@
+-- Non-overlapping, complete invoices
+-- Migration: 022
+-- Description: A tenant's invoice periods may not overlap, and line items can only be added while their invoice is being finalized
+
+CREATE EXTENSION IF NOT EXISTS btree_gist;
+
+ALTER TABLE invoices ADD CONSTRAINT invoices_no_overlapping_periods
+    EXCLUDE USING gist (tenant_id WITH =, tstzrange(period_start, period_end) WITH &&);
+
+-- Line items may only be inserted in the transaction that inserted their invoice
+CREATE OR REPLACE FUNCTION reject_invoice_line_item_change()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT' AND EXISTS (
+        SELECT 1 FROM invoices
+        WHERE id = NEW.invoice_id AND xmin = pg_current_xact_id()::xid
+    ) THEN
+        RETURN NEW;
+    END IF;
+    RAISE EXCEPTION 'finalized invoices are immutable';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER trigger_invoice_line_items_immutable ON invoice_line_items;
+
+CREATE TRIGGER trigger_invoice_line_items_immutable
+    BEFORE INSERT OR UPDATE OR DELETE ON invoice_line_items
+    FOR EACH ROW
+    EXECUTE FUNCTION reject_invoice_line_item_change();
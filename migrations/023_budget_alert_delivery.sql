@@ -0,0 +1,11 @@
+-- Budget alert delivery
+-- Migration: 023
+-- Description: Alerts are marked delivered once the notifier succeeds; undelivered alerts are retried
+
+ALTER TABLE budget_alerts
+    ADD COLUMN delivered_at TIMESTAMPTZ,
+    -- Last time a notification was attempted; one attempt at a time
+    ADD COLUMN attempted_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+-- Alerts recorded so far were sent before they were recorded
+UPDATE budget_alerts SET delivered_at = created_at, attempted_at = created_at;
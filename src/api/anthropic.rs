@@ -1,74 +1,53 @@
-use crate::{auth, core::entities::UnifiedRequest, routing::AppState};
+use crate::{billing::TpmLimits, core::entities::UnifiedRequest, db::KeyInfo, routing::AppState};
 use axum::{
     extract::State,
     response::{IntoResponse, Response},
-    Json,
+    Extension, Json,
 };
 use futures_util::StreamExt;
 
 pub async fn messages(
     State(app): State<AppState>,
-    headers: axum::http::HeaderMap,
+    Extension(key_info): Extension<KeyInfo>,
+    Extension(tpm_limits): Extension<TpmLimits>,
     Json(req): Json<crate::api::anthropic_adapter::AnthropicMessagesRequest>,
 ) -> Response {
-    // 1) XJPkey 鉴权
-    let raw_key = match auth::extract_xjpkey(&headers) {
-        Ok(key) => key,
-        Err(e) => return e.into_response(),
-    };
-
-    // 2) 验证密钥并获取密钥信息
-    let key_store = app.key_store();
-    let key_info = match auth::verify_key(&*key_store, &raw_key).await {
-        Ok(info) => info,
-        Err(e) => return e.into_response(),
-    };
-
-    // 3) 适配为 UnifiedRequest
+    // 1) 适配为 UnifiedRequest（密钥已由 auth 中间件验证，限流与配额由 rate limit 中间件执行）
     let unified: UnifiedRequest = crate::api::anthropic_adapter::to_unified(req);
     let model_name = unified.logical_model.clone();
 
-    // 4) 调用路由（with billing tracking）
-    match app.invoke_with_billing(unified, key_info.tenant_id.clone(), key_info.id).await {
+    // 2) 调用路由（with billing tracking）
+    match app
+        .invoke_with_billing(unified, key_info.tenant_id.clone(), key_info.id, tpm_limits)
+        .await
+    {
         Ok(crate::connectors::ConnectorResponse::Streaming(stream)) => {
-            // 简化的 Anthropic SSE：仅输出 content_block_delta 与最终 message_stop
-            let mut started = false;
-            let mapped = stream.map(
-                move |item| -> Result<axum::response::sse::Event, std::convert::Infallible> {
+            // UnifiedChunk → Anthropic 事件序列（文本与 tool_use 内容块）
+            let mut encoder = crate::api::anthropic_adapter::StreamEncoder::new(&model_name);
+            let mapped = stream.flat_map(move |item| {
+                let events: Vec<Result<axum::response::sse::Event, std::convert::Infallible>> =
                     match item {
-                        Ok(chunk) => {
-                            let mut ev = axum::response::sse::Event::default();
-                            if !started {
-                                started = true;
-                                ev = ev.event("message_start").data("{}");
-                                return Ok(ev);
-                            }
-                            if let Some(txt) = chunk.text_delta {
-                                let data = serde_json::json!({
-                                    "type":"content_block_delta",
-                                    "delta":{"type":"text_delta","text": txt},
-                                    "index":0
-                                })
-                                .to_string();
-                                Ok(axum::response::sse::Event::default()
-                                    .event("content_block_delta")
-                                    .data(data))
-                            } else if chunk.done {
-                                Ok(axum::response::sse::Event::default()
-                                    .event("message_stop")
-                                    .data("{}"))
-                            } else {
+                        Ok(chunk) => encoder
+                            .encode(chunk)
+                            .into_iter()
+                            .map(|(name, data)| {
                                 Ok(axum::response::sse::Event::default()
-                                    .event("ping")
-                                    .data("{}"))
-                            }
+                                    .event(name)
+                                    .data(data.to_string()))
+                            })
+                            .collect(),
+                        Err(e) => {
+                            let data = serde_json::json!({
+                                "type": "error",
+                                "error": {"type": "api_error", "message": e.to_string()}
+                            });
+                            vec![Ok(axum::response::sse::Event::default()
+                                .event("error")
+                                .data(data.to_string()))]
                         }
-                        Err(e) => Ok(axum::response::sse::Event::default()
-                            .event("error")
-                            .data(e.to_string())),
-                    }
-                },
-            );
+                    };
+                futures_util::stream::iter(events)
+            });
             axum::response::Sse::new(mapped).into_response()
         }
         Ok(crate::connectors::ConnectorResponse::NonStreaming(chunk)) => {
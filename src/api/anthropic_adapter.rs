@@ -1,8 +1,12 @@
 use serde::Deserialize;
 use serde_json::json;
+use std::collections::HashMap;
 use uuid::Uuid;
 
-use crate::core::entities::{ContentPart, UnifiedChunk, UnifiedMessage, UnifiedRequest};
+use crate::core::entities::{
+    ContentPart, FinishReason, ToolCall, ToolChoice, ToolSpec, UnifiedChunk, UnifiedMessage,
+    UnifiedRequest,
+};
 
 #[derive(Deserialize)]
 pub struct AnthropicMessagesRequest {
@@ -18,18 +22,53 @@ pub struct AnthropicMessagesRequest {
     pub temperature: Option<f32>,
     #[serde(default)]
     pub top_p: Option<f32>,
+    #[serde(default)]
+    pub tools: Option<Vec<serde_json::Value>>,
+    #[serde(default)]
+    pub tool_choice: Option<serde_json::Value>,
     #[serde(flatten)]
     pub extra: serde_json::Value,
 }
 
+fn message(role: &str, content: Vec<ContentPart>) -> UnifiedMessage {
+    UnifiedMessage {
+        role: role.into(),
+        content,
+        name: None,
+        tool_calls: Vec::new(),
+        tool_call_id: None,
+    }
+}
+
+/// Text of a `tool_result` block: either a plain string or a list of text blocks
+fn tool_result_text(content: Option<&serde_json::Value>) -> String {
+    match content {
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(serde_json::Value::Array(blocks)) => blocks
+            .iter()
+            .filter_map(|b| b.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+fn to_tool_choice(v: &serde_json::Value) -> Option<ToolChoice> {
+    match v.get("type").and_then(|t| t.as_str())? {
+        "auto" => Some(ToolChoice::Auto),
+        "any" => Some(ToolChoice::Required),
+        "none" => Some(ToolChoice::None),
+        "tool" => Some(ToolChoice::Tool {
+            name: v.get("name")?.as_str()?.to_string(),
+        }),
+        _ => None,
+    }
+}
+
 pub fn to_unified(req: AnthropicMessagesRequest) -> UnifiedRequest {
     let mut messages = Vec::new();
     if let Some(sys) = req.system {
-        messages.push(UnifiedMessage {
-            role: "system".into(),
-            content: vec![ContentPart::Text { text: sys }],
-            name: None,
-        });
+        messages.push(message("system", vec![ContentPart::Text { text: sys }]));
     }
     for m in req.messages {
         let role = m
@@ -39,34 +78,91 @@ pub fn to_unified(req: AnthropicMessagesRequest) -> UnifiedRequest {
             .to_string();
         let content = m.get("content").cloned().unwrap_or(json!(""));
         let mut parts = Vec::new();
+        let mut tool_calls = Vec::new();
+        // tool_result blocks become separate "tool" messages, placed before the
+        // rest of the user turn so they directly follow the assistant's calls
+        let mut tool_results = Vec::new();
         match content {
             serde_json::Value::String(s) => parts.push(ContentPart::Text { text: s }),
             serde_json::Value::Array(arr) => {
                 for c in arr {
                     let t = c.get("type").and_then(|x| x.as_str()).unwrap_or("text");
-                    if t == "text" {
-                        if let Some(txt) = c.get("text").and_then(|x| x.as_str()) {
-                            parts.push(ContentPart::Text {
-                                text: txt.to_string(),
-                            });
+                    match t {
+                        "text" => {
+                            if let Some(txt) = c.get("text").and_then(|x| x.as_str()) {
+                                parts.push(ContentPart::Text {
+                                    text: txt.to_string(),
+                                });
+                            }
+                        }
+                        "image" => {
+                            let source = &c["source"];
+                            match source.get("type").and_then(|x| x.as_str()) {
+                                Some("base64") => parts.push(ContentPart::ImageB64 {
+                                    b64: source["data"].as_str().unwrap_or("").to_string(),
+                                    mime: source["media_type"]
+                                        .as_str()
+                                        .unwrap_or("image/png")
+                                        .to_string(),
+                                }),
+                                Some("url") => parts.push(ContentPart::ImageUrl {
+                                    url: source["url"].as_str().unwrap_or("").to_string(),
+                                    mime: None,
+                                }),
+                                _ => {}
+                            }
+                        }
+                        "tool_use" => tool_calls.push(ToolCall {
+                            id: c["id"].as_str().unwrap_or("").to_string(),
+                            name: c["name"].as_str().unwrap_or("").to_string(),
+                            arguments: c.get("input").cloned().unwrap_or(json!({})).to_string(),
+                        }),
+                        "tool_result" => {
+                            let mut result = message(
+                                "tool",
+                                vec![ContentPart::Text {
+                                    text: tool_result_text(c.get("content")),
+                                }],
+                            );
+                            result.tool_call_id = c["tool_use_id"].as_str().map(String::from);
+                            tool_results.push(result);
                         }
+                        _ => {}
                     }
                 }
             }
             _ => {}
         }
-        messages.push(UnifiedMessage {
-            role,
-            content: parts,
-            name: None,
-        });
+        let has_results = !tool_results.is_empty();
+        messages.extend(tool_results);
+        if !parts.is_empty() || !tool_calls.is_empty() || !has_results {
+            let mut msg = message(&role, parts);
+            msg.tool_calls = tool_calls;
+            messages.push(msg);
+        }
     }
 
+    let tools = req.tools.map(|tools| {
+        tools
+            .into_iter()
+            .filter_map(|tool| {
+                Some(ToolSpec {
+                    name: tool.get("name")?.as_str()?.to_string(),
+                    description: tool
+                        .get("description")
+                        .and_then(|d| d.as_str())
+                        .map(String::from),
+                    json_schema: tool.get("input_schema").cloned().unwrap_or(json!({})),
+                })
+            })
+            .collect()
+    });
+
     UnifiedRequest {
         logical_model: req.model,
         messages,
-        tools: None,
-        tool_choice: None,
+        tools,
+        tool_choice: req.tool_choice.as_ref().and_then(to_tool_choice),
         max_output_tokens: req.max_tokens,
         temperature: req.temperature,
         top_p: req.top_p,
@@ -75,19 +171,316 @@ pub fn to_unified(req: AnthropicMessagesRequest) -> UnifiedRequest {
     }
 }
 
+/// Anthropic `stop_reason` for how generation ended
+fn stop_reason(finish: Option<FinishReason>, saw_tool_calls: bool) -> &'static str {
+    match finish {
+        Some(FinishReason::Length) => "max_tokens",
+        Some(FinishReason::ToolCalls) => "tool_use",
+        _ if saw_tool_calls => "tool_use",
+        _ => "end_turn",
+    }
+}
+
 pub fn final_message_json(model: &str, chunk: UnifiedChunk) -> serde_json::Value {
     let id = format!("msg_{}", Uuid::new_v4());
+    let mut content = Vec::new();
+    if let Some(text) = chunk.text_delta.filter(|t| !t.is_empty()) {
+        content.push(json!({"type": "text", "text": text}));
+    }
+    let stop_reason = stop_reason(chunk.finish_reason, !chunk.tool_calls.is_empty());
+    for call in chunk.tool_calls {
+        let input = call
+            .arguments
+            .as_deref()
+            .and_then(|a| serde_json::from_str::<serde_json::Value>(a).ok())
+            .unwrap_or(json!({}));
+        content.push(json!({
+            "type": "tool_use",
+            "id": call.id.unwrap_or_else(|| format!("toolu_{}", Uuid::new_v4().simple())),
+            "name": call.name.unwrap_or_default(),
+            "input": input
+        }));
+    }
+    if content.is_empty() {
+        content.push(json!({"type": "text", "text": ""}));
+    }
     json!({
         "id": id,
         "type": "message",
         "role": "assistant",
         "model": model,
-        "content": [{
-            "type": "text",
-            "text": chunk.text_delta.unwrap_or_default()
-        }],
-        "stop_reason": "end_turn",
+        "content": content,
+        "stop_reason": stop_reason,
         "stop_sequence": null,
         "usage": { "input_tokens": null, "output_tokens": null }
     })
 }
+
+/// Turns unified chunks into the Anthropic streaming event sequence:
+/// `message_start`, then per content block `content_block_start` /
+/// `content_block_delta` / `content_block_stop`, then `message_delta` and
+/// `message_stop`
+pub struct StreamEncoder {
+    model: String,
+    started: bool,
+    finished: bool,
+    /// Index of the content block currently open, if any
+    open_block: Option<usize>,
+    /// Whether the open block is the text block
+    text_open: bool,
+    next_block: usize,
+    /// Upstream tool call index -> content block index
+    tool_blocks: HashMap<u32, usize>,
+    finish_reason: Option<FinishReason>,
+}
+
+impl StreamEncoder {
+    pub fn new(model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            started: false,
+            finished: false,
+            open_block: None,
+            text_open: false,
+            next_block: 0,
+            tool_blocks: HashMap::new(),
+            finish_reason: None,
+        }
+    }
+
+    fn close_block(&mut self, events: &mut Vec<(&'static str, serde_json::Value)>) {
+        if let Some(index) = self.open_block.take() {
+            events.push((
+                "content_block_stop",
+                json!({"type": "content_block_stop", "index": index}),
+            ));
+        }
+        self.text_open = false;
+    }
+
+    fn open(
+        &mut self,
+        block: serde_json::Value,
+        events: &mut Vec<(&'static str, serde_json::Value)>,
+    ) -> usize {
+        self.close_block(events);
+        let index = self.next_block;
+        self.next_block += 1;
+        self.open_block = Some(index);
+        events.push((
+            "content_block_start",
+            json!({"type": "content_block_start", "index": index, "content_block": block}),
+        ));
+        index
+    }
+
+    /// Events for one upstream chunk, as (event name, data) pairs
+    pub fn encode(&mut self, chunk: UnifiedChunk) -> Vec<(&'static str, serde_json::Value)> {
+        let mut events = Vec::new();
+        if self.finished {
+            return events;
+        }
+        if !self.started {
+            self.started = true;
+            events.push((
+                "message_start",
+                json!({
+                    "type": "message_start",
+                    "message": {
+                        "id": format!("msg_{}", Uuid::new_v4()),
+                        "type": "message",
+                        "role": "assistant",
+                        "model": self.model,
+                        "content": [],
+                        "stop_reason": null,
+                        "stop_sequence": null,
+                        "usage": { "input_tokens": null, "output_tokens": null }
+                    }
+                }),
+            ));
+        }
+
+        if let Some(text) = chunk.text_delta.filter(|t| !t.is_empty()) {
+            let index = match self.open_block {
+                Some(index) if self.text_open => index,
+                _ => {
+                    let index = self.open(json!({"type": "text", "text": ""}), &mut events);
+                    self.text_open = true;
+                    index
+                }
+            };
+            events.push((
+                "content_block_delta",
+                json!({
+                    "type": "content_block_delta",
+                    "index": index,
+                    "delta": {"type": "text_delta", "text": text}
+                }),
+            ));
+        }
+
+        for call in chunk.tool_calls {
+            let index = match self.tool_blocks.get(&call.index) {
+                Some(&index) => index,
+                None => {
+                    let index = self.open(
+                        json!({
+                            "type": "tool_use",
+                            "id": call.id.clone().unwrap_or_else(|| format!("toolu_{}", Uuid::new_v4().simple())),
+                            "name": call.name.clone().unwrap_or_default(),
+                            "input": {}
+                        }),
+                        &mut events,
+                    );
+                    self.tool_blocks.insert(call.index, index);
+                    index
+                }
+            };
+            if let Some(partial) = call.arguments.filter(|a| !a.is_empty()) {
+                events.push((
+                    "content_block_delta",
+                    json!({
+                        "type": "content_block_delta",
+                        "index": index,
+                        "delta": {"type": "input_json_delta", "partial_json": partial}
+                    }),
+                ));
+            }
+        }
+
+        if chunk.finish_reason.is_some() {
+            self.finish_reason = chunk.finish_reason;
+        }
+        if chunk.done {
+            self.finished = true;
+            self.close_block(&mut events);
+            let stop_reason = stop_reason(self.finish_reason, !self.tool_blocks.is_empty());
+            events.push((
+                "message_delta",
+                json!({
+                    "type": "message_delta",
+                    "delta": {"stop_reason": stop_reason, "stop_sequence": null},
+                    "usage": {"output_tokens": null}
+                }),
+            ));
+            events.push(("message_stop", json!({"type": "message_stop"})));
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::entities::ToolCallDelta;
+
+    fn chunk(text: Option<&str>, tool_calls: Vec<ToolCallDelta>, done: bool) -> UnifiedChunk {
+        UnifiedChunk {
+            text_delta: text.map(String::from),
+            tool_calls,
+            finish_reason: None,
+            done,
+            provider_events: None,
+        }
+    }
+
+    fn call(id: Option<&str>, name: Option<&str>, arguments: &str) -> ToolCallDelta {
+        ToolCallDelta {
+            index: 0,
+            id: id.map(String::from),
+            name: name.map(String::from),
+            arguments: Some(arguments.into()),
+        }
+    }
+
+    #[test]
+    fn test_tool_blocks_to_unified() {
+        let req: AnthropicMessagesRequest = serde_json::from_value(json!({
+            "model": "claude",
+            "tools": [{"name": "get_weather", "input_schema": {"type": "object"}}],
+            "tool_choice": {"type": "tool", "name": "get_weather"},
+            "messages": [
+                {"role": "user", "content": "weather in Paris?"},
+                {"role": "assistant", "content": [
+                    {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {"city": "Paris"}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": [{"type": "text", "text": "18C"}]}
+                ]}
+            ]
+        }))
+        .unwrap();
+        let unified = to_unified(req);
+
+        assert_eq!(unified.tools.as_ref().unwrap()[0].name, "get_weather");
+        assert_eq!(
+            unified.tool_choice,
+            Some(ToolChoice::Tool {
+                name: "get_weather".into()
+            })
+        );
+        assert_eq!(unified.messages.len(), 3);
+        assert_eq!(
+            unified.messages[1].tool_calls[0].arguments,
+            r#"{"city":"Paris"}"#
+        );
+        assert_eq!(unified.messages[2].role, "tool");
+        assert_eq!(unified.messages[2].tool_call_id.as_deref(), Some("toolu_1"));
+        assert!(unified.extra.get("tools").is_none());
+    }
+
+    #[test]
+    fn test_stream_emits_tool_use_blocks() {
+        let mut enc = StreamEncoder::new("claude");
+        let mut names = Vec::new();
+        let chunks = [
+            chunk(Some("Checking"), vec![], false),
+            chunk(
+                None,
+                vec![call(Some("call_1"), Some("get_weather"), "")],
+                false,
+            ),
+            chunk(None, vec![call(None, None, r#"{"city":"Paris"}"#)], false),
+            chunk(None, vec![], true),
+        ];
+        let mut last = json!(null);
+        for c in chunks {
+            for (name, data) in enc.encode(c) {
+                names.push(name);
+                if name == "message_delta" {
+                    last = data;
+                }
+            }
+        }
+        assert_eq!(
+            names,
+            [
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop"
+            ]
+        );
+        assert_eq!(last["delta"]["stop_reason"], "tool_use");
+    }
+
+    #[test]
+    fn test_final_message_tool_use() {
+        let body = final_message_json(
+            "claude",
+            chunk(
+                None,
+                vec![call(Some("call_1"), Some("f"), r#"{"a":1}"#)],
+                true,
+            ),
+        );
+        assert_eq!(body["stop_reason"], "tool_use");
+        assert_eq!(body["content"][0]["type"], "tool_use");
+        assert_eq!(body["content"][0]["input"]["a"], 1);
+    }
+}
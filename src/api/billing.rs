@@ -1,8 +1,9 @@
-use axum::{extract::{State, Query}, Json, response::IntoResponse};
+use axum::{extract::{Path, State, Query}, http::{header, HeaderMap, StatusCode}, Json, response::IntoResponse};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::{routing::AppState, billing::{CostCalculator, OrUsage}};
+use crate::{auth, routing::AppState, billing::{invoice::{self, Invoice}, rollup, CostCalculator, OrUsage, PriceQuery, SummaryPeriod}, db::{BillingStore, CreditEntryType, NewBudget, NewRateCard, Quota, TransactionCursor, TransactionFilter}};
+use std::sync::Arc;
 
 #[derive(Deserialize)]
 pub struct QuoteBody {
@@ -15,7 +16,7 @@ pub async fn quote(
     State(app): State<AppState>,
     Json(body): Json<QuoteBody>,
 ) -> impl IntoResponse {
-    let pricing = match app.pricing.get(&body.provider_model_id).await {
+    let pricing = match app.pricing.get(&PriceQuery::model(&body.provider_model_id)).await {
         Ok(p) => p,
         Err(e) => return axum::Json(serde_json::json!({ "error": e.to_string() })),
     };
@@ -55,56 +56,130 @@ pub async fn quote(
 #[derive(Deserialize)]
 pub struct TransactionQueryParams {
     pub tenant_id: Option<String>,
-    pub api_key_id: Option<String>,
+    pub api_key_id: Option<Uuid>,
+    pub start: Option<String>, // ISO 8601
+    pub end: Option<String>,   // ISO 8601
+    pub logical_model: Option<String>,
+    pub provider: Option<String>,
+    pub status: Option<String>,
+    /// Minimum billed cost
+    pub min_cost: Option<rust_decimal::Decimal>,
     #[serde(default = "default_limit")]
     pub limit: i64,
-    #[serde(default)]
-    pub offset: i64,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    /// `ndjson` streams every matching transaction, one JSON object per line
+    pub format: Option<String>,
 }
 
 fn default_limit() -> i64 {
     100
 }
 
+const MAX_PAGE_SIZE: i64 = 1000;
+
+fn bad_request(error: impl Into<String>) -> axum::response::Response {
+    (
+        StatusCode::BAD_REQUEST,
+        axum::Json(serde_json::json!({ "error": error.into() })),
+    )
+        .into_response()
+}
+
 pub async fn get_transactions(
     State(app): State<AppState>,
     Query(params): Query<TransactionQueryParams>,
-) -> impl IntoResponse {
-    if let Some(tenant_id) = params.tenant_id {
-        match app
-            .billing_store()
-            .get_transactions_by_tenant(&tenant_id, params.limit, params.offset)
-            .await
-        {
-            Ok(transactions) => axum::Json(serde_json::json!({
-                "transactions": transactions,
-                "limit": params.limit,
-                "offset": params.offset
-            })),
-            Err(e) => axum::Json(serde_json::json!({ "error": e.to_string() })),
-        }
-    } else if let Some(api_key_id_str) = params.api_key_id {
-        let api_key_id = match Uuid::parse_str(&api_key_id_str) {
-            Ok(id) => id,
-            Err(e) => {
-                return axum::Json(serde_json::json!({ "error": format!("Invalid API key ID: {}", e) }))
+) -> axum::response::Response {
+    if params.tenant_id.is_none() && params.api_key_id.is_none() {
+        return bad_request("Must provide tenant_id or api_key_id");
+    }
+    let start = match params.start.as_deref().map(|s| parse_time("start", s)).transpose() {
+        Ok(start) => start,
+        Err(e) => return bad_request(e),
+    };
+    let end = match params.end.as_deref().map(|s| parse_time("end", s)).transpose() {
+        Ok(end) => end,
+        Err(e) => return bad_request(e),
+    };
+    let cursor = match params.cursor.as_deref().map(TransactionCursor::decode) {
+        None => None,
+        Some(Some(cursor)) => Some(cursor),
+        Some(None) => return bad_request("Invalid cursor"),
+    };
+    let filter = TransactionFilter {
+        tenant_id: params.tenant_id,
+        api_key_id: params.api_key_id,
+        start,
+        end,
+        logical_model: params.logical_model,
+        provider: params.provider,
+        status: params.status,
+        min_cost: params.min_cost,
+    };
+    let store = app.billing_store();
+
+    match params.format.as_deref() {
+        None | Some("json") => {}
+        Some("ndjson") => return stream_transactions(store, filter, cursor),
+        Some(other) => return bad_request(format!("Unknown format '{}'", other)),
+    }
+
+    let limit = params.limit.clamp(1, MAX_PAGE_SIZE);
+    match store.query_transactions(&filter, cursor, limit).await {
+        Ok(page) => axum::Json(serde_json::json!({
+            "transactions": page.transactions,
+            "next_cursor": page.next_cursor.map(|c| c.encode()),
+            "limit": limit
+        }))
+        .into_response(),
+        Err(e) => axum::Json(serde_json::json!({ "error": e.to_string() })).into_response(),
+    }
+}
+
+/// Every transaction matching `filter` as NDJSON, fetched page by page while
+/// the client reads
+fn stream_transactions(
+    store: Arc<dyn BillingStore>,
+    filter: TransactionFilter,
+    cursor: Option<TransactionCursor>,
+) -> axum::response::Response {
+    // `None` once the last page has been sent
+    let pages = futures_util::stream::unfold(Some(cursor), move |state| {
+        let store = store.clone();
+        let filter = filter.clone();
+        async move {
+            let cursor = state?;
+            match store.query_transactions(&filter, cursor, MAX_PAGE_SIZE).await {
+                Ok(page) => {
+                    let mut chunk = String::new();
+                    for tx in &page.transactions {
+                        match serde_json::to_string(tx) {
+                            Ok(line) => {
+                                chunk.push_str(&line);
+                                chunk.push('\n');
+                            }
+                            Err(e) => return Some((Err(std::io::Error::other(e)), None)),
+                        }
+                    }
+                    Some((Ok(chunk), page.next_cursor.map(Some)))
+                }
+                Err(e) => {
+                    tracing::error!("Transaction export failed: {}", e);
+                    Some((Err(std::io::Error::other(e)), None))
+                }
             }
-        };
-        match app
-            .billing_store()
-            .get_transactions_by_api_key(api_key_id, params.limit, params.offset)
-            .await
-        {
-            Ok(transactions) => axum::Json(serde_json::json!({
-                "transactions": transactions,
-                "limit": params.limit,
-                "offset": params.offset
-            })),
-            Err(e) => axum::Json(serde_json::json!({ "error": e.to_string() })),
         }
-    } else {
-        axum::Json(serde_json::json!({ "error": "Must provide tenant_id or api_key_id" }))
-    }
+    });
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        axum::body::Body::from_stream(pages),
+    )
+        .into_response()
+}
+
+fn parse_time(name: &str, value: &str) -> Result<time::OffsetDateTime, String> {
+    time::OffsetDateTime::parse(value, &time::format_description::well_known::Iso8601::DEFAULT)
+        .map_err(|e| format!("Invalid {} time: {}", name, e))
 }
 
 // Get cost summary
@@ -113,40 +188,425 @@ pub struct SummaryQueryParams {
     pub tenant_id: String,
     pub start: String, // ISO 8601
     pub end: String,   // ISO 8601
+    /// Only this key of the tenant
+    pub api_key_id: Option<Uuid>,
+    /// Read per-period summaries with model and provider breakdowns from
+    /// the rollup instead of totals over raw transactions
+    pub period: Option<SummaryPeriod>,
 }
 
 pub async fn get_summary(
     State(app): State<AppState>,
     Query(params): Query<SummaryQueryParams>,
 ) -> impl IntoResponse {
-    // Parse timestamps
-    let start = match time::OffsetDateTime::parse(
-        &params.start,
-        &time::format_description::well_known::Iso8601::DEFAULT,
-    ) {
-        Ok(t) => t,
-        Err(e) => {
-            return axum::Json(
-                serde_json::json!({ "error": format!("Invalid start time: {}", e) }),
-            )
-        }
+    let (start, end) = match (parse_time("start", &params.start), parse_time("end", &params.end)) {
+        (Ok(start), Ok(end)) => (start, end),
+        (Err(e), _) | (_, Err(e)) => return axum::Json(serde_json::json!({ "error": e })),
+    };
+    let store = app.billing_store();
+
+    if let Some(period) = params.period {
+        return match store
+            .get_billing_summaries(&params.tenant_id, params.api_key_id, period, start, end)
+            .await
+        {
+            Ok(summaries) => axum::Json(serde_json::json!({
+                "period": period,
+                "summaries": summaries
+            })),
+            Err(e) => axum::Json(serde_json::json!({ "error": e.to_string() })),
+        };
+    }
+
+    let summary = match params.api_key_id {
+        Some(api_key_id) => store.get_api_key_cost_summary(api_key_id, start, end).await,
+        None => store.get_cost_summary(&params.tenant_id, start, end).await,
     };
-    let end = match time::OffsetDateTime::parse(
-        &params.end,
-        &time::format_description::well_known::Iso8601::DEFAULT,
-    ) {
-        Ok(t) => t,
-        Err(e) => {
-            return axum::Json(serde_json::json!({ "error": format!("Invalid end time: {}", e) }))
+    match summary {
+        Ok(summary) => axum::Json(serde_json::json!(summary)),
+        Err(e) => axum::Json(serde_json::json!({ "error": e.to_string() })),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BackfillBody {
+    pub start: String, // ISO 8601
+    pub end: String,   // ISO 8601
+}
+
+/// Rebuild the summary rollup for a date range; requires the admin token
+pub async fn backfill_summary(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<BackfillBody>,
+) -> axum::response::Response {
+    if let Err(e) = auth::verify_admin_token(&headers) {
+        return e.into_response();
+    }
+    let (start, end) = match (parse_time("start", &body.start), parse_time("end", &body.end)) {
+        (Ok(start), Ok(end)) => (start, end),
+        (Err(e), _) | (_, Err(e)) => {
+            return (StatusCode::BAD_REQUEST, axum::Json(serde_json::json!({ "error": e }))).into_response()
         }
     };
+    match rollup::backfill(app.billing_store().as_ref(), start, end).await {
+        Ok(periods) => axum::Json(serde_json::json!({ "periods": periods })).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
 
+#[derive(Deserialize)]
+pub struct RateCardQueryParams {
+    pub tenant_id: String,
+    pub api_key_id: Option<Uuid>,
+}
+
+/// Rate card in effect for a tenant, or for one of its API keys
+pub async fn get_rate_card(
+    State(app): State<AppState>,
+    Query(params): Query<RateCardQueryParams>,
+) -> impl IntoResponse {
+    // Without a key only the tenant-wide card applies; nil never matches a key
+    let api_key_id = params.api_key_id.unwrap_or_else(Uuid::nil);
     match app
-        .billing_store()
-        .get_cost_summary(&params.tenant_id, start, end)
+        .rate_card_store()
+        .active_card(&params.tenant_id, api_key_id)
         .await
     {
-        Ok(summary) => axum::Json(serde_json::json!(summary)),
+        Ok(card) => axum::Json(serde_json::json!({ "rate_card": card })),
         Err(e) => axum::Json(serde_json::json!({ "error": e.to_string() })),
     }
 }
+
+/// Store a new rate card version; requires the admin token
+pub async fn create_rate_card(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<NewRateCard>,
+) -> axum::response::Response {
+    if let Err(e) = auth::verify_admin_token(&headers) {
+        return e.into_response();
+    }
+    match app.rate_card_store().create_card(body).await {
+        Ok(card) => axum::Json(serde_json::json!({ "rate_card": card })).into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            axum::Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreditQueryParams {
+    pub tenant_id: String,
+    pub api_key_id: Option<Uuid>,
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+/// Prepaid balance of a tenant or API key with its latest ledger entries
+pub async fn get_credits(
+    State(app): State<AppState>,
+    Query(params): Query<CreditQueryParams>,
+) -> impl IntoResponse {
+    let store = app.credit_store();
+    let account = match store.account(&params.tenant_id, params.api_key_id).await {
+        Ok(Some(account)) => account,
+        Ok(None) => return axum::Json(serde_json::json!({ "account": null, "ledger": [] })),
+        Err(e) => return axum::Json(serde_json::json!({ "error": e.to_string() })),
+    };
+    match store.ledger(account.id, params.limit).await {
+        Ok(ledger) => axum::Json(serde_json::json!({
+            "account": account,
+            "available": account.available(),
+            "ledger": ledger
+        })),
+        Err(e) => axum::Json(serde_json::json!({ "error": e.to_string() })),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreditBody {
+    pub tenant_id: String,
+    #[serde(default)]
+    pub api_key_id: Option<Uuid>,
+    pub entry_type: CreditEntryType,
+    /// USD; adjustments may be negative
+    pub amount: rust_decimal::Decimal,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+/// Top up or adjust a prepaid balance; requires the admin token
+pub async fn add_credits(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<CreditBody>,
+) -> axum::response::Response {
+    if let Err(e) = auth::verify_admin_token(&headers) {
+        return e.into_response();
+    }
+    if body.entry_type == CreditEntryType::TopUp && body.amount <= rust_decimal::Decimal::ZERO {
+        return (
+            StatusCode::BAD_REQUEST,
+            axum::Json(serde_json::json!({ "error": "top_up amount must be positive" })),
+        )
+            .into_response();
+    }
+    match app
+        .credit_store()
+        .credit(&body.tenant_id, body.api_key_id, body.entry_type, body.amount, body.note)
+        .await
+    {
+        Ok(account) => axum::Json(serde_json::json!({
+            "account": account,
+            "available": account.available()
+        }))
+        .into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            axum::Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BudgetQueryParams {
+    pub tenant_id: String,
+    pub api_key_id: Option<Uuid>,
+}
+
+/// Spend of the current period against each budget of a tenant or API key
+pub async fn get_budgets(
+    State(app): State<AppState>,
+    Query(params): Query<BudgetQueryParams>,
+) -> impl IntoResponse {
+    match app
+        .budget_monitor()
+        .status(&params.tenant_id, params.api_key_id)
+        .await
+    {
+        Ok(budgets) => axum::Json(serde_json::json!({ "budgets": budgets })),
+        Err(e) => axum::Json(serde_json::json!({ "error": e.to_string() })),
+    }
+}
+
+/// Create or replace a budget; requires the admin token
+pub async fn set_budget(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<NewBudget>,
+) -> axum::response::Response {
+    if let Err(e) = auth::verify_admin_token(&headers) {
+        return e.into_response();
+    }
+    let invalid_threshold = body.thresholds.iter().flatten().any(|t| *t <= 0);
+    if body.amount <= rust_decimal::Decimal::ZERO || invalid_threshold {
+        return (
+            StatusCode::BAD_REQUEST,
+            axum::Json(serde_json::json!({ "error": "amount and thresholds must be positive" })),
+        )
+            .into_response();
+    }
+    match app.budget_monitor().set_budget(body).await {
+        Ok(budget) => axum::Json(serde_json::json!({ "budget": budget })).into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            axum::Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct QuotaQueryParams {
+    pub tenant_id: String,
+    pub api_key_id: Option<Uuid>,
+}
+
+/// Quotas of a tenant or API key with today's and this month's usage
+pub async fn get_quotas(
+    State(app): State<AppState>,
+    Query(params): Query<QuotaQueryParams>,
+) -> impl IntoResponse {
+    match app
+        .quota_enforcer()
+        .store()
+        .list_quotas(&params.tenant_id, params.api_key_id, time::OffsetDateTime::now_utc())
+        .await
+    {
+        Ok(quotas) => axum::Json(serde_json::json!({ "quotas": quotas })),
+        Err(e) => axum::Json(serde_json::json!({ "error": e.to_string() })),
+    }
+}
+
+/// Create or replace the quotas of a tenant or API key; requires the admin token
+pub async fn set_quota(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<Quota>,
+) -> axum::response::Response {
+    if let Err(e) = auth::verify_admin_token(&headers) {
+        return e.into_response();
+    }
+    let limits = [
+        body.requests_per_day,
+        body.tokens_per_day,
+        body.tokens_per_month,
+        body.tokens_per_minute,
+    ];
+    if limits.iter().flatten().any(|l| *l < 0) {
+        return (
+            StatusCode::BAD_REQUEST,
+            axum::Json(serde_json::json!({ "error": "quota limits must not be negative" })),
+        )
+            .into_response();
+    }
+    let concurrency = [body.max_concurrent_requests, body.scheduling_weight];
+    if concurrency.iter().flatten().any(|v| *v <= 0) {
+        return (
+            StatusCode::BAD_REQUEST,
+            axum::Json(serde_json::json!({
+                "error": "max_concurrent_requests and scheduling_weight must be positive"
+            })),
+        )
+            .into_response();
+    }
+    match app.quota_enforcer().store().upsert_quota(body).await {
+        Ok(quota) => axum::Json(serde_json::json!({ "quota": quota })).into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            axum::Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+fn export_invoice(invoice: Invoice, format: ExportFormat) -> axum::response::Response {
+    match format {
+        ExportFormat::Json => axum::Json(serde_json::json!({ "invoice": invoice })).into_response(),
+        ExportFormat::Csv => (
+            [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{}.csv\"", invoice.invoice_number),
+                ),
+            ],
+            invoice.to_csv(),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct InvoiceQueryParams {
+    pub tenant_id: String,
+    pub start: Option<String>, // ISO 8601
+    pub end: Option<String>,   // ISO 8601
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+/// Invoice of a period (finalized, else a draft), or the tenant's finalized
+/// invoices when no period is given
+pub async fn get_invoices(
+    State(app): State<AppState>,
+    Query(params): Query<InvoiceQueryParams>,
+) -> axum::response::Response {
+    let store = app.invoice_store();
+    let (start, end) = match (params.start.as_deref(), params.end.as_deref()) {
+        (Some(start), Some(end)) => match (parse_time("start", start), parse_time("end", end)) {
+            (Ok(start), Ok(end)) => (start, end),
+            (Err(e), _) | (_, Err(e)) => {
+                return (StatusCode::BAD_REQUEST, axum::Json(serde_json::json!({ "error": e }))).into_response()
+            }
+        },
+        (None, None) => {
+            return match store.list_invoices(&params.tenant_id).await {
+                Ok(invoices) => axum::Json(serde_json::json!({ "invoices": invoices })).into_response(),
+                Err(e) => axum::Json(serde_json::json!({ "error": e.to_string() })).into_response(),
+            }
+        }
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                axum::Json(serde_json::json!({ "error": "start and end must be given together" })),
+            )
+                .into_response()
+        }
+    };
+    match invoice::invoice(store.as_ref(), &params.tenant_id, start, end).await {
+        Ok(invoice) => export_invoice(invoice, params.format),
+        Err(e) => axum::Json(serde_json::json!({ "error": e.to_string() })).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExportQueryParams {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+/// Finalized invoice by number
+pub async fn get_invoice(
+    State(app): State<AppState>,
+    Path(invoice_number): Path<String>,
+    Query(params): Query<ExportQueryParams>,
+) -> axum::response::Response {
+    match app.invoice_store().get_invoice(&invoice_number).await {
+        Ok(Some(invoice)) => export_invoice(invoice, params.format),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            axum::Json(serde_json::json!({ "error": "invoice not found" })),
+        )
+            .into_response(),
+        Err(e) => axum::Json(serde_json::json!({ "error": e.to_string() })).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FinalizeBody {
+    pub tenant_id: String,
+    pub start: String, // ISO 8601
+    pub end: String,   // ISO 8601
+}
+
+/// Freeze the invoice of an ended period; requires the admin token
+pub async fn finalize_invoice(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<FinalizeBody>,
+) -> axum::response::Response {
+    if let Err(e) = auth::verify_admin_token(&headers) {
+        return e.into_response();
+    }
+    let (start, end) = match (parse_time("start", &body.start), parse_time("end", &body.end)) {
+        (Ok(start), Ok(end)) => (start, end),
+        (Err(e), _) | (_, Err(e)) => {
+            return (StatusCode::BAD_REQUEST, axum::Json(serde_json::json!({ "error": e }))).into_response()
+        }
+    };
+    match invoice::finalize(app.invoice_store().as_ref(), &body.tenant_id, start, end).await {
+        Ok(invoice) => axum::Json(serde_json::json!({ "invoice": invoice })).into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            axum::Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
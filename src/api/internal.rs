@@ -0,0 +1,45 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+
+use crate::{auth, routing::AppState};
+
+/// Current circuit breaker state for every route that has seen traffic
+pub async fn circuit_breakers(State(app): State<AppState>) -> impl IntoResponse {
+    Json(serde_json::json!({
+        "breakers": app.circuit_breakers().snapshot()
+    }))
+}
+
+/// Active routing config path and generation
+pub async fn registry_status(State(app): State<AppState>) -> impl IntoResponse {
+    let registry = app.registry();
+    Json(serde_json::json!({
+        "path": registry.path(),
+        "generation": registry.generation()
+    }))
+}
+
+/// Re-read and validate the routing config, swapping it in on success
+pub async fn reload_registry(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+) -> axum::response::Response {
+    if let Err(e) = auth::verify_admin_token(&headers) {
+        return e.into_response();
+    }
+    match app.registry().reload("admin endpoint").await {
+        Ok(generation) => Json(serde_json::json!({ "generation": generation })).into_response(),
+        Err(e) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({
+                "error": { "message": e.to_string(), "type": "config_error" },
+                "generation": app.registry().generation()
+            })),
+        )
+            .into_response(),
+    }
+}
@@ -3,3 +3,4 @@ pub mod anthropic_adapter;
 pub mod openai;
 pub mod openai_adapter;
 pub mod billing;
+pub mod internal;
@@ -1,59 +1,47 @@
-use crate::{auth, core::entities::UnifiedRequest, routing::AppState};
+use crate::{billing::TpmLimits, core::entities::UnifiedRequest, db::KeyInfo, routing::AppState};
 use axum::{
     extract::State,
     response::{IntoResponse, Response},
-    Json,
+    Extension, Json,
 };
 use futures_util::StreamExt;
 
 pub async fn chat_completions(
     State(app): State<AppState>,
-    headers: axum::http::HeaderMap,
+    Extension(key_info): Extension<KeyInfo>,
+    Extension(tpm_limits): Extension<TpmLimits>,
     Json(req): Json<crate::api::openai_adapter::OpenAiChatRequest>,
 ) -> Response {
-    // 1) XJPkey 鉴权
-    let raw_key = match auth::extract_xjpkey(&headers) {
-        Ok(key) => key,
-        Err(e) => return e.into_response(),
-    };
-
-    // 2) 验证密钥并获取密钥信息
-    let key_store = app.key_store();
-    let key_info = match auth::verify_key(&*key_store, &raw_key).await {
-        Ok(info) => info,
-        Err(e) => return e.into_response(),
-    };
-
-    // 3) 适配为 UnifiedRequest
+    // 1) 适配为 UnifiedRequest（密钥已由 auth 中间件验证，限流与配额由 rate limit 中间件执行）
     let unified: UnifiedRequest = crate::api::openai_adapter::to_unified(req);
     let model_name = unified.logical_model.clone();
 
-    // 4) 调用路由（with billing tracking）
-    match app.invoke_with_billing(unified, key_info.tenant_id.clone(), key_info.id).await {
+    // 2) 调用路由（with billing tracking）
+    match app
+        .invoke_with_billing(unified, key_info.tenant_id.clone(), key_info.id, tpm_limits)
+        .await
+    {
         Ok(crate::connectors::ConnectorResponse::Streaming(stream)) => {
-            // 将 UnifiedChunk → OpenAI 流式片段
-            let mapped = stream.map(
-                move |item| -> Result<axum::response::sse::Event, std::convert::Infallible> {
+            // 将 UnifiedChunk → OpenAI 流式片段（含 tool_calls 增量与 finish_reason）
+            let mut encoder = crate::api::openai_adapter::StreamEncoder::new(&model_name);
+            let mapped = stream.flat_map(move |item| {
+                let events: Vec<Result<axum::response::sse::Event, std::convert::Infallible>> =
                     match item {
-                        Ok(chunk) => {
-                            if chunk.done && chunk.text_delta.is_none() {
-                                return Ok(axum::response::sse::Event::default().data("[DONE]"));
-                            }
-                            let data =
-                                crate::api::openai_adapter::from_unified_chunk(&model_name, chunk);
-                            let json = serde_json::to_string(&data).unwrap_or("{}".to_string());
-                            Ok(axum::response::sse::Event::default().data(json))
-                        }
+                        Ok(chunk) => encoder
+                            .encode(chunk)
+                            .into_iter()
+                            .map(|data| Ok(axum::response::sse::Event::default().data(data)))
+                            .collect(),
                         Err(e) => {
                             let json = serde_json::json!({
                                 "error": { "message": e.to_string() }
                             })
                             .to_string();
-                            Ok(axum::response::sse::Event::default().data(json))
+                            vec![Ok(axum::response::sse::Event::default().data(json))]
                         }
-                    }
-                },
-            );
+                    };
+                futures_util::stream::iter(events)
+            });
             axum::response::Sse::new(mapped).into_response()
         }
         Ok(crate::connectors::ConnectorResponse::NonStreaming(chunk)) => {
@@ -3,7 +3,10 @@ use serde_json::json;
 use time::OffsetDateTime;
 use uuid::Uuid;
 
-use crate::core::entities::{ContentPart, ToolSpec, UnifiedChunk, UnifiedMessage, UnifiedRequest};
+use crate::core::entities::{
+    ContentPart, FinishReason, ToolCall, ToolCallDelta, ToolChoice, ToolSpec, UnifiedChunk,
+    UnifiedMessage, UnifiedRequest,
+};
 
 #[derive(Deserialize)]
 pub struct OpenAiChatRequest {
@@ -12,6 +15,8 @@ pub struct OpenAiChatRequest {
     #[serde(default)]
     pub tools: Option<Vec<serde_json::Value>>,
     #[serde(default)]
+    pub tool_choice: Option<serde_json::Value>,
+    #[serde(default)]
     pub stream: Option<bool>,
     #[serde(default)]
     pub temperature: Option<f32>,
@@ -23,6 +28,21 @@ pub struct OpenAiChatRequest {
     pub extra: serde_json::Value,
 }
 
+/// `"auto" | "none" | "required"` or `{"type":"function","function":{"name":..}}`
+fn to_tool_choice(v: &serde_json::Value) -> Option<ToolChoice> {
+    match v {
+        serde_json::Value::String(s) => match s.as_str() {
+            "auto" => Some(ToolChoice::Auto),
+            "none" => Some(ToolChoice::None),
+            "required" => Some(ToolChoice::Required),
+            _ => None,
+        },
+        _ => Some(ToolChoice::Tool {
+            name: v.pointer("/function/name")?.as_str()?.to_string(),
+        }),
+    }
+}
+
 pub fn to_unified(req: OpenAiChatRequest) -> UnifiedRequest {
     let mut messages = Vec::new();
     for m in req.messages {
@@ -66,10 +86,32 @@ pub fn to_unified(req: OpenAiChatRequest) -> UnifiedRequest {
             }
             _ => {}
         }
+        let tool_calls = m
+            .get("tool_calls")
+            .and_then(|x| x.as_array())
+            .map(|calls| {
+                calls
+                    .iter()
+                    .map(|call| ToolCall {
+                        id: call["id"].as_str().unwrap_or("").to_string(),
+                        name: call["function"]["name"].as_str().unwrap_or("").to_string(),
+                        arguments: call["function"]["arguments"]
+                            .as_str()
+                            .unwrap_or("{}")
+                            .to_string(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
         messages.push(UnifiedMessage {
             role,
             content: parts,
-            name: None,
+            name: m.get("name").and_then(|x| x.as_str()).map(String::from),
+            tool_calls,
+            tool_call_id: m
+                .get("tool_call_id")
+                .and_then(|x| x.as_str())
+                .map(String::from),
         });
     }
 
@@ -103,7 +145,7 @@ pub fn to_unified(req: OpenAiChatRequest) -> UnifiedRequest {
         logical_model: req.model,
         messages,
         tools,
-        tool_choice: None, // TODO: handle tool_choice if needed
+        tool_choice: req.tool_choice.as_ref().and_then(to_tool_choice),
         max_output_tokens: req.max_tokens,
         temperature: req.temperature,
         top_p: req.top_p,
@@ -112,17 +154,47 @@ pub fn to_unified(req: OpenAiChatRequest) -> UnifiedRequest {
     }
 }
 
+fn finish_reason_str(reason: FinishReason) -> &'static str {
+    match reason {
+        FinishReason::Stop => "stop",
+        FinishReason::Length => "length",
+        FinishReason::ToolCalls => "tool_calls",
+        FinishReason::ContentFilter => "content_filter",
+    }
+}
+
+fn tool_call_json(call: &ToolCallDelta, with_index: bool) -> serde_json::Value {
+    let mut function = serde_json::Map::new();
+    if let Some(name) = &call.name {
+        function.insert("name".into(), json!(name));
+    }
+    if let Some(arguments) = &call.arguments {
+        function.insert("arguments".into(), json!(arguments));
+    }
+    let mut v = json!({ "function": function });
+    if with_index {
+        v["index"] = json!(call.index);
+    }
+    if let Some(id) = &call.id {
+        v["id"] = json!(id);
+        v["type"] = json!("function");
+    }
+    v
+}
+
 #[derive(Serialize)]
 struct OpenAiDelta {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub role: Option<String>,
     pub content: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<serde_json::Value>,
 }
 
 #[derive(Serialize)]
 struct OpenAiChoiceDelta {
     pub index: u32,
     pub delta: OpenAiDelta,
-    #[serde(skip_serializing_if = "Option::is_none")]
     pub finish_reason: Option<String>,
 }
 
@@ -135,35 +207,120 @@ struct OpenAiStreamChunk {
     pub choices: Vec<OpenAiChoiceDelta>,
 }
 
-pub fn from_unified_chunk(model: &str, chunk: UnifiedChunk) -> serde_json::Value {
-    let id = format!("chatcmpl-{}", Uuid::new_v4());
-    let created = OffsetDateTime::now_utc().unix_timestamp();
-    let delta = OpenAiDelta {
-        role: None,
-        content: chunk.text_delta.clone(),
-    };
-    let choice = OpenAiChoiceDelta {
-        index: 0,
-        delta,
-        finish_reason: if chunk.done {
-            Some("stop".into())
-        } else {
+/// Turns unified chunks into `chat.completion.chunk` payloads sharing one id,
+/// ending with a chunk that carries `finish_reason` and the `[DONE]` marker
+pub struct StreamEncoder {
+    id: String,
+    created: i64,
+    model: String,
+    role_sent: bool,
+    saw_tool_calls: bool,
+    finish_sent: bool,
+    done: bool,
+}
+
+impl StreamEncoder {
+    pub fn new(model: &str) -> Self {
+        Self {
+            id: format!("chatcmpl-{}", Uuid::new_v4()),
+            created: OffsetDateTime::now_utc().unix_timestamp(),
+            model: model.to_string(),
+            role_sent: false,
+            saw_tool_calls: false,
+            finish_sent: false,
+            done: false,
+        }
+    }
+
+    fn chunk(&mut self, delta: OpenAiDelta, finish_reason: Option<String>) -> String {
+        let chunk = OpenAiStreamChunk {
+            id: self.id.clone(),
+            object: "chat.completion.chunk".into(),
+            created: self.created,
+            model: self.model.clone(),
+            choices: vec![OpenAiChoiceDelta {
+                index: 0,
+                delta,
+                finish_reason,
+            }],
+        };
+        serde_json::to_string(&chunk).unwrap_or("{}".to_string())
+    }
+
+    /// SSE `data:` payloads for one upstream chunk
+    pub fn encode(&mut self, chunk: UnifiedChunk) -> Vec<String> {
+        let mut out = Vec::new();
+        if self.done {
+            return out;
+        }
+        self.saw_tool_calls |= !chunk.tool_calls.is_empty();
+        let finish_reason = if self.finish_sent {
             None
-        },
-    };
-    serde_json::to_value(OpenAiStreamChunk {
-        id,
-        object: "chat.completion.chunk".into(),
-        created,
-        model: model.into(),
-        choices: vec![choice],
-    })
-    .unwrap()
+        } else {
+            chunk
+                .finish_reason
+                .map(|r| finish_reason_str(r).to_string())
+        };
+        let has_content = chunk.text_delta.as_deref().is_some_and(|t| !t.is_empty())
+            || !chunk.tool_calls.is_empty();
+        if has_content || finish_reason.is_some() {
+            let delta = OpenAiDelta {
+                role: (!self.role_sent).then(|| "assistant".to_string()),
+                content: chunk.text_delta,
+                tool_calls: chunk
+                    .tool_calls
+                    .iter()
+                    .map(|c| tool_call_json(c, true))
+                    .collect(),
+            };
+            self.role_sent = true;
+            self.finish_sent |= finish_reason.is_some();
+            out.push(self.chunk(delta, finish_reason));
+        }
+        if chunk.done {
+            self.done = true;
+            if !self.finish_sent {
+                let reason = if self.saw_tool_calls {
+                    "tool_calls"
+                } else {
+                    "stop"
+                };
+                let delta = OpenAiDelta {
+                    role: None,
+                    content: None,
+                    tool_calls: Vec::new(),
+                };
+                out.push(self.chunk(delta, Some(reason.into())));
+            }
+            out.push("[DONE]".to_string());
+        }
+        out
+    }
 }
 
 pub fn from_unified_final(model: &str, chunk: UnifiedChunk) -> serde_json::Value {
     let id = format!("chatcmpl-{}", Uuid::new_v4());
     let created = OffsetDateTime::now_utc().unix_timestamp();
+    let finish_reason = match chunk.finish_reason {
+        Some(reason) => finish_reason_str(reason),
+        None if !chunk.tool_calls.is_empty() => "tool_calls",
+        None => "stop",
+    };
+    let mut message = json!({
+        "role": "assistant",
+        "content": chunk.text_delta.filter(|t| !t.is_empty())
+    });
+    if chunk.tool_calls.is_empty() {
+        if message["content"].is_null() {
+            message["content"] = json!("");
+        }
+    } else {
+        message["tool_calls"] = chunk
+            .tool_calls
+            .iter()
+            .map(|c| tool_call_json(c, false))
+            .collect();
+    }
     json!({
         "id": id,
         "object": "chat.completion",
@@ -171,11 +328,94 @@ pub fn from_unified_final(model: &str, chunk: UnifiedChunk) -> serde_json::Value
         "model": model,
         "choices": [{
             "index": 0,
-            "finish_reason": "stop",
-            "message": {
-                "role": "assistant",
-                "content": chunk.text_delta.unwrap_or_default()
-            }
+            "finish_reason": finish_reason,
+            "message": message
         }]
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_agent_turns_to_unified() {
+        let req: OpenAiChatRequest = serde_json::from_value(json!({
+            "model": "gpt-4o",
+            "tool_choice": {"type": "function", "function": {"name": "get_weather"}},
+            "messages": [
+                {"role": "user", "content": "weather in Paris?"},
+                {"role": "assistant", "content": null, "tool_calls": [
+                    {"id": "call_1", "type": "function",
+                     "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}}
+                ]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "18C"}
+            ]
+        }))
+        .unwrap();
+        let unified = to_unified(req);
+
+        assert_eq!(
+            unified.tool_choice,
+            Some(ToolChoice::Tool {
+                name: "get_weather".into()
+            })
+        );
+        assert!(unified.messages[1].content.is_empty());
+        assert_eq!(unified.messages[1].tool_calls[0].id, "call_1");
+        assert_eq!(unified.messages[2].tool_call_id.as_deref(), Some("call_1"));
+        assert!(unified.extra.get("tool_choice").is_none());
+    }
+
+    #[test]
+    fn test_stream_tool_call_deltas() {
+        let mut enc = StreamEncoder::new("gpt-4o");
+        let chunk = |tool_calls, done| UnifiedChunk {
+            text_delta: None,
+            tool_calls,
+            finish_reason: None,
+            done,
+            provider_events: None,
+        };
+        let first = enc.encode(chunk(
+            vec![ToolCallDelta {
+                index: 0,
+                id: Some("call_1".into()),
+                name: Some("get_weather".into()),
+                arguments: Some(String::new()),
+            }],
+            false,
+        ));
+        let v: serde_json::Value = serde_json::from_str(&first[0]).unwrap();
+        assert_eq!(v["choices"][0]["delta"]["role"], "assistant");
+        assert_eq!(v["choices"][0]["delta"]["tool_calls"][0]["id"], "call_1");
+
+        let last = enc.encode(chunk(vec![], true));
+        let v: serde_json::Value = serde_json::from_str(&last[0]).unwrap();
+        assert_eq!(v["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(last[1], "[DONE]");
+    }
+
+    #[test]
+    fn test_final_tool_calls() {
+        let body = from_unified_final(
+            "gpt-4o",
+            UnifiedChunk {
+                text_delta: None,
+                tool_calls: vec![ToolCallDelta {
+                    index: 0,
+                    id: Some("call_1".into()),
+                    name: Some("f".into()),
+                    arguments: Some("{}".into()),
+                }],
+                finish_reason: Some(FinishReason::ToolCalls),
+                done: true,
+                provider_events: None,
+            },
+        );
+        let choice = &body["choices"][0];
+        assert_eq!(choice["finish_reason"], "tool_calls");
+        assert!(choice["message"]["content"].is_null());
+        assert_eq!(choice["message"]["tool_calls"][0]["function"]["name"], "f");
+    }
+}
@@ -1,5 +1,6 @@
 use axum::http::StatusCode;
 use axum::{
+    extract::State,
     http::HeaderMap,
     response::{IntoResponse, Response},
     Json,
@@ -7,6 +8,7 @@ use axum::{
 
 use crate::db::keys::KeyStoreError;
 use crate::db::{KeyInfo, KeyStore};
+use crate::routing::AppState;
 
 #[derive(thiserror::Error, Debug)]
 pub enum AuthError {
@@ -22,6 +24,8 @@ pub enum AuthError {
     Expired,
     #[error("database error: {0}")]
     Database(String),
+    #[error("admin endpoints are disabled (XJP_ADMIN_TOKEN not set)")]
+    AdminDisabled,
 }
 
 impl From<KeyStoreError> for AuthError {
@@ -56,59 +60,79 @@ pub fn extract_xjpkey(headers: &HeaderMap) -> Result<String, AuthError> {
     Err(AuthError::Missing)
 }
 
+/// Check the `x-admin-token` header against `XJP_ADMIN_TOKEN` for internal
+/// endpoints that change gateway state
+pub fn verify_admin_token(headers: &HeaderMap) -> Result<(), AuthError> {
+    let expected = std::env::var("XJP_ADMIN_TOKEN")
+        .ok()
+        .filter(|t| !t.is_empty())
+        .ok_or(AuthError::AdminDisabled)?;
+    let given = headers
+        .get("x-admin-token")
+        .and_then(|v| v.to_str().ok())
+        .ok_or(AuthError::Missing)?;
+
+    // Compare digests so the check does not leak the token length or prefix
+    use sha2::{Digest, Sha256};
+    if Sha256::digest(given.as_bytes()) == Sha256::digest(expected.as_bytes()) {
+        Ok(())
+    } else {
+        Err(AuthError::Invalid)
+    }
+}
+
 /// Verify an API key using the KeyStore and return KeyInfo
 pub async fn verify_key(key_store: &dyn KeyStore, raw_key: &str) -> Result<KeyInfo, AuthError> {
-    let key_info = key_store.verify_key(raw_key).await?;
-
-    // Update last_used_at asynchronously (fire and forget)
-    let key_id = key_info.id;
-    tokio::spawn(async move {
-        // Note: we need to clone the key_store or pass it differently
-        // For now, this is a placeholder - we'll fix this in the middleware
-        tracing::debug!("Key {} used", key_id);
-    });
+    Ok(key_store.verify_key(raw_key).await?)
+}
 
-    Ok(key_info)
+impl AuthError {
+    /// `type` label of the `AUTH_ERRORS` metric
+    pub fn metric_label(&self) -> &'static str {
+        match self {
+            AuthError::Missing => "missing",
+            AuthError::Invalid => "invalid",
+            AuthError::NotFound => "not_found",
+            AuthError::Inactive => "inactive",
+            AuthError::Expired => "expired",
+            AuthError::Database(_) => "database",
+            AuthError::AdminDisabled => "admin_disabled",
+        }
+    }
 }
 
-/// Authentication middleware
+/// Authenticate the XJP key of a request once and make its `KeyInfo`
+/// available to later layers and handlers as a request extension
 pub async fn auth_middleware(
+    State(app): State<AppState>,
     mut request: axum::extract::Request,
     next: axum::middleware::Next,
-) -> Result<axum::response::Response, axum::response::Response> {
-    use axum::extract::State;
-
-    // Extract headers
-    let headers = request.headers().clone();
-
-    // Extract the raw key
-    let raw_key = match extract_xjpkey(&headers) {
-        Ok(key) => key,
-        Err(e) => return Err(e.into_response()),
+) -> Response {
+    let key_store = app.key_store();
+    let verified = match extract_xjpkey(request.headers()) {
+        Ok(raw_key) => verify_key(&*key_store, &raw_key).await,
+        Err(e) => Err(e),
+    };
+    let key_info = match verified {
+        Ok(info) => info,
+        Err(e) => {
+            crate::metrics::AUTH_ERRORS
+                .with_label_values(&[e.metric_label()])
+                .inc();
+            return e.into_response();
+        }
     };
 
-    // Get KeyStore from extensions (set by the state)
-    let key_store = request
-        .extensions()
-        .get::<std::sync::Arc<dyn KeyStore>>()
-        .cloned();
-
-    if let Some(key_store) = key_store {
-        // Verify the key
-        let key_info = match verify_key(&*key_store, &raw_key).await {
-            Ok(info) => info,
-            Err(e) => return Err(e.into_response()),
-        };
-
-        // Store KeyInfo in request extensions for downstream middleware/handlers
-        request.extensions_mut().insert(key_info);
-    } else {
-        return Err(
-            AuthError::Database("KeyStore not found in extensions".to_string()).into_response(),
-        );
-    }
+    // Update last_used_at without holding up the request
+    let key_id = key_info.id;
+    tokio::spawn(async move {
+        if let Err(e) = key_store.touch_key(key_id).await {
+            tracing::warn!("Failed to update last_used_at of key {}: {}", key_id, e);
+        }
+    });
 
-    Ok(next.run(request).await)
+    request.extensions_mut().insert(key_info);
+    next.run(request).await
 }
 
 impl IntoResponse for AuthError {
@@ -120,6 +144,7 @@ impl IntoResponse for AuthError {
             AuthError::Inactive => StatusCode::FORBIDDEN,
             AuthError::Expired => StatusCode::UNAUTHORIZED,
             AuthError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AuthError::AdminDisabled => StatusCode::FORBIDDEN,
         };
         let body = serde_json::json!({
             "error": { "message": self.to_string(), "type": "auth_error" }
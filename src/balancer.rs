@@ -0,0 +1,254 @@
+use dashmap::DashMap;
+use rand::Rng;
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::registry::{EgressRoute, LbStrategy};
+
+/// Weight of the newest sample in the latency moving average
+const LATENCY_EWMA_ALPHA: f64 = 0.3;
+
+/// Latency sample recorded for a failed request, so a fast-failing route
+/// does not look like the fastest one
+const FAILURE_PENALTY: Duration = Duration::from_secs(10);
+
+/// In-process statistics for one route
+#[derive(Default)]
+struct RouteStats {
+    inflight: AtomicI64,
+    /// Exponentially weighted moving average latency in microseconds (0 = no samples yet)
+    latency_ewma_us: AtomicU64,
+}
+
+/// Decrements the route's in-flight counter when dropped
+pub struct InflightGuard {
+    stats: Arc<RouteStats>,
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        self.stats.inflight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Picks which route of a logical model to try first
+pub struct LoadBalancer {
+    /// Keyed by route id, so routes to the same model with different keys are tracked apart
+    stats: DashMap<String, Arc<RouteStats>>,
+    round_robin: DashMap<String, AtomicUsize>,
+}
+
+impl LoadBalancer {
+    pub fn new() -> Self {
+        Self {
+            stats: DashMap::new(),
+            round_robin: DashMap::new(),
+        }
+    }
+
+    fn stats(&self, route: &EgressRoute) -> Arc<RouteStats> {
+        self.stats
+            .entry(route.id.clone())
+            .or_default()
+            .clone()
+    }
+
+    /// Order a route chain for one request: the route chosen by `strategy`
+    /// goes first, the rest follow in configuration order for failover.
+    /// Routes with weight 0 are never picked first.
+    pub fn order<'a>(
+        &self,
+        logical_model: &str,
+        strategy: LbStrategy,
+        chain: &'a [EgressRoute],
+    ) -> Vec<&'a EgressRoute> {
+        let eligible: Vec<usize> = (0..chain.len()).filter(|&i| chain[i].weight > 0).collect();
+        let first = match (strategy, eligible.len()) {
+            (LbStrategy::Priority, _) | (_, 0) => None,
+            (_, 1) => Some(eligible[0]),
+            _ => match strategy {
+                LbStrategy::Priority => None,
+                LbStrategy::Weighted => {
+                    let total: u64 = eligible.iter().map(|&i| chain[i].weight as u64).sum();
+                    let mut pick = rand::thread_rng().gen_range(0..total);
+                    eligible.iter().copied().find(|&i| {
+                        let w = chain[i].weight as u64;
+                        if pick < w {
+                            true
+                        } else {
+                            pick -= w;
+                            false
+                        }
+                    })
+                }
+                LbStrategy::RoundRobin => {
+                    let n = self
+                        .round_robin
+                        .entry(logical_model.to_string())
+                        .or_default()
+                        .fetch_add(1, Ordering::Relaxed);
+                    Some(eligible[n % eligible.len()])
+                }
+                // An unsampled route gets one probe request; while that is in
+                // flight it ranks after every sampled route
+                LbStrategy::LeastLatency => eligible.iter().copied().min_by_key(|&i| {
+                    let stats = self.stats(&chain[i]);
+                    match stats.latency_ewma_us.load(Ordering::Relaxed) {
+                        0 if stats.inflight.load(Ordering::Relaxed) > 0 => u64::MAX,
+                        ewma => ewma,
+                    }
+                }),
+                LbStrategy::LeastInflight => eligible
+                    .iter()
+                    .copied()
+                    .min_by_key(|&i| self.stats(&chain[i]).inflight.load(Ordering::Relaxed)),
+            },
+        };
+
+        let mut ordered = Vec::with_capacity(chain.len());
+        if let Some(i) = first {
+            ordered.push(&chain[i]);
+        }
+        ordered.extend(
+            chain
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| Some(*i) != first)
+                .map(|(_, r)| r),
+        );
+        ordered
+    }
+
+    /// Mark a request to this route as in flight until the guard is dropped.
+    /// `max_inflight` is enforced by `ConcurrencyLimiter`, which queues for it.
+    pub fn start(&self, route: &EgressRoute) -> InflightGuard {
+        let stats = self.stats(route);
+        stats.inflight.fetch_add(1, Ordering::Relaxed);
+        InflightGuard { stats }
+    }
+
+    /// Feed a failed request into the latency average as at least `FAILURE_PENALTY`
+    pub fn record_failure(&self, route: &EgressRoute, elapsed: Duration) {
+        self.record_latency(route, elapsed.max(FAILURE_PENALTY));
+    }
+
+    /// Feed an observed upstream latency (time to response headers) into the average
+    pub fn record_latency(&self, route: &EgressRoute, latency: Duration) {
+        let stats = self.stats(route);
+        let sample = latency.as_micros() as u64;
+        let _ = stats
+            .latency_ewma_us
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |prev| {
+                Some(if prev == 0 {
+                    sample
+                } else {
+                    (LATENCY_EWMA_ALPHA * sample as f64 + (1.0 - LATENCY_EWMA_ALPHA) * prev as f64)
+                        as u64
+                })
+            });
+    }
+}
+
+impl Default for LoadBalancer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::ProviderKind;
+
+    fn route(id: &str, weight: u32) -> EgressRoute {
+        EgressRoute {
+            id: id.into(),
+            provider: ProviderKind::OpenRouter,
+            provider_model_id: id.into(),
+            region: None,
+            project: None,
+            extra: Default::default(),
+            timeouts_ms: None,
+            retry: None,
+            weight,
+            capabilities: Default::default(),
+            tokenizer: None,
+            price: None,
+            max_inflight: None,
+            api_key_secret: None,
+        }
+    }
+
+    fn ids(routes: Vec<&EgressRoute>) -> Vec<&str> {
+        routes
+            .iter()
+            .map(|r| r.provider_model_id.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_round_robin_keeps_failover_order() {
+        let lb = LoadBalancer::new();
+        let chain = vec![route("a", 1), route("b", 1), route("fallback", 0)];
+
+        assert_eq!(
+            ids(lb.order("m", LbStrategy::RoundRobin, &chain)),
+            ["a", "b", "fallback"]
+        );
+        assert_eq!(
+            ids(lb.order("m", LbStrategy::RoundRobin, &chain)),
+            ["b", "a", "fallback"]
+        );
+        assert_eq!(
+            ids(lb.order("m", LbStrategy::RoundRobin, &chain)),
+            ["a", "b", "fallback"]
+        );
+    }
+
+    #[test]
+    fn test_least_inflight_and_latency() {
+        let lb = LoadBalancer::new();
+        let chain = vec![route("a", 1), route("b", 1)];
+
+        let _busy = lb.start(&chain[0]);
+        assert_eq!(
+            ids(lb.order("m", LbStrategy::LeastInflight, &chain))[0],
+            "b"
+        );
+
+        lb.record_latency(&chain[0], Duration::from_millis(50));
+        lb.record_latency(&chain[1], Duration::from_millis(500));
+        assert_eq!(ids(lb.order("m", LbStrategy::LeastLatency, &chain))[0], "a");
+    }
+
+    #[test]
+    fn test_least_latency_avoids_failing_route() {
+        let lb = LoadBalancer::new();
+        let chain = vec![route("a", 1), route("b", 1)];
+
+        // Unsampled routes are probed, but not while a probe is in flight
+        let probe = lb.start(&chain[0]);
+        assert_eq!(ids(lb.order("m", LbStrategy::LeastLatency, &chain))[0], "b");
+        lb.record_latency(&chain[1], Duration::from_millis(200));
+        drop(probe);
+        assert_eq!(ids(lb.order("m", LbStrategy::LeastLatency, &chain))[0], "a");
+
+        // Route a answers fast until it starts failing fast
+        lb.record_latency(&chain[0], Duration::from_millis(50));
+        assert_eq!(ids(lb.order("m", LbStrategy::LeastLatency, &chain))[0], "a");
+        lb.record_failure(&chain[0], Duration::from_millis(5));
+        for _ in 0..10 {
+            assert_eq!(ids(lb.order("m", LbStrategy::LeastLatency, &chain))[0], "b");
+        }
+    }
+
+    #[test]
+    fn test_weighted_skips_zero_weight() {
+        let lb = LoadBalancer::new();
+        let chain = vec![route("a", 0), route("b", 3), route("c", 1)];
+        for _ in 0..50 {
+            assert_ne!(ids(lb.order("m", LbStrategy::Weighted, &chain))[0], "a");
+        }
+    }
+}
@@ -0,0 +1,448 @@
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::billing::rollup::SummaryPeriod;
+use crate::billing::BillingTransaction;
+use crate::db::billing::{TransactionCursor, TransactionFilter, TransactionPage};
+use crate::db::{BillingStore, BillingSummary, BudgetStore, CostSummary, NewBudget};
+
+/// Header carrying the webhook body signature, `sha256=<hex>`
+pub const SIGNATURE_HEADER: &str = "X-XJP-Signature";
+
+/// Length of a budget period; periods start at UTC midnight
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetPeriod {
+    Daily,
+    Monthly,
+}
+
+impl BudgetPeriod {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BudgetPeriod::Daily => "daily",
+            BudgetPeriod::Monthly => "monthly",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "daily" => Some(BudgetPeriod::Daily),
+            "monthly" => Some(BudgetPeriod::Monthly),
+            _ => None,
+        }
+    }
+
+    /// Start (inclusive) and end (exclusive) of the period containing `t`
+    pub fn bounds(self, t: time::OffsetDateTime) -> (time::OffsetDateTime, time::OffsetDateTime) {
+        match self {
+            BudgetPeriod::Daily => SummaryPeriod::Daily.bounds(t),
+            BudgetPeriod::Monthly => SummaryPeriod::Monthly.bounds(t),
+        }
+    }
+}
+
+/// Soft spend limit of a tenant, or of one of its API keys. Crossing a
+/// threshold sends an alert; requests are never blocked.
+#[derive(Clone, Debug, Serialize)]
+pub struct Budget {
+    pub id: Uuid,
+    pub tenant_id: String,
+    /// Budget covers this key only; `None` for the tenant-wide budget
+    pub api_key_id: Option<Uuid>,
+    pub period: BudgetPeriod,
+    /// USD of billed cost per period
+    pub amount: Decimal,
+    /// Percentages of `amount` that trigger an alert
+    pub thresholds: Vec<i32>,
+}
+
+impl Budget {
+    /// Thresholds whose line `spend` has reached
+    pub fn crossed(&self, spend: Decimal) -> Vec<i32> {
+        self.thresholds
+            .iter()
+            .copied()
+            .filter(|&t| spend >= self.amount * Decimal::from(t) / Decimal::ONE_HUNDRED)
+            .collect()
+    }
+}
+
+/// Spend of the current period against a budget
+#[derive(Clone, Debug, Serialize)]
+pub struct BudgetStatus {
+    #[serde(flatten)]
+    pub budget: Budget,
+    #[serde(with = "time::serde::rfc3339")]
+    pub period_start: time::OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub period_end: time::OffsetDateTime,
+    pub spend: Decimal,
+    /// Spend as a percentage of the budget, two decimals
+    pub percent: Decimal,
+}
+
+/// Sent once per budget, period and threshold
+#[derive(Clone, Debug, Serialize)]
+pub struct BudgetEvent {
+    pub budget_id: Uuid,
+    pub tenant_id: String,
+    pub api_key_id: Option<Uuid>,
+    pub period: BudgetPeriod,
+    #[serde(with = "time::serde::rfc3339")]
+    pub period_start: time::OffsetDateTime,
+    pub threshold: i32,
+    pub budget: Decimal,
+    pub spend: Decimal,
+    /// Request whose transaction crossed the threshold
+    pub request_id: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub at: time::OffsetDateTime,
+}
+
+/// Delivers budget events
+#[async_trait]
+pub trait BudgetNotifier: Send + Sync {
+    async fn notify(&self, event: &BudgetEvent) -> anyhow::Result<()>;
+}
+
+/// Writes events to the log
+pub struct LogNotifier;
+
+#[async_trait]
+impl BudgetNotifier for LogNotifier {
+    async fn notify(&self, event: &BudgetEvent) -> anyhow::Result<()> {
+        tracing::warn!(
+            "Budget {} of tenant {} reached {}%: {} of {} ({})",
+            event.budget_id,
+            event.tenant_id,
+            event.threshold,
+            event.spend,
+            event.budget,
+            event.period.as_str()
+        );
+        Ok(())
+    }
+}
+
+/// POSTs events as JSON, signed with HMAC-SHA256 of the body
+pub struct WebhookNotifier {
+    client: Client,
+    url: String,
+    secret: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: String, secret: String) -> anyhow::Result<Self> {
+        let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
+        Ok(Self { client, url, secret })
+    }
+
+    /// Hex HMAC-SHA256 of `body`, as sent in `SIGNATURE_HEADER` after `sha256=`
+    pub fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+        mac.update(body);
+        format!("{:x}", mac.finalize().into_bytes())
+    }
+}
+
+#[async_trait]
+impl BudgetNotifier for WebhookNotifier {
+    async fn notify(&self, event: &BudgetEvent) -> anyhow::Result<()> {
+        let body = serde_json::to_vec(event)?;
+        self.client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, format!("sha256={}", Self::sign(&self.secret, &body)))
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Sends every event to each notifier; one failing doesn't stop the others
+pub struct Notifiers(Vec<Box<dyn BudgetNotifier>>);
+
+impl Notifiers {
+    /// Log sink, plus a webhook when `XJP_BUDGET_WEBHOOK_URL` is set
+    /// (signed with `XJP_BUDGET_WEBHOOK_SECRET`)
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut notifiers: Vec<Box<dyn BudgetNotifier>> = vec![Box::new(LogNotifier)];
+        if let Ok(url) = std::env::var("XJP_BUDGET_WEBHOOK_URL") {
+            let secret = std::env::var("XJP_BUDGET_WEBHOOK_SECRET")
+                .map_err(|_| anyhow::anyhow!("XJP_BUDGET_WEBHOOK_SECRET is required with XJP_BUDGET_WEBHOOK_URL"))?;
+            notifiers.push(Box::new(WebhookNotifier::new(url, secret)?));
+        }
+        Ok(Self(notifiers))
+    }
+}
+
+#[async_trait]
+impl BudgetNotifier for Notifiers {
+    async fn notify(&self, event: &BudgetEvent) -> anyhow::Result<()> {
+        for notifier in &self.0 {
+            if let Err(e) = notifier.notify(event).await {
+                tracing::error!("Failed to deliver budget alert for {}: {}", event.budget_id, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compares spend to budgets and sends an event the first time each
+/// threshold is crossed in a period
+pub struct BudgetMonitor {
+    billing: Arc<dyn BillingStore>,
+    budgets: Arc<dyn BudgetStore>,
+    notifier: Arc<dyn BudgetNotifier>,
+}
+
+impl BudgetMonitor {
+    pub fn new(
+        billing: Arc<dyn BillingStore>,
+        budgets: Arc<dyn BudgetStore>,
+        notifier: Arc<dyn BudgetNotifier>,
+    ) -> Self {
+        Self { billing, budgets, notifier }
+    }
+
+    async fn spend(
+        &self,
+        budget: &Budget,
+        start: time::OffsetDateTime,
+        end: time::OffsetDateTime,
+    ) -> Result<CostSummary, sqlx::Error> {
+        match budget.api_key_id {
+            Some(key) => self.billing.get_api_key_cost_summary(key, start, end).await,
+            None => self.billing.get_cost_summary(&budget.tenant_id, start, end).await,
+        }
+    }
+
+    /// Alert on every threshold the transaction's scope has now crossed
+    pub async fn check(&self, tx: &BillingTransaction) -> anyhow::Result<()> {
+        if tx.billed_cost <= Decimal::ZERO {
+            return Ok(());
+        }
+        for budget in self.budgets.budgets_for(&tx.tenant_id, tx.api_key_id).await? {
+            let (start, end) = budget.period.bounds(tx.created_at);
+            let spend = self.spend(&budget, start, end).await?.billed_cost;
+            for threshold in budget.crossed(spend) {
+                let Some(alert_id) = self
+                    .budgets
+                    .claim_alert(budget.id, start, threshold, spend, &tx.request_id)
+                    .await?
+                else {
+                    continue;
+                };
+                let event = BudgetEvent {
+                    budget_id: budget.id,
+                    tenant_id: budget.tenant_id.clone(),
+                    api_key_id: budget.api_key_id,
+                    period: budget.period,
+                    period_start: start,
+                    threshold,
+                    budget: budget.amount,
+                    spend,
+                    request_id: tx.request_id.clone(),
+                    at: time::OffsetDateTime::now_utc(),
+                };
+                // Left undelivered on failure, so a later transaction retries it
+                self.notifier.notify(&event).await?;
+                self.budgets.mark_alert_delivered(alert_id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Current spend of the tenant's budgets, or only those of one key
+    pub async fn status(&self, tenant_id: &str, api_key_id: Option<Uuid>) -> anyhow::Result<Vec<BudgetStatus>> {
+        let now = time::OffsetDateTime::now_utc();
+        let mut statuses = Vec::new();
+        for budget in self.budgets.list_budgets(tenant_id, api_key_id).await? {
+            let (period_start, period_end) = budget.period.bounds(now);
+            let spend = self.spend(&budget, period_start, period_end).await?.billed_cost;
+            let percent = (spend * Decimal::ONE_HUNDRED / budget.amount).round_dp(2);
+            statuses.push(BudgetStatus {
+                budget,
+                period_start,
+                period_end,
+                spend,
+                percent,
+            });
+        }
+        Ok(statuses)
+    }
+
+    pub async fn set_budget(&self, budget: NewBudget) -> Result<Budget, sqlx::Error> {
+        self.budgets.upsert_budget(budget).await
+    }
+}
+
+/// BillingStore that checks budgets after each stored transaction
+pub struct AlertingBillingStore {
+    inner: Arc<dyn BillingStore>,
+    monitor: Arc<BudgetMonitor>,
+}
+
+impl AlertingBillingStore {
+    pub fn new(inner: Arc<dyn BillingStore>, monitor: Arc<BudgetMonitor>) -> Self {
+        Self { inner, monitor }
+    }
+}
+
+#[async_trait]
+impl BillingStore for AlertingBillingStore {
+    async fn insert_transaction(&self, tx: BillingTransaction) -> Result<(), sqlx::Error> {
+        let checked = tx.clone();
+        self.inner.insert_transaction(tx).await?;
+        // Soft limits: a failed check is logged, never surfaced to billing
+        if let Err(e) = self.monitor.check(&checked).await {
+            tracing::error!("Failed to check budgets for request {}: {}", checked.request_id, e);
+        }
+        Ok(())
+    }
+
+    async fn query_transactions(
+        &self,
+        filter: &TransactionFilter,
+        cursor: Option<TransactionCursor>,
+        limit: i64,
+    ) -> Result<TransactionPage, sqlx::Error> {
+        self.inner.query_transactions(filter, cursor, limit).await
+    }
+
+    async fn get_cost_summary(
+        &self,
+        tenant_id: &str,
+        start: time::OffsetDateTime,
+        end: time::OffsetDateTime,
+    ) -> Result<CostSummary, sqlx::Error> {
+        self.inner.get_cost_summary(tenant_id, start, end).await
+    }
+
+    async fn get_api_key_cost_summary(
+        &self,
+        api_key_id: Uuid,
+        start: time::OffsetDateTime,
+        end: time::OffsetDateTime,
+    ) -> Result<CostSummary, sqlx::Error> {
+        self.inner.get_api_key_cost_summary(api_key_id, start, end).await
+    }
+
+    async fn rollup_period(
+        &self,
+        period: SummaryPeriod,
+        start: time::OffsetDateTime,
+        end: time::OffsetDateTime,
+    ) -> Result<u64, sqlx::Error> {
+        self.inner.rollup_period(period, start, end).await
+    }
+
+    async fn days_recorded_between(
+        &self,
+        since: time::OffsetDateTime,
+        until: time::OffsetDateTime,
+    ) -> Result<Vec<time::OffsetDateTime>, sqlx::Error> {
+        self.inner.days_recorded_between(since, until).await
+    }
+
+    async fn rollup_watermark(&self) -> Result<Option<time::OffsetDateTime>, sqlx::Error> {
+        self.inner.rollup_watermark().await
+    }
+
+    async fn set_rollup_watermark(&self, watermark: time::OffsetDateTime) -> Result<(), sqlx::Error> {
+        self.inner.set_rollup_watermark(watermark).await
+    }
+
+    async fn get_billing_summaries(
+        &self,
+        tenant_id: &str,
+        api_key_id: Option<Uuid>,
+        period: SummaryPeriod,
+        start: time::OffsetDateTime,
+        end: time::OffsetDateTime,
+    ) -> Result<Vec<BillingSummary>, sqlx::Error> {
+        self.inner
+            .get_billing_summaries(tenant_id, api_key_id, period, start, end)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(year: i32, month: time::Month, day: u8, hour: u8) -> time::OffsetDateTime {
+        time::Date::from_calendar_date(year, month, day)
+            .unwrap()
+            .with_hms(hour, 0, 0)
+            .unwrap()
+            .assume_utc()
+    }
+
+    #[test]
+    fn test_period_bounds() {
+        use time::Month::{December, January};
+        let t = utc(2025, December, 31, 18);
+        assert_eq!(
+            BudgetPeriod::Daily.bounds(t),
+            (utc(2025, December, 31, 0), utc(2026, January, 1, 0))
+        );
+        assert_eq!(
+            BudgetPeriod::Monthly.bounds(t),
+            (utc(2025, December, 1, 0), utc(2026, January, 1, 0))
+        );
+    }
+
+    #[test]
+    fn test_crossed_thresholds() {
+        let budget = Budget {
+            id: Uuid::nil(),
+            tenant_id: "acme".into(),
+            api_key_id: None,
+            period: BudgetPeriod::Daily,
+            amount: Decimal::from(10),
+            thresholds: vec![50, 80, 100],
+        };
+        assert!(budget.crossed(Decimal::new(499, 2)).is_empty());
+        assert_eq!(budget.crossed(Decimal::from(5)), vec![50]);
+        assert_eq!(budget.crossed(Decimal::new(1001, 2)), vec![50, 80, 100]);
+    }
+
+    #[test]
+    fn test_webhook_signature() {
+        assert_eq!(
+            WebhookNotifier::sign("key", b"The quick brown fox jumps over the lazy dog"),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn test_event_timestamps_are_rfc3339() {
+        let event = BudgetEvent {
+            budget_id: Uuid::nil(),
+            tenant_id: "acme".into(),
+            api_key_id: None,
+            period: BudgetPeriod::Monthly,
+            period_start: utc(2025, time::Month::June, 1, 0),
+            threshold: 80,
+            budget: Decimal::from(500),
+            spend: Decimal::new(40125, 2),
+            request_id: "req_1".into(),
+            at: utc(2025, time::Month::June, 17, 9),
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["period_start"], "2025-06-01T00:00:00Z");
+        assert_eq!(json["period"], "monthly");
+        assert_eq!(json["spend"], "401.25");
+    }
+}
@@ -1,4 +1,6 @@
+use rust_decimal::Decimal;
 use serde::{Serialize, Deserialize};
+use crate::billing::money::round_money;
 use crate::billing::price::ModelPricing;
 use crate::billing::tokens::TokenUsage;
 
@@ -8,27 +10,32 @@ pub struct CostBreakdown {
     pub completion_tokens: u64,
     pub reasoning_tokens: u64,
     pub cached_prompt_tokens: u64,
-    pub prompt_cost: f64,
-    pub completion_cost: f64,
-    pub internal_reasoning_cost: f64,
-    pub cache_read_cost: f64,
-    pub request_cost: f64,
-    pub total_cost: f64,
+    pub prompt_cost: Decimal,
+    pub completion_cost: Decimal,
+    pub internal_reasoning_cost: Decimal,
+    pub cache_read_cost: Decimal,
+    pub request_cost: Decimal,
+    pub total_cost: Decimal,
     pub unit: &'static str,
 }
 
 pub struct CostCalculator;
 
 impl CostCalculator {
+    /// Upstream cost of a request. Each line is rounded with `round_money`;
+    /// the total is their exact sum.
     pub fn compute(usage: &TokenUsage, price: &ModelPricing) -> CostBreakdown {
+        let tokens = |n: u64, unit_price: Decimal| round_money(Decimal::from(n) * unit_price);
+
         let prompt_non_cached = usage.prompt_tokens.saturating_sub(usage.cached_prompt_tokens);
-        let prompt_cost = (prompt_non_cached as f64) * price.prompt;
-        let cache_read_cost = (usage.cached_prompt_tokens as f64) * price.input_cache_read;
+        let prompt_cost = tokens(prompt_non_cached, price.prompt);
+        let cache_read_cost = tokens(usage.cached_prompt_tokens, price.input_cache_read);
 
-        let completion_cost = (usage.completion_tokens as f64) * price.completion;
-        let internal_reasoning_cost = (usage.reasoning_tokens as f64) * if price.internal_reasoning > 0.0 { price.internal_reasoning } else { price.completion };
+        let completion_cost = tokens(usage.completion_tokens, price.completion);
+        let reasoning_price = if price.internal_reasoning > Decimal::ZERO { price.internal_reasoning } else { price.completion };
+        let internal_reasoning_cost = tokens(usage.reasoning_tokens, reasoning_price);
 
-        let request_cost = price.request;
+        let request_cost = round_money(price.request);
 
         let total_cost = prompt_cost + cache_read_cost + completion_cost + internal_reasoning_cost + request_cost;
 
@@ -47,3 +54,212 @@ impl CostCalculator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::billing::money::MONEY_SCALE;
+    use proptest::prelude::*;
+
+    fn price() -> impl Strategy<Value = Decimal> {
+        (0i64..10_000_000, 6u32..=12).prop_map(|(m, scale)| Decimal::new(m, scale))
+    }
+
+    fn usage() -> impl Strategy<Value = TokenUsage> {
+        (0u64..2_000_000, 0u64..200_000, 0u64..50_000, 0u64..2_000_000).prop_map(
+            |(prompt_tokens, completion_tokens, reasoning_tokens, cached)| TokenUsage {
+                prompt_tokens,
+                completion_tokens,
+                reasoning_tokens,
+                cached_prompt_tokens: cached.min(prompt_tokens),
+            },
+        )
+    }
+
+    fn pricing() -> impl Strategy<Value = ModelPricing> {
+        (price(), price(), price(), price(), price()).prop_map(
+            |(prompt, completion, internal_reasoning, input_cache_read, request)| ModelPricing {
+                prompt,
+                completion,
+                internal_reasoning,
+                input_cache_read,
+                request,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn lines(b: &CostBreakdown) -> [Decimal; 5] {
+        [
+            b.prompt_cost,
+            b.completion_cost,
+            b.internal_reasoning_cost,
+            b.cache_read_cost,
+            b.request_cost,
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn prop_breakdown_is_stored_exactly(usage in usage(), price in pricing()) {
+            let b = CostCalculator::compute(&usage, &price);
+            for amount in lines(&b) {
+                prop_assert!(amount.scale() <= MONEY_SCALE);
+                prop_assert_eq!(round_money(amount), amount);
+            }
+            prop_assert_eq!(b.total_cost, lines(&b).iter().sum::<Decimal>());
+        }
+
+    }
+
+    /// Usage and prices small enough for a transaction to fit `DECIMAL(12, 8)`
+    fn stored_request() -> impl Strategy<Value = (TokenUsage, ModelPricing, i64)> {
+        let price = || (0i64..1_000_000, 8u32..=12).prop_map(|(m, scale)| Decimal::new(m, scale));
+        let usage = (0u64..20_000, 0u64..5_000, 0u64..20_000).prop_map(|(prompt_tokens, completion_tokens, cached)| {
+            TokenUsage {
+                prompt_tokens,
+                completion_tokens,
+                reasoning_tokens: 0,
+                cached_prompt_tokens: cached.min(prompt_tokens),
+            }
+        });
+        let pricing = (price(), price(), price(), price()).prop_map(|(prompt, completion, input_cache_read, request)| {
+            ModelPricing {
+                prompt,
+                completion,
+                input_cache_read,
+                request,
+                ..Default::default()
+            }
+        });
+        (usage, pricing, 0i64..86_400)
+    }
+
+    fn transaction(
+        tenant_id: &str,
+        api_key_id: uuid::Uuid,
+        day: time::OffsetDateTime,
+        (usage, price, second): &(TokenUsage, ModelPricing, i64),
+    ) -> crate::billing::BillingTransaction {
+        let b = CostCalculator::compute(usage, price);
+        crate::billing::BillingTransaction {
+            id: uuid::Uuid::new_v4(),
+            tenant_id: tenant_id.to_string(),
+            api_key_id,
+            request_id: uuid::Uuid::new_v4().to_string(),
+            logical_model: "prop-model".into(),
+            provider: "OpenRouter".into(),
+            provider_model_id: "prop/model".into(),
+            prompt_tokens: b.prompt_tokens as i64,
+            completion_tokens: b.completion_tokens as i64,
+            reasoning_tokens: 0,
+            cached_prompt_tokens: b.cached_prompt_tokens as i64,
+            total_tokens: (b.prompt_tokens + b.completion_tokens) as i64,
+            prompt_cost: b.prompt_cost,
+            completion_cost: b.completion_cost,
+            reasoning_cost: b.internal_reasoning_cost,
+            cache_read_cost: b.cache_read_cost,
+            request_cost: b.request_cost,
+            upstream_cost: b.total_cost,
+            billed_cost: b.total_cost,
+            pricing_snapshot: serde_json::json!({}),
+            response_time_ms: 0,
+            status: "success".into(),
+            error_message: None,
+            created_at: day + time::Duration::seconds(*second),
+            attempts: 1,
+            time_to_first_token_ms: None,
+            usage_source: "provider".into(),
+        }
+    }
+
+    /// Store the transactions of a fresh tenant, roll up their day and
+    /// return the computed total, `SUM(billed_cost)`, the daily and monthly
+    /// summary totals and the cost summary total
+    async fn rolled_up_totals(
+        pool: &sqlx::PgPool,
+        requests: &[(TokenUsage, ModelPricing, i64)],
+    ) -> Result<[Decimal; 5], sqlx::Error> {
+        use crate::db::{BillingStore, PgBillingStore};
+
+        let store = PgBillingStore::new(pool.clone());
+        let tenant_id = format!("prop-rollup-{}", uuid::Uuid::new_v4());
+        // A day long past, so real transactions are not rolled up with these
+        let day = time::OffsetDateTime::from_unix_timestamp(981_158_400).unwrap();
+        let api_key_id = sqlx::query_scalar!(
+            "INSERT INTO api_keys (key_hash, tenant_id) VALUES ($1, $2) RETURNING id",
+            tenant_id,
+            tenant_id
+        )
+        .fetch_one(pool)
+        .await?;
+
+        let result = async {
+            let mut computed = Decimal::ZERO;
+            for request in requests {
+                let tx = transaction(&tenant_id, api_key_id, day, request);
+                computed += tx.billed_cost;
+                store.insert_transaction(tx).await?;
+            }
+            crate::billing::rollup::backfill(&store, day, day + time::Duration::DAY).await?;
+
+            let stored = sqlx::query_scalar!(
+                r#"SELECT COALESCE(SUM(billed_cost), 0) as "sum!" FROM billing_transactions WHERE tenant_id = $1"#,
+                tenant_id
+            )
+            .fetch_one(pool)
+            .await?;
+            let summary = |period: &'static str| {
+                sqlx::query_scalar!(
+                    "SELECT billed_cost FROM tenant_billing_summary WHERE tenant_id = $1 AND period_type = $2",
+                    tenant_id,
+                    period
+                )
+                .fetch_one(pool)
+            };
+            let daily = summary("daily").await?;
+            let monthly = summary("monthly").await?;
+            let cost_summary = store
+                .get_cost_summary(&tenant_id, day, day + time::Duration::DAY)
+                .await?
+                .billed_cost;
+            Ok([computed, stored, daily, monthly, cost_summary])
+        }
+        .await;
+
+        // Deleting the key cascades to its transactions and summaries
+        sqlx::query!("DELETE FROM api_keys WHERE id = $1", api_key_id)
+            .execute(pool)
+            .await?;
+        sqlx::query!("DELETE FROM quota_usage WHERE tenant_id = $1", tenant_id)
+            .execute(pool)
+            .await?;
+        result
+    }
+
+    /// Needs the database from `DATABASE_URL`; skipped when it is not set
+    #[test]
+    fn prop_transaction_sums_equal_summary() {
+        let Ok(url) = std::env::var("DATABASE_URL") else {
+            eprintln!("DATABASE_URL not set, skipping");
+            return;
+        };
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let pool = rt.block_on(sqlx::PgPool::connect(&url)).unwrap();
+
+        let mut runner = proptest::test_runner::TestRunner::new(ProptestConfig::with_cases(16));
+        runner
+            .run(&prop::collection::vec(stored_request(), 1..50), |requests| {
+                let totals = rt
+                    .block_on(rolled_up_totals(&pool, &requests))
+                    .map_err(|e| TestCaseError::fail(e.to_string()))?;
+                let [computed, ..] = totals;
+                for total in totals {
+                    prop_assert_eq!(total, computed);
+                }
+                prop_assert!(computed.scale() <= MONEY_SCALE);
+                Ok(())
+            })
+            .unwrap();
+    }
+}
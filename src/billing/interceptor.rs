@@ -1,8 +1,13 @@
-use crate::billing::{PricingCache, CostCalculator, TokenUsage};
-use crate::core::entities::UnifiedRequest;
-use crate::connectors::ConnectorResponse;
+use crate::billing::{CostBreakdown, CostCalculator, ModelPricing, PriceQuery, PricingChain, TokenUsage};
+use crate::billing::rates::{month_start, RatedRequest};
+use crate::billing::tokens::{counter_for, prompt_text};
+use crate::billing::tpm::{TokenHold, TpmLimiter, TpmLimits};
+use crate::db::{CreditStore, RateCardStore, Reservation, ReserveOutcome};
+use crate::core::entities::{UnifiedChunk, UnifiedRequest};
+use crate::connectors::{ConnectorError, ConnectorResponse};
+use rust_decimal::Decimal;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 use serde::{Serialize, Deserialize};
 
@@ -16,8 +21,38 @@ pub struct BillingContext {
     pub provider: String,
     pub provider_model_id: String,
     pub start_time: Instant,
+    /// Number of routes tried before one served the request
+    pub attempts: u32,
+    /// Time until the response completed; defaults to the time `after_request` runs
+    pub response_time: Option<Duration>,
+    /// Streaming only: time until the first content chunk
+    pub time_to_first_token: Option<Duration>,
+    /// Tokenizer of the serving route, for estimates when usage is missing
+    pub tokenizer: String,
+    /// Prompt as seen by the tokenizer, kept for estimates
+    pub prompt_text: String,
+    /// Price configured on the serving route, ahead of any other pricing source
+    pub route_price: Option<ModelPricing>,
+    /// Prepaid credit held until the transaction settles
+    pub reservation: Option<Reservation>,
+    /// Tokens-per-minute limits of the key and tenant
+    pub tpm_limits: TpmLimits,
+    /// Tokens charged against those limits until the transaction settles
+    pub token_hold: Option<TokenHold>,
 }
 
+/// A route that may serve a request, as far as reserving for it is concerned
+#[derive(Clone, Copy, Debug)]
+pub struct RouteQuote<'a> {
+    pub provider_model_id: &'a str,
+    pub tokenizer: &'a str,
+    /// Price configured on the route, ahead of any other pricing source
+    pub price: Option<&'a ModelPricing>,
+}
+
+/// Output tokens reserved for requests without `max_output_tokens`
+pub const DEFAULT_RESERVED_OUTPUT_TOKENS: u64 = 4096;
+
 /// A single billing transaction record
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BillingTransaction {
@@ -33,27 +68,42 @@ pub struct BillingTransaction {
     pub reasoning_tokens: i64,
     pub cached_prompt_tokens: i64,
     pub total_tokens: i64,
-    pub prompt_cost: f64,
-    pub completion_cost: f64,
-    pub reasoning_cost: f64,
-    pub cache_read_cost: f64,
-    pub request_cost: f64,
-    pub total_cost: f64,
+    pub prompt_cost: Decimal,
+    pub completion_cost: Decimal,
+    pub reasoning_cost: Decimal,
+    pub cache_read_cost: Decimal,
+    pub request_cost: Decimal,
+    /// What the provider charges: the sum of the costs above
+    pub upstream_cost: Decimal,
+    /// What the tenant is charged after its rate card
+    pub billed_cost: Decimal,
     pub pricing_snapshot: serde_json::Value,
     pub response_time_ms: i32,
     pub status: String,
     pub error_message: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
     pub created_at: time::OffsetDateTime,
+    pub attempts: i32,
+    pub time_to_first_token_ms: Option<i32>,
+    /// `provider` when the upstream reported usage, `estimated` when it was counted locally
+    pub usage_source: String,
 }
 
 /// Billing interceptor for tracking usage and costs
 pub struct BillingInterceptor {
-    pricing_cache: Arc<PricingCache>,
+    pricing: Arc<PricingChain>,
+    rate_cards: Arc<dyn RateCardStore>,
+    credits: Arc<dyn CreditStore>,
+    tpm: TpmLimiter,
 }
 
 impl BillingInterceptor {
-    pub fn new(pricing_cache: Arc<PricingCache>) -> Self {
-        Self { pricing_cache }
+    pub fn new(
+        pricing: Arc<PricingChain>,
+        rate_cards: Arc<dyn RateCardStore>,
+        credits: Arc<dyn CreditStore>,
+    ) -> Self {
+        Self { pricing, rate_cards, credits, tpm: TpmLimiter::new() }
     }
 
     /// Create billing context before request
@@ -64,6 +114,7 @@ impl BillingInterceptor {
         api_key_id: Uuid,
         provider: String,
         provider_model_id: String,
+        tokenizer: String,
     ) -> BillingContext {
         BillingContext {
             request_id: Uuid::new_v4().to_string(),
@@ -73,98 +124,401 @@ impl BillingInterceptor {
             provider,
             provider_model_id,
             start_time: Instant::now(),
+            attempts: 1,
+            response_time: None,
+            time_to_first_token: None,
+            tokenizer,
+            prompt_text: prompt_text(&req.messages),
+            route_price: None,
+            reservation: None,
+            tpm_limits: TpmLimits::default(),
+            token_hold: None,
+        }
+    }
+
+    /// Upper estimate of a request's tokens: the prompt as counted locally
+    /// with `tokenizer` plus `max_output_tokens` of output
+    async fn estimate_request_usage(ctx: &BillingContext, tokenizer: &str, max_output_tokens: Option<u32>) -> TokenUsage {
+        TokenUsage {
+            prompt_tokens: estimate_tokens(tokenizer, &ctx.prompt_text).await,
+            completion_tokens: max_output_tokens
+                .map(u64::from)
+                .unwrap_or(DEFAULT_RESERVED_OUTPUT_TOKENS),
+            reasoning_tokens: 0,
+            cached_prompt_tokens: 0,
+        }
+    }
+
+    /// What a request with this usage will be billed at the tenant's rates
+    /// when `route` serves it, without free tokens
+    async fn cost_of(&self, ctx: &BillingContext, route: &RouteQuote<'_>, usage: &TokenUsage) -> anyhow::Result<Decimal> {
+        let pricing = self
+            .pricing
+            .get(&PriceQuery {
+                provider_model_id: route.provider_model_id,
+                route_price: route.price,
+            })
+            .await?;
+        let breakdown = CostCalculator::compute(usage, &pricing);
+        Ok(match self.rate_cards.active_card(&ctx.tenant_id, ctx.api_key_id).await? {
+            Some(card) => {
+                card.apply(&RatedRequest {
+                    logical_model: &ctx.logical_model,
+                    provider_model_id: route.provider_model_id,
+                    usage,
+                    upstream: &breakdown,
+                    success: true,
+                    free_tokens_left: 0,
+                })
+                .billed_cost
+            }
+            None => breakdown.total_cost,
+        })
+    }
+
+    /// Charge the estimated tokens against the key's and tenant's
+    /// tokens-per-minute limits, then hold the estimated cost on the tenant's
+    /// prepaid account. Any of `routes` may end up serving the request, so
+    /// both holds cover the largest estimate among them. Rejects with `TokenRateLimited`
+    /// (HTTP 429) when a token bucket is empty and with `InsufficientCredit`
+    /// (HTTP 402) when the balance cannot cover the request or no route has a
+    /// price; tenants without an account are not limited.
+    pub async fn reserve(
+        &self,
+        ctx: &mut BillingContext,
+        routes: &[RouteQuote<'_>],
+        max_output_tokens: Option<u32>,
+    ) -> Result<(), ConnectorError> {
+        // Routes count the prompt with their own tokenizer; each is counted once
+        let mut estimates: Vec<(&RouteQuote, TokenUsage)> = Vec::with_capacity(routes.len());
+        for route in routes {
+            let usage = match estimates.iter().find(|(counted, _)| counted.tokenizer == route.tokenizer) {
+                Some((_, usage)) => usage.clone(),
+                None => Self::estimate_request_usage(ctx, route.tokenizer, max_output_tokens).await,
+            };
+            estimates.push((route, usage));
+        }
+
+        let tokens = estimates
+            .iter()
+            .map(|(_, usage)| usage.prompt_tokens + usage.completion_tokens)
+            .max()
+            .unwrap_or_default();
+        ctx.token_hold = self
+            .tpm
+            .acquire(&ctx.tenant_id, ctx.api_key_id, ctx.tpm_limits, tokens)
+            .map_err(|retry_after| ConnectorError::TokenRateLimited { retry_after })?;
+
+        let reserved = self.reserve_credit(ctx, &estimates).await;
+        if reserved.is_err() {
+            if let Some(hold) = ctx.token_hold.take() {
+                self.tpm.release(hold);
+            }
+        }
+        reserved
+    }
+
+    async fn reserve_credit(
+        &self,
+        ctx: &mut BillingContext,
+        estimates: &[(&RouteQuote<'_>, TokenUsage)],
+    ) -> Result<(), ConnectorError> {
+        let account = match self.credits.account(&ctx.tenant_id, Some(ctx.api_key_id)).await {
+            Ok(None) => self.credits.account(&ctx.tenant_id, None).await,
+            found => found,
+        }
+        .map_err(|e| ConnectorError::Internal(format!("credit check failed: {}", e)))?;
+        if account.is_none() {
+            return Ok(());
+        }
+
+        let mut amount = None;
+        for (route, usage) in estimates {
+            match self.cost_of(ctx, route, usage).await {
+                Ok(cost) => amount = amount.max(Some(cost)),
+                Err(e) => tracing::debug!("No cost estimate for {}: {}", route.provider_model_id, e),
+            }
+        }
+        // An unpriced request would use a prepaid balance for free
+        let Some(amount) = amount else {
+            tracing::warn!(
+                "Refusing unpriced model {} for prepaid tenant {}",
+                ctx.logical_model,
+                ctx.tenant_id
+            );
+            return Err(ConnectorError::InsufficientCredit(format!(
+                "model {} has no price, so it cannot be charged to a prepaid balance",
+                ctx.logical_model
+            )));
+        };
+        match self
+            .credits
+            .reserve(&ctx.tenant_id, ctx.api_key_id, &ctx.request_id, amount)
+            .await
+            .map_err(|e| ConnectorError::Internal(format!("credit check failed: {}", e)))?
+        {
+            ReserveOutcome::NoAccount => Ok(()),
+            ReserveOutcome::Reserved(reservation) => {
+                ctx.reservation = Some(reservation);
+                Ok(())
+            }
+            ReserveOutcome::Insufficient { available } => Err(ConnectorError::InsufficientCredit(format!(
+                "available balance {} USD does not cover the estimated cost {} USD",
+                available, amount
+            ))),
         }
     }
 
-    /// Process billing after request completes
+    /// Correct the tokens charged up front to the tokens actually used
+    pub fn settle_tokens(&self, hold: Option<TokenHold>, actual: u64) {
+        if let Some(hold) = hold {
+            self.tpm.settle(hold, actual);
+        }
+    }
+
+    /// Debit the billed cost and release the credit held for the request
+    pub async fn settle(&self, reservation: Option<Reservation>, cost: Decimal) {
+        let Some(reservation) = reservation else {
+            return;
+        };
+        tracing::debug!(
+            "Settling request {}: reserved {} USD, billed {} USD",
+            reservation.request_id,
+            reservation.amount,
+            cost
+        );
+        if let Err(e) = self.credits.settle(&reservation, cost).await {
+            tracing::error!("Failed to settle credit for request {}: {}", reservation.request_id, e);
+        }
+    }
+
+    /// Process billing after request completes. Without provider-reported
+    /// `usage` the tokens are estimated from the prompt and `completion_text`.
     pub async fn after_request(
         &self,
-        ctx: BillingContext,
-        response: &ConnectorResponse,
+        ctx: &BillingContext,
+        usage: Option<TokenUsage>,
+        completion_text: &str,
         status: &str,
         error_message: Option<String>,
     ) -> anyhow::Result<BillingTransaction> {
-        // 1. Extract usage from response
-        let usage = self.extract_usage(response).unwrap_or_default();
+        let (usage, usage_source) = resolve_usage(ctx, usage, completion_text).await;
 
-        // 2. Fetch pricing
-        let pricing = self.pricing_cache.get(&ctx.provider_model_id).await?;
+        // 1. Fetch pricing
+        let pricing = self
+            .pricing
+            .get(&PriceQuery {
+                provider_model_id: &ctx.provider_model_id,
+                route_price: ctx.route_price.as_ref(),
+            })
+            .await?;
 
-        // 3. Calculate cost breakdown
+        // 2. Calculate cost breakdown
         let breakdown = CostCalculator::compute(&usage, &pricing);
 
-        // 4. Build transaction record
-        let transaction = BillingTransaction {
-            id: Uuid::new_v4(),
-            tenant_id: ctx.tenant_id,
-            api_key_id: ctx.api_key_id,
-            request_id: ctx.request_id,
-            logical_model: ctx.logical_model,
-            provider: ctx.provider,
-            provider_model_id: ctx.provider_model_id,
-
-            prompt_tokens: breakdown.prompt_tokens as i64,
-            completion_tokens: breakdown.completion_tokens as i64,
-            reasoning_tokens: breakdown.reasoning_tokens as i64,
-            cached_prompt_tokens: breakdown.cached_prompt_tokens as i64,
-            total_tokens: (breakdown.prompt_tokens + breakdown.completion_tokens) as i64,
-
-            prompt_cost: breakdown.prompt_cost,
-            completion_cost: breakdown.completion_cost,
-            reasoning_cost: breakdown.internal_reasoning_cost,
-            cache_read_cost: breakdown.cache_read_cost,
-            request_cost: breakdown.request_cost,
-            total_cost: breakdown.total_cost,
-
-            pricing_snapshot: serde_json::to_value(&pricing)?,
-            response_time_ms: ctx.start_time.elapsed().as_millis() as i32,
-            status: status.to_string(),
-            error_message,
-            created_at: time::OffsetDateTime::now_utc(),
+        // 3. Apply the tenant's rate card, if it has one
+        let mut pricing_snapshot = serde_json::to_value(&pricing)?;
+        let billed_cost = match self.rate_cards.active_card(&ctx.tenant_id, ctx.api_key_id).await? {
+            Some(card) => {
+                let free_tokens_left = if card.free_tokens_per_month > 0 {
+                    let since = month_start(time::OffsetDateTime::now_utc());
+                    let used = self.rate_cards.tokens_used_since(&card, since).await?;
+                    (card.free_tokens_per_month - used).max(0) as u64
+                } else {
+                    0
+                };
+                let billed = card.apply(&RatedRequest {
+                    logical_model: &ctx.logical_model,
+                    provider_model_id: &ctx.provider_model_id,
+                    usage: &usage,
+                    upstream: &breakdown,
+                    success: status == "success",
+                    free_tokens_left,
+                });
+                pricing_snapshot["rate_card"] = card.snapshot(&billed);
+                billed.billed_cost
+            }
+            None => breakdown.total_cost,
         };
 
-        Ok(transaction)
+        // 4. Build transaction record
+        Ok(transaction(ctx, &breakdown, billed_cost, pricing_snapshot, status, error_message, usage_source))
     }
 
-    /// Extract usage from connector response
-    fn extract_usage(&self, response: &ConnectorResponse) -> anyhow::Result<TokenUsage> {
+    /// Transaction for a request `after_request` could not price: the
+    /// upstream cost is unknown and the tenant is billed `charged`, the
+    /// credit held up front. Stored with status `pricing_failed`.
+    pub async fn unpriced_transaction(
+        &self,
+        ctx: &BillingContext,
+        usage: Option<TokenUsage>,
+        completion_text: &str,
+        charged: Decimal,
+        pricing_error: &anyhow::Error,
+    ) -> BillingTransaction {
+        let (usage, usage_source) = resolve_usage(ctx, usage, completion_text).await;
+        let breakdown = CostBreakdown {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            reasoning_tokens: usage.reasoning_tokens,
+            cached_prompt_tokens: usage.cached_prompt_tokens,
+            unit: "USD",
+            ..Default::default()
+        };
+        transaction(
+            ctx,
+            &breakdown,
+            charged,
+            serde_json::json!({ "error": pricing_error.to_string() }),
+            "pricing_failed",
+            Some(format!("pricing failed: {}", pricing_error)),
+            usage_source,
+        )
+    }
+
+    /// Extract usage from a non-streaming connector response, if the
+    /// provider reported any. Streaming responses are billed by
+    /// `billing::stream` once the stream ends.
+    pub fn extract_usage(&self, response: &ConnectorResponse) -> anyhow::Result<Option<TokenUsage>> {
         match response {
-            ConnectorResponse::NonStreaming(chunk) => {
-                if let Some(events) = &chunk.provider_events {
-                    // Try OpenRouter format
-                    if let Some(usage_obj) = events.get("usage") {
-                        let or_usage: crate::billing::OrUsage = serde_json::from_value(
-                            serde_json::json!({"usage": usage_obj})
-                        )?;
-                        return Ok(or_usage.into_token_usage());
-                    }
-
-                    // Try Vertex AI format
-                    if let Some(metadata) = events.get("usageMetadata") {
-                        let prompt = metadata.get("promptTokenCount")
-                            .and_then(|v| v.as_u64()).unwrap_or(0);
-                        let completion = metadata.get("candidatesTokenCount")
-                            .and_then(|v| v.as_u64()).unwrap_or(0);
-                        let reasoning = metadata.get("thoughts_token_count")
-                            .and_then(|v| v.as_u64()).unwrap_or(0);
-
-                        return Ok(TokenUsage {
-                            prompt_tokens: prompt,
-                            completion_tokens: completion,
-                            reasoning_tokens: reasoning,
-                            cached_prompt_tokens: 0,
-                        });
-                    }
-                }
-
-                // Fallback: no usage data
-                Ok(TokenUsage::default())
-            }
-            ConnectorResponse::Streaming(_) => {
-                // For streaming, usage should be accumulated in the last chunk
-                // This is handled at the routing layer
-                Ok(TokenUsage::default())
-            }
+            ConnectorResponse::NonStreaming(chunk) => Ok(chunk
+                .provider_events
+                .as_ref()
+                .map(usage_from_provider_events)
+                .transpose()?
+                .flatten()),
+            ConnectorResponse::Streaming(_) => Ok(None),
         }
     }
 }
+
+fn transaction(
+    ctx: &BillingContext,
+    breakdown: &CostBreakdown,
+    billed_cost: Decimal,
+    pricing_snapshot: serde_json::Value,
+    status: &str,
+    error_message: Option<String>,
+    usage_source: &str,
+) -> BillingTransaction {
+    BillingTransaction {
+        id: Uuid::new_v4(),
+        tenant_id: ctx.tenant_id.clone(),
+        api_key_id: ctx.api_key_id,
+        request_id: ctx.request_id.clone(),
+        logical_model: ctx.logical_model.clone(),
+        provider: ctx.provider.clone(),
+        provider_model_id: ctx.provider_model_id.clone(),
+
+        prompt_tokens: breakdown.prompt_tokens as i64,
+        completion_tokens: breakdown.completion_tokens as i64,
+        reasoning_tokens: breakdown.reasoning_tokens as i64,
+        cached_prompt_tokens: breakdown.cached_prompt_tokens as i64,
+        total_tokens: (breakdown.prompt_tokens + breakdown.completion_tokens) as i64,
+
+        prompt_cost: breakdown.prompt_cost,
+        completion_cost: breakdown.completion_cost,
+        reasoning_cost: breakdown.internal_reasoning_cost,
+        cache_read_cost: breakdown.cache_read_cost,
+        request_cost: breakdown.request_cost,
+        upstream_cost: breakdown.total_cost,
+        billed_cost,
+
+        pricing_snapshot,
+        response_time_ms: ctx
+            .response_time
+            .unwrap_or_else(|| ctx.start_time.elapsed())
+            .as_millis() as i32,
+        status: status.to_string(),
+        error_message,
+        created_at: time::OffsetDateTime::now_utc(),
+        attempts: ctx.attempts as i32,
+        time_to_first_token_ms: ctx.time_to_first_token.map(|d| d.as_millis() as i32),
+        usage_source: usage_source.to_string(),
+    }
+}
+
+/// Provider-reported usage, or a local estimate when there is none
+async fn resolve_usage(
+    ctx: &BillingContext,
+    usage: Option<TokenUsage>,
+    completion_text: &str,
+) -> (TokenUsage, &'static str) {
+    match usage {
+        Some(usage) => (usage, "provider"),
+        None => (estimate_usage(ctx, completion_text).await, "estimated"),
+    }
+}
+
+/// Count tokens locally with the route's tokenizer
+async fn estimate_usage(ctx: &BillingContext, completion_text: &str) -> TokenUsage {
+    TokenUsage {
+        prompt_tokens: estimate_tokens(&ctx.tokenizer, &ctx.prompt_text).await,
+        completion_tokens: estimate_tokens(&ctx.tokenizer, completion_text).await,
+        reasoning_tokens: 0,
+        cached_prompt_tokens: 0,
+    }
+}
+
+async fn estimate_tokens(tokenizer: &str, text: &str) -> u64 {
+    if text.is_empty() {
+        return 0;
+    }
+    counter_for(tokenizer)
+        .count_text(tokenizer, text)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::warn!("Token estimate with {} failed: {}", tokenizer, e);
+            0
+        })
+}
+
+/// Generated text of a chunk as a tokenizer sees it: text and tool call arguments
+pub fn completion_text(chunk: &UnifiedChunk) -> String {
+    let mut text = chunk.text_delta.clone().unwrap_or_default();
+    for call in &chunk.tool_calls {
+        if let Some(name) = &call.name {
+            text.push_str(name);
+        }
+        if let Some(arguments) = &call.arguments {
+            text.push_str(arguments);
+        }
+    }
+    text
+}
+
+/// Usage reported in a provider payload: OpenRouter `usage` or Vertex
+/// `usageMetadata`. Vertex repeats cumulative counts on every stream chunk,
+/// OpenRouter sends them once in the final chunk.
+pub fn usage_from_provider_events(
+    events: &serde_json::Value,
+) -> anyhow::Result<Option<TokenUsage>> {
+    // Try OpenRouter format
+    if let Some(usage_obj) = events.get("usage").filter(|u| !u.is_null()) {
+        let or_usage: crate::billing::OrUsage =
+            serde_json::from_value(serde_json::json!({"usage": usage_obj}))?;
+        return Ok(Some(or_usage.into_token_usage()));
+    }
+
+    // Try Vertex AI format
+    if let Some(metadata) = events.get("usageMetadata") {
+        let prompt = metadata.get("promptTokenCount")
+            .and_then(|v| v.as_u64()).unwrap_or(0);
+        let completion = metadata.get("candidatesTokenCount")
+            .and_then(|v| v.as_u64()).unwrap_or(0);
+        let reasoning = metadata.get("thoughtsTokenCount")
+            .or_else(|| metadata.get("thoughts_token_count"))
+            .and_then(|v| v.as_u64()).unwrap_or(0);
+        let cached = metadata.get("cachedContentTokenCount")
+            .and_then(|v| v.as_u64()).unwrap_or(0);
+
+        return Ok(Some(TokenUsage {
+            prompt_tokens: prompt,
+            completion_tokens: completion,
+            reasoning_tokens: reasoning,
+            cached_prompt_tokens: cached,
+        }));
+    }
+
+    Ok(None)
+}
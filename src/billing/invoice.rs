@@ -0,0 +1,248 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::billing::rollup::ROLLUP_LAG;
+use crate::db::InvoiceStore;
+
+/// Usage of one logical model through one API key within the invoice period
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct InvoiceLine {
+    pub api_key_id: Uuid,
+    pub logical_model: String,
+    pub requests: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub total_tokens: i64,
+    pub upstream_cost: Decimal,
+    pub billed_cost: Decimal,
+}
+
+/// Sums over all lines of an invoice
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct InvoiceTotals {
+    pub requests: i64,
+    pub total_tokens: i64,
+    pub upstream_cost: Decimal,
+    pub billed_cost: Decimal,
+}
+
+impl InvoiceTotals {
+    pub fn of(lines: &[InvoiceLine]) -> Self {
+        lines.iter().fold(Self::default(), |mut totals, line| {
+            totals.requests += line.requests;
+            totals.total_tokens += line.total_tokens;
+            totals.upstream_cost += line.upstream_cost;
+            totals.billed_cost += line.billed_cost;
+            totals
+        })
+    }
+}
+
+/// Charges of a tenant for a billing period. Drafts are computed on request;
+/// finalized invoices are stored and never change.
+#[derive(Clone, Debug, Serialize)]
+pub struct Invoice {
+    pub invoice_number: String,
+    pub tenant_id: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub period_start: time::OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub period_end: time::OffsetDateTime,
+    /// `None` for a draft
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub finalized_at: Option<time::OffsetDateTime>,
+    pub totals: InvoiceTotals,
+    pub line_items: Vec<InvoiceLine>,
+}
+
+/// Invoice number derived from tenant and period only, so a draft and its
+/// finalized invoice share it: `INV-<start>-<end>-<tenant hash>`
+pub fn invoice_number(tenant_id: &str, start: time::OffsetDateTime, end: time::OffsetDateTime) -> String {
+    let date = |t: time::OffsetDateTime| {
+        let d = t.to_offset(time::UtcOffset::UTC).date();
+        format!("{:04}{:02}{:02}", d.year(), u8::from(d.month()), d.day())
+    };
+    // The whole hash: two tenants sharing a number could not both be invoiced
+    let hash = Sha256::digest(tenant_id.as_bytes());
+    format!(
+        "INV-{}-{}-{}",
+        date(start),
+        date(end),
+        hash.iter().map(|b| format!("{:02X}", b)).collect::<String>()
+    )
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+impl Invoice {
+    pub fn draft(
+        tenant_id: &str,
+        period_start: time::OffsetDateTime,
+        period_end: time::OffsetDateTime,
+        line_items: Vec<InvoiceLine>,
+    ) -> Self {
+        Self {
+            invoice_number: invoice_number(tenant_id, period_start, period_end),
+            tenant_id: tenant_id.to_string(),
+            period_start,
+            period_end,
+            finalized_at: None,
+            totals: InvoiceTotals::of(&line_items),
+            line_items,
+        }
+    }
+
+    /// One row per line item followed by a `total` row
+    pub fn to_csv(&self) -> String {
+        let rfc3339 = |t: time::OffsetDateTime| {
+            t.format(&time::format_description::well_known::Rfc3339)
+                .unwrap_or_default()
+        };
+        let prefix = [
+            csv_field(&self.invoice_number),
+            csv_field(&self.tenant_id),
+            rfc3339(self.period_start),
+            rfc3339(self.period_end),
+        ]
+        .join(",");
+
+        let mut out = String::from(
+            "invoice_number,tenant_id,period_start,period_end,api_key_id,logical_model,\
+             requests,prompt_tokens,completion_tokens,total_tokens,upstream_cost,billed_cost\n",
+        );
+        for line in &self.line_items {
+            out.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{}\n",
+                prefix,
+                line.api_key_id,
+                csv_field(&line.logical_model),
+                line.requests,
+                line.prompt_tokens,
+                line.completion_tokens,
+                line.total_tokens,
+                line.upstream_cost,
+                line.billed_cost
+            ));
+        }
+        let prompt_tokens: i64 = self.line_items.iter().map(|l| l.prompt_tokens).sum();
+        let completion_tokens: i64 = self.line_items.iter().map(|l| l.completion_tokens).sum();
+        out.push_str(&format!(
+            "{},,total,{},{},{},{},{},{}\n",
+            prefix,
+            self.totals.requests,
+            prompt_tokens,
+            completion_tokens,
+            self.totals.total_tokens,
+            self.totals.upstream_cost,
+            self.totals.billed_cost
+        ));
+        out
+    }
+}
+
+/// The finalized invoice of the period if there is one, else a draft from
+/// the transactions recorded so far
+pub async fn invoice(
+    store: &dyn InvoiceStore,
+    tenant_id: &str,
+    start: time::OffsetDateTime,
+    end: time::OffsetDateTime,
+) -> Result<Invoice, sqlx::Error> {
+    if let Some(invoice) = store.finalized_invoice(tenant_id, start, end).await? {
+        return Ok(invoice);
+    }
+    let lines = store.invoice_lines(tenant_id, start, end).await?;
+    Ok(Invoice::draft(tenant_id, start, end, lines))
+}
+
+/// Freeze the invoice of a period that has ended
+pub async fn finalize(
+    store: &dyn InvoiceStore,
+    tenant_id: &str,
+    start: time::OffsetDateTime,
+    end: time::OffsetDateTime,
+) -> anyhow::Result<Invoice> {
+    if start >= end {
+        anyhow::bail!("invoice period must end after it starts");
+    }
+    // Transactions of the last moments of the period may still be committing
+    if end + ROLLUP_LAG > time::OffsetDateTime::now_utc() {
+        anyhow::bail!("invoice period has not ended yet");
+    }
+    let draft = invoice(store, tenant_id, start, end).await?;
+    if draft.finalized_at.is_some() {
+        return Ok(draft);
+    }
+    store.finalize(&draft).await.map_err(|e| match &e {
+        sqlx::Error::Database(db) if db.constraint() == Some("invoices_invoice_number_key") => {
+            anyhow::anyhow!("invoice number {} is already used by another invoice", draft.invoice_number)
+        }
+        sqlx::Error::Database(db) if db.constraint() == Some("invoices_no_overlapping_periods") => {
+            anyhow::anyhow!("invoice period overlaps another finalized invoice of tenant {}", tenant_id)
+        }
+        _ => e.into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(year: i32, month: time::Month, day: u8) -> time::OffsetDateTime {
+        time::Date::from_calendar_date(year, month, day)
+            .unwrap()
+            .midnight()
+            .assume_utc()
+    }
+
+    #[test]
+    fn test_invoice_number_is_stable() {
+        let start = utc(2025, time::Month::June, 1);
+        let end = utc(2025, time::Month::July, 1);
+        let number = invoice_number("acme", start, end);
+        assert!(number.starts_with("INV-20250601-20250701-"));
+        assert_eq!(number.len(), "INV-20250601-20250701-".len() + 64);
+        assert_eq!(number, invoice_number("acme", start, end));
+        assert_ne!(number, invoice_number("acme-eu", start, end));
+    }
+
+    #[test]
+    fn test_totals_and_csv() {
+        let line = |model: &str, requests, cost| InvoiceLine {
+            api_key_id: Uuid::nil(),
+            logical_model: model.to_string(),
+            requests,
+            prompt_tokens: requests * 10,
+            completion_tokens: requests * 5,
+            total_tokens: requests * 15,
+            upstream_cost: cost,
+            billed_cost: cost,
+        };
+        let invoice = Invoice::draft(
+            "acme",
+            utc(2025, time::Month::June, 1),
+            utc(2025, time::Month::July, 1),
+            vec![
+                line("claude-sonnet-4.5", 2, Decimal::new(12, 3)),
+                line("gpt,4o", 1, Decimal::new(3, 3)),
+            ],
+        );
+        assert_eq!(invoice.totals.requests, 3);
+        assert_eq!(invoice.totals.billed_cost, Decimal::new(15, 3));
+
+        let csv = invoice.to_csv();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows.len(), 4);
+        assert!(rows[2].contains(",\"gpt,4o\",1,10,5,15,0.003,0.003"));
+        assert!(rows[3].ends_with(",,total,3,30,15,45,0.015,0.015"));
+        assert!(rows[3].contains(",2025-06-01T00:00:00Z,2025-07-01T00:00:00Z,"));
+    }
+}
@@ -1,11 +1,23 @@
+pub mod money;
 pub mod price;
 pub mod tokens;
 pub mod calc;
 pub mod usage;
 pub mod interceptor;
+pub mod stream;
+pub mod rates;
+pub mod budget;
+pub mod rollup;
+pub mod invoice;
+pub mod tpm;
 
-pub use price::{PricingCache, ModelPricing};
+pub use money::round_money;
+pub use price::{ModelPricing, PriceQuery, PricingChain};
 pub use tokens::{TokenCounter, TokenUsage, GptTokenCounter, ClaudeTokenCounter};
 pub use calc::{CostBreakdown, CostCalculator};
 pub use usage::{OrUsage, UsageFields};
-pub use interceptor::{BillingInterceptor, BillingContext, BillingTransaction};
+pub use interceptor::{BillingInterceptor, BillingContext, BillingTransaction, RouteQuote};
+pub use rates::RateCard;
+pub use budget::{AlertingBillingStore, BudgetMonitor, Notifiers};
+pub use rollup::SummaryPeriod;
+pub use tpm::TpmLimits;
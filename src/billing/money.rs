@@ -0,0 +1,37 @@
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::str::FromStr;
+
+/// Decimal places of every stored amount, matching the `DECIMAL(12, 8)` columns
+pub const MONEY_SCALE: u32 = 8;
+
+/// The single rounding rule for money: amounts are rounded to `MONEY_SCALE`
+/// places, half to even, as soon as they are computed. Totals are sums of
+/// already rounded amounts, so they never need rounding themselves and equal
+/// whatever Postgres sums from the stored rows.
+pub fn round_money(amount: Decimal) -> Decimal {
+    amount
+        .round_dp_with_strategy(MONEY_SCALE, RoundingStrategy::MidpointNearestEven)
+        .normalize()
+}
+
+/// Parse a provider price string, plain (`"0.0000003"`) or scientific (`"3e-7"`).
+/// Prices are per token and are kept at full precision, not rounded.
+pub fn parse_price(s: &str) -> Option<Decimal> {
+    Decimal::from_str(s)
+        .or_else(|_| Decimal::from_scientific(s))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rounding_and_parsing() {
+        assert_eq!(round_money(Decimal::new(125, 9)), Decimal::new(12, 8));
+        assert_eq!(round_money(Decimal::new(135, 9)), Decimal::new(14, 8));
+        assert_eq!(parse_price("0.0000003"), Some(Decimal::new(3, 7)));
+        assert_eq!(parse_price("3e-7"), Some(Decimal::new(3, 7)));
+        assert_eq!(parse_price("free"), None);
+    }
+}
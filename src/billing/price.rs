@@ -1,8 +1,11 @@
-use std::{collections::HashMap, time::{Duration, Instant}};
+use std::{collections::HashMap, path::Path, time::{Duration, Instant}};
+use rust_decimal::Decimal;
 use serde::Deserialize;
 use tokio::sync::RwLock;
 use reqwest::Client;
 
+use crate::billing::money::parse_price;
+
 #[derive(Clone, Debug, Default, Deserialize)]
 pub struct Pricing {
     #[serde(default)]
@@ -23,22 +26,25 @@ pub struct Pricing {
     pub input_cache_write: Option<String>,
 }
 
-#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+/// USD per token (per request for `request`, per image for `image`), exact.
+/// Serialized as strings so JSON snapshots keep every digit.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ModelPricing {
-    pub prompt: f64,
-    pub completion: f64,
-    pub request: f64,
-    pub image: f64,
-    pub web_search: f64,
-    pub internal_reasoning: f64,
-    pub input_cache_read: f64,
-    pub input_cache_write: f64,
+    pub prompt: Decimal,
+    pub completion: Decimal,
+    pub request: Decimal,
+    pub image: Decimal,
+    pub web_search: Decimal,
+    pub internal_reasoning: Decimal,
+    pub input_cache_read: Decimal,
+    pub input_cache_write: Decimal,
 }
 
 impl From<Pricing> for ModelPricing {
     fn from(p: Pricing) -> Self {
-        fn parse(s: &Option<String>) -> f64 {
-            s.as_ref().and_then(|x| x.parse::<f64>().ok()).unwrap_or(0.0)
+        fn parse(s: &Option<String>) -> Decimal {
+            s.as_deref().and_then(parse_price).unwrap_or_default()
         }
         Self {
             prompt: parse(&p.prompt),
@@ -65,13 +71,84 @@ struct ModelsResponse {
     data: Vec<ModelEntry>,
 }
 
-pub struct PricingCache {
+/// What a pricing source is asked to price
+pub struct PriceQuery<'a> {
+    pub provider_model_id: &'a str,
+    /// `price` configured on the serving route, if any
+    pub route_price: Option<&'a ModelPricing>,
+}
+
+impl<'a> PriceQuery<'a> {
+    pub fn model(provider_model_id: &'a str) -> Self {
+        Self { provider_model_id, route_price: None }
+    }
+}
+
+/// A place prices come from
+#[async_trait::async_trait]
+pub trait PricingSource: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// `Ok(None)` when this source has no price for the model
+    async fn price(&self, query: &PriceQuery<'_>) -> anyhow::Result<Option<ModelPricing>>;
+}
+
+/// Price set on the route in `xjp.toml`
+pub struct RoutePriceOverride;
+
+#[async_trait::async_trait]
+impl PricingSource for RoutePriceOverride {
+    fn name(&self) -> &'static str {
+        "route"
+    }
+
+    async fn price(&self, query: &PriceQuery<'_>) -> anyhow::Result<Option<ModelPricing>> {
+        Ok(query.route_price.cloned())
+    }
+}
+
+/// Fixed price table keyed by provider_model_id, loaded from TOML or JSON
+pub struct StaticPriceTable {
+    prices: HashMap<String, ModelPricing>,
+}
+
+impl StaticPriceTable {
+    pub fn new(prices: HashMap<String, ModelPricing>) -> Self {
+        Self { prices }
+    }
+
+    /// Load a table file; `.json` files are parsed as JSON, anything else as TOML
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("read price table {}: {}", path.display(), e))?;
+        let prices = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&text)?
+        } else {
+            toml::from_str(&text)?
+        };
+        Ok(Self::new(prices))
+    }
+}
+
+#[async_trait::async_trait]
+impl PricingSource for StaticPriceTable {
+    fn name(&self) -> &'static str {
+        "static"
+    }
+
+    async fn price(&self, query: &PriceQuery<'_>) -> anyhow::Result<Option<ModelPricing>> {
+        Ok(self.prices.get(query.provider_model_id).cloned())
+    }
+}
+
+/// Prices from OpenRouter's models endpoint, cached for 15 minutes
+pub struct OpenRouterPricing {
     client: Client,
     cache: RwLock<HashMap<String, (ModelPricing, Instant)>>,
     ttl: Duration,
 }
 
-impl PricingCache {
+impl OpenRouterPricing {
     pub fn new() -> anyhow::Result<Self> {
         Ok(Self {
             client: Client::builder().timeout(Duration::from_secs(30)).build()?,
@@ -80,12 +157,12 @@ impl PricingCache {
         })
     }
 
-    pub async fn get(&self, model_id: &str) -> anyhow::Result<ModelPricing> {
+    async fn get(&self, model_id: &str) -> anyhow::Result<Option<ModelPricing>> {
         {
             let map = self.cache.read().await;
             if let Some((mp, ts)) = map.get(model_id) {
                 if ts.elapsed() < self.ttl {
-                    return Ok(mp.clone());
+                    return Ok(Some(mp.clone()));
                 }
             }
         }
@@ -106,10 +183,106 @@ impl PricingCache {
                 map.insert(m.id.clone(), (ModelPricing::from(p), Instant::now()));
             }
         }
-        if let Some((mp, _)) = map.get(model_id) {
-            Ok(mp.clone())
+        Ok(map.get(model_id).map(|(mp, _)| mp.clone()))
+    }
+}
+
+#[async_trait::async_trait]
+impl PricingSource for OpenRouterPricing {
+    fn name(&self) -> &'static str {
+        "openrouter"
+    }
+
+    async fn price(&self, query: &PriceQuery<'_>) -> anyhow::Result<Option<ModelPricing>> {
+        self.get(query.provider_model_id).await
+    }
+}
+
+/// Pricing sources asked in priority order; the first price found wins
+pub struct PricingChain {
+    sources: Vec<Box<dyn PricingSource>>,
+}
+
+impl PricingChain {
+    pub fn new(sources: Vec<Box<dyn PricingSource>>) -> Self {
+        Self { sources }
+    }
+
+    /// Route overrides, then the table in `XJP_PRICING_FILE` if set, then OpenRouter
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut sources: Vec<Box<dyn PricingSource>> = vec![Box::new(RoutePriceOverride)];
+        if let Ok(path) = std::env::var("XJP_PRICING_FILE") {
+            let table = StaticPriceTable::load(Path::new(&path))?;
+            tracing::info!("Loaded {} prices from {}", table.prices.len(), path);
+            sources.push(Box::new(table));
+        }
+        sources.push(Box::new(OpenRouterPricing::new()?));
+        Ok(Self::new(sources))
+    }
+
+    pub async fn get(&self, query: &PriceQuery<'_>) -> anyhow::Result<ModelPricing> {
+        let mut errors = Vec::new();
+        for source in &self.sources {
+            match source.price(query).await {
+                Ok(Some(price)) => {
+                    tracing::debug!("Priced {} from {}", query.provider_model_id, source.name());
+                    return Ok(price);
+                }
+                Ok(None) => {}
+                Err(e) => errors.push(format!("{}: {}", source.name(), e)),
+            }
+        }
+        if errors.is_empty() {
+            Err(anyhow::anyhow!("pricing not found for model {}", query.provider_model_id))
         } else {
-            Err(anyhow::anyhow!("pricing not found for model {}", model_id))
+            Err(anyhow::anyhow!(
+                "pricing not found for model {} ({})",
+                query.provider_model_id,
+                errors.join("; ")
+            ))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(prompt: Decimal) -> ModelPricing {
+        ModelPricing { prompt, ..Default::default() }
+    }
+
+    #[tokio::test]
+    async fn test_chain_priority() {
+        let table: HashMap<String, ModelPricing> = toml::from_str(
+            r#"
+["publishers/google/models/gemini-1.5-pro-002"]
+prompt = 0.00000125
+completion = 0.000005
+"#,
+        )
+        .unwrap();
+        let chain = PricingChain::new(vec![
+            Box::new(RoutePriceOverride),
+            Box::new(StaticPriceTable::new(table)),
+        ]);
+        let gemini = "publishers/google/models/gemini-1.5-pro-002";
+
+        let p = chain.get(&PriceQuery::model(gemini)).await.unwrap();
+        assert_eq!(p.prompt, Decimal::new(125, 8));
+        assert_eq!(p.completion, Decimal::new(5, 6));
+
+        let route_price = price(Decimal::new(1, 6));
+        let query = PriceQuery { provider_model_id: gemini, route_price: Some(&route_price) };
+        assert_eq!(chain.get(&query).await.unwrap(), route_price);
+
+        assert!(chain.get(&PriceQuery::model("gpt-4o-like")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_example_table_loads() {
+        let table = StaticPriceTable::load(Path::new("config/prices.example.toml")).unwrap();
+        let p = table.price(&PriceQuery::model("gpt-4o-like")).await.unwrap().unwrap();
+        assert_eq!(p.completion, Decimal::new(1, 5));
+    }
+}
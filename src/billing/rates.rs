@@ -0,0 +1,185 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::billing::{round_money, CostBreakdown, CostCalculator, ModelPricing, TokenUsage};
+
+/// Resale terms for a tenant, or for one of its API keys
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RateCard {
+    pub id: Uuid,
+    pub tenant_id: String,
+    /// Card applies to this key only; `None` for the tenant-wide card
+    pub api_key_id: Option<Uuid>,
+    pub version: i32,
+    /// Added on top of upstream cost, e.g. 20.0 bills at 120%
+    pub markup_percent: Decimal,
+    /// Fixed USD fee per successful request
+    pub request_fee: Decimal,
+    /// Prices keyed by logical model or provider_model_id; a match replaces
+    /// upstream cost and markup for that model
+    pub model_prices: HashMap<String, ModelPricing>,
+    /// Tokens per calendar month (UTC) that are not billed
+    pub free_tokens_per_month: i64,
+}
+
+/// What the tenant is charged for one request
+#[derive(Clone, Debug, PartialEq)]
+pub struct BilledCost {
+    pub billed_cost: Decimal,
+    /// Tokens of this request covered by the free allowance
+    pub free_tokens: u64,
+}
+
+/// Request being billed under a rate card
+pub struct RatedRequest<'a> {
+    pub logical_model: &'a str,
+    pub provider_model_id: &'a str,
+    pub usage: &'a TokenUsage,
+    pub upstream: &'a CostBreakdown,
+    pub success: bool,
+    /// Free tokens of the month not yet used before this request
+    pub free_tokens_left: u64,
+}
+
+impl RateCard {
+    /// Billed cost: the model override price or upstream cost plus markup,
+    /// scaled down by the share of tokens the free allowance covers, plus
+    /// the request fee
+    pub fn apply(&self, req: &RatedRequest<'_>) -> BilledCost {
+        let base = match self
+            .model_prices
+            .get(req.logical_model)
+            .or_else(|| self.model_prices.get(req.provider_model_id))
+        {
+            Some(price) => CostCalculator::compute(req.usage, price).total_cost,
+            None => req.upstream.total_cost * (Decimal::ONE + self.markup_percent / Decimal::ONE_HUNDRED),
+        };
+
+        let total_tokens = req.usage.prompt_tokens + req.usage.completion_tokens;
+        let free_tokens = total_tokens.min(req.free_tokens_left);
+        let base = if total_tokens > 0 {
+            base * Decimal::from(total_tokens - free_tokens) / Decimal::from(total_tokens)
+        } else {
+            base
+        };
+
+        let fee = if req.success { self.request_fee } else { Decimal::ZERO };
+        BilledCost {
+            billed_cost: round_money(base + fee).max(Decimal::ZERO),
+            free_tokens,
+        }
+    }
+
+    /// Terms recorded next to the model price in `pricing_snapshot`
+    pub fn snapshot(&self, billed: &BilledCost) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "version": self.version,
+            "api_key_id": self.api_key_id,
+            "markup_percent": self.markup_percent,
+            "request_fee": self.request_fee,
+            "free_tokens": billed.free_tokens,
+        })
+    }
+}
+
+/// Start of the calendar month (UTC) containing `t`; free tokens reset here
+pub fn month_start(t: time::OffsetDateTime) -> time::OffsetDateTime {
+    let t = t.to_offset(time::UtcOffset::UTC);
+    t.replace_date(t.date().replace_day(1).expect("day 1 exists in every month"))
+        .replace_time(time::Time::MIDNIGHT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn card() -> RateCard {
+        RateCard {
+            id: Uuid::nil(),
+            tenant_id: "acme".into(),
+            api_key_id: None,
+            version: 3,
+            markup_percent: Decimal::from(25),
+            request_fee: Decimal::new(1, 3),
+            model_prices: HashMap::new(),
+            free_tokens_per_month: 0,
+        }
+    }
+
+    fn usage(prompt_tokens: u64, completion_tokens: u64) -> TokenUsage {
+        TokenUsage {
+            prompt_tokens,
+            completion_tokens,
+            ..Default::default()
+        }
+    }
+
+    fn rated<'a>(
+        usage: &'a TokenUsage,
+        upstream: &'a CostBreakdown,
+        free_tokens_left: u64,
+    ) -> RatedRequest<'a> {
+        RatedRequest {
+            logical_model: "claude-sonnet-4.5",
+            provider_model_id: "anthropic/claude-3.5-sonnet",
+            usage,
+            upstream,
+            success: true,
+            free_tokens_left,
+        }
+    }
+
+    #[test]
+    fn test_markup_and_fee() {
+        let usage = usage(1000, 500);
+        let price = ModelPricing {
+            prompt: Decimal::new(3, 6),
+            completion: Decimal::new(15, 6),
+            ..Default::default()
+        };
+        let upstream = CostCalculator::compute(&usage, &price);
+        assert_eq!(upstream.total_cost, Decimal::new(105, 4));
+        let billed = card().apply(&rated(&usage, &upstream, 0));
+        assert_eq!(billed.billed_cost, Decimal::new(141250, 7));
+
+        let failed = RatedRequest {
+            success: false,
+            ..rated(&usage, &upstream, 0)
+        };
+        assert_eq!(card().apply(&failed).billed_cost, Decimal::new(131250, 7));
+    }
+
+    #[test]
+    fn test_model_override_and_free_tokens() {
+        let usage = usage(1000, 1000);
+        let upstream = CostBreakdown {
+            total_cost: Decimal::ONE,
+            ..Default::default()
+        };
+        let mut card = card();
+        card.request_fee = Decimal::ZERO;
+        card.model_prices.insert(
+            "claude-sonnet-4.5".into(),
+            ModelPricing {
+                prompt: Decimal::new(1, 5),
+                completion: Decimal::new(1, 5),
+                ..Default::default()
+            },
+        );
+
+        let billed = card.apply(&rated(&usage, &upstream, 0));
+        assert_eq!(billed.billed_cost, Decimal::new(2, 2));
+
+        // Half the tokens are free
+        let billed = card.apply(&rated(&usage, &upstream, 1000));
+        assert_eq!(billed.free_tokens, 1000);
+        assert_eq!(billed.billed_cost, Decimal::new(1, 2));
+
+        let billed = card.apply(&rated(&usage, &upstream, 5000));
+        assert_eq!(billed.free_tokens, 2000);
+        assert_eq!(billed.billed_cost, Decimal::ZERO);
+    }
+}
@@ -0,0 +1,180 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::billing::rates::month_start;
+use crate::db::BillingStore;
+
+/// Transactions recorded within this long of now may still be committing,
+/// so each run stops short of it
+pub(crate) const ROLLUP_LAG: time::Duration = time::Duration::minutes(1);
+
+/// Aggregation period of `tenant_billing_summary`; periods start at UTC
+/// midnight and weeks on Monday
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SummaryPeriod {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl SummaryPeriod {
+    pub const ALL: [SummaryPeriod; 3] = [SummaryPeriod::Daily, SummaryPeriod::Weekly, SummaryPeriod::Monthly];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SummaryPeriod::Daily => "daily",
+            SummaryPeriod::Weekly => "weekly",
+            SummaryPeriod::Monthly => "monthly",
+        }
+    }
+
+    /// Start (inclusive) and end (exclusive) of the period containing `t`
+    pub fn bounds(self, t: time::OffsetDateTime) -> (time::OffsetDateTime, time::OffsetDateTime) {
+        let day = t
+            .to_offset(time::UtcOffset::UTC)
+            .replace_time(time::Time::MIDNIGHT);
+        match self {
+            SummaryPeriod::Daily => (day, day + time::Duration::days(1)),
+            SummaryPeriod::Weekly => {
+                let start = day - time::Duration::days(day.weekday().number_days_from_monday().into());
+                (start, start + time::Duration::days(7))
+            }
+            SummaryPeriod::Monthly => {
+                let start = month_start(t);
+                let (year, month) = match start.month() {
+                    time::Month::December => (start.year() + 1, time::Month::January),
+                    month => (start.year(), month.next()),
+                };
+                let end = start.replace_date(
+                    time::Date::from_calendar_date(year, month, 1).expect("day 1 exists in every month"),
+                );
+                (start, end)
+            }
+        }
+    }
+
+    /// Periods overlapping `[start, end)`
+    pub fn periods_between(
+        self,
+        start: time::OffsetDateTime,
+        end: time::OffsetDateTime,
+    ) -> Vec<(time::OffsetDateTime, time::OffsetDateTime)> {
+        let mut periods = Vec::new();
+        let mut t = start;
+        while t < end {
+            let bounds = self.bounds(t);
+            periods.push(bounds);
+            t = bounds.1;
+        }
+        periods
+    }
+}
+
+/// Re-aggregate every period containing one of `days`, for all period types
+async fn rollup_days(
+    store: &dyn BillingStore,
+    days: &[time::OffsetDateTime],
+) -> Result<usize, sqlx::Error> {
+    let periods: BTreeSet<_> = SummaryPeriod::ALL
+        .iter()
+        .flat_map(|period| days.iter().map(move |day| (*period, period.bounds(*day))))
+        .collect();
+    for (period, (start, end)) in &periods {
+        store.rollup_period(*period, *start, *end).await?;
+    }
+    Ok(periods.len())
+}
+
+/// Aggregate transactions recorded since the last run. Late arrivals, e.g. a
+/// long stream finishing after midnight, re-aggregate the periods they belong
+/// to, so a period is always rebuilt from all of its transactions.
+pub async fn run_once(store: &dyn BillingStore) -> Result<usize, sqlx::Error> {
+    let until = time::OffsetDateTime::now_utc() - ROLLUP_LAG;
+    let since = store
+        .rollup_watermark()
+        .await?
+        .unwrap_or(time::OffsetDateTime::UNIX_EPOCH);
+    if since >= until {
+        return Ok(0);
+    }
+    let days = store.days_recorded_between(since, until).await?;
+    let rebuilt = rollup_days(store, &days).await?;
+    store.set_rollup_watermark(until).await?;
+    Ok(rebuilt)
+}
+
+/// Rebuild all periods overlapping `[start, end)`
+pub async fn backfill(
+    store: &dyn BillingStore,
+    start: time::OffsetDateTime,
+    end: time::OffsetDateTime,
+) -> Result<usize, sqlx::Error> {
+    let days: Vec<_> = SummaryPeriod::Daily
+        .periods_between(start, end)
+        .into_iter()
+        .map(|(day, _)| day)
+        .collect();
+    rollup_days(store, &days).await
+}
+
+/// Run the rollup every `interval`
+pub fn spawn(store: Arc<dyn BillingStore>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match run_once(store.as_ref()).await {
+                Ok(0) => {}
+                Ok(n) => tracing::debug!("Rebuilt {} billing summary periods", n),
+                Err(e) => tracing::error!("Billing summary rollup failed: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Month::{December, January};
+
+    fn utc(year: i32, month: time::Month, day: u8, hour: u8) -> time::OffsetDateTime {
+        time::Date::from_calendar_date(year, month, day)
+            .unwrap()
+            .with_hms(hour, 0, 0)
+            .unwrap()
+            .assume_utc()
+    }
+
+    #[test]
+    fn test_period_bounds() {
+        // Wednesday
+        let t = utc(2025, December, 31, 18);
+        assert_eq!(
+            SummaryPeriod::Daily.bounds(t),
+            (utc(2025, December, 31, 0), utc(2026, January, 1, 0))
+        );
+        assert_eq!(
+            SummaryPeriod::Weekly.bounds(t),
+            (utc(2025, December, 29, 0), utc(2026, January, 5, 0))
+        );
+        assert_eq!(
+            SummaryPeriod::Monthly.bounds(t),
+            (utc(2025, December, 1, 0), utc(2026, January, 1, 0))
+        );
+    }
+
+    #[test]
+    fn test_periods_between() {
+        let weeks = SummaryPeriod::Weekly.periods_between(utc(2025, December, 31, 0), utc(2026, January, 6, 0));
+        assert_eq!(
+            weeks.iter().map(|(start, _)| *start).collect::<Vec<_>>(),
+            vec![utc(2025, December, 29, 0), utc(2026, January, 5, 0)]
+        );
+        assert!(SummaryPeriod::Daily
+            .periods_between(utc(2026, January, 5, 0), utc(2026, January, 5, 0))
+            .is_empty());
+    }
+}
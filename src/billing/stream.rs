@@ -0,0 +1,179 @@
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use rust_decimal::Decimal;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::billing::interceptor::{completion_text, usage_from_provider_events};
+use crate::billing::{BillingContext, BillingInterceptor, TokenUsage};
+use crate::connectors::ConnectorError;
+use crate::core::entities::UnifiedChunk;
+use crate::db::BillingStore;
+
+/// Transaction status for a failed request
+pub fn error_status(e: &ConnectorError) -> &'static str {
+    match e {
+        ConnectorError::Timeout => "timeout",
+        _ => "error",
+    }
+}
+
+/// Price and store a transaction in the background
+pub fn record(
+    interceptor: Arc<BillingInterceptor>,
+    store: Arc<dyn BillingStore>,
+    mut ctx: BillingContext,
+    usage: Option<TokenUsage>,
+    completion_text: String,
+    status: &'static str,
+    error_message: Option<String>,
+) {
+    tokio::spawn(async move {
+        let reservation = ctx.reservation.take();
+        let token_hold = ctx.token_hold.take();
+        match interceptor
+            .after_request(&ctx, usage.clone(), &completion_text, status, error_message)
+            .await
+        {
+            Ok(transaction) => {
+                let billed_cost = transaction.billed_cost;
+                interceptor.settle_tokens(token_hold, transaction.total_tokens.max(0) as u64);
+                if let Err(e) = store.insert_transaction(transaction).await {
+                    tracing::error!("Failed to insert billing transaction: {}", e);
+                }
+                interceptor.settle(reservation, billed_cost).await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to price request {}: {}", ctx.request_id, e);
+                // Without a price the credit held up front is charged; the
+                // tokens are still known, reported or estimated locally
+                let charged = reservation.as_ref().map_or(Decimal::ZERO, |r| r.amount);
+                let transaction = interceptor
+                    .unpriced_transaction(&ctx, usage, &completion_text, charged, &e)
+                    .await;
+                interceptor.settle_tokens(token_hold, transaction.total_tokens.max(0) as u64);
+                if let Err(e) = store.insert_transaction(transaction).await {
+                    tracing::error!("Failed to insert billing transaction: {}", e);
+                }
+                interceptor.settle(reservation, charged).await;
+            }
+        }
+    });
+}
+
+/// Usage and timing collected while a stream is consumed
+struct StreamBilling {
+    ctx: Option<BillingContext>,
+    interceptor: Arc<BillingInterceptor>,
+    store: Arc<dyn BillingStore>,
+    usage: Option<TokenUsage>,
+    /// Output so far, for a local estimate if the provider never reports usage
+    completion_text: String,
+    first_token: Option<Duration>,
+    /// Upstream sent its final chunk
+    done: bool,
+    /// Upstream stream ended, with or without a final chunk
+    ended: bool,
+    error: Option<(&'static str, String)>,
+}
+
+impl StreamBilling {
+    fn observe(&mut self, item: &Result<UnifiedChunk, ConnectorError>) {
+        let chunk = match item {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                if self.error.is_none() {
+                    self.error = Some((error_status(e), e.to_string()));
+                }
+                return;
+            }
+        };
+        let has_content = chunk.text_delta.as_deref().is_some_and(|t| !t.is_empty())
+            || !chunk.tool_calls.is_empty();
+        if has_content && self.first_token.is_none() {
+            self.first_token = self.ctx.as_ref().map(|ctx| ctx.start_time.elapsed());
+        }
+        if let Some(events) = &chunk.provider_events {
+            match usage_from_provider_events(events) {
+                Ok(Some(usage)) => self.usage = Some(usage),
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Unparseable usage in stream chunk: {}", e),
+            }
+        }
+        self.completion_text.push_str(&completion_text(chunk));
+        self.done |= chunk.done;
+    }
+}
+
+impl Drop for StreamBilling {
+    /// Runs when the response body is dropped: after the stream finished, or
+    /// early when the client disconnected
+    fn drop(&mut self) {
+        let Some(mut ctx) = self.ctx.take() else {
+            return;
+        };
+        ctx.response_time = Some(ctx.start_time.elapsed());
+        ctx.time_to_first_token = self.first_token;
+        let (status, error_message) = match self.error.take() {
+            Some((status, message)) => (status, Some(message)),
+            None if self.done || self.ended => ("success", None),
+            None => (
+                "cancelled",
+                Some("client disconnected before the stream finished".to_string()),
+            ),
+        };
+        if tokio::runtime::Handle::try_current().is_err() {
+            tracing::error!("No runtime to record billing for request {}", ctx.request_id);
+            return;
+        }
+        record(
+            self.interceptor.clone(),
+            self.store.clone(),
+            ctx,
+            self.usage.take(),
+            std::mem::take(&mut self.completion_text),
+            status,
+            error_message,
+        );
+    }
+}
+
+/// Wrap a streamed response so its transaction is written once the stream
+/// ends, with the usage reported in the final chunks
+pub fn bill_stream(
+    stream: BoxStream<'static, Result<UnifiedChunk, ConnectorError>>,
+    ctx: BillingContext,
+    interceptor: Arc<BillingInterceptor>,
+    store: Arc<dyn BillingStore>,
+) -> BoxStream<'static, Result<UnifiedChunk, ConnectorError>> {
+    let mut billing = StreamBilling {
+        ctx: Some(ctx),
+        interceptor,
+        store,
+        usage: None,
+        completion_text: String::new(),
+        first_token: None,
+        done: false,
+        ended: false,
+        error: None,
+    };
+    // A trailing `None` marks the natural end of the upstream stream
+    Box::pin(
+        stream
+            .map(Some)
+            .chain(futures_util::stream::once(async { None }))
+            .filter_map(move |item| {
+                let out = match item {
+                    Some(item) => {
+                        billing.observe(&item);
+                        Some(item)
+                    }
+                    None => {
+                        billing.ended = true;
+                        None
+                    }
+                };
+                futures_util::future::ready(out)
+            }),
+    )
+}
@@ -11,31 +11,49 @@ pub struct TokenUsage {
     pub cached_prompt_tokens: u64,
 }
 
+/// Text a tokenizer sees for a prompt: text parts and tool call arguments
+pub fn prompt_text(messages: &[UnifiedMessage]) -> String {
+    let mut text = String::new();
+    for m in messages {
+        for p in &m.content {
+            if let ContentPart::Text { text: t } = p {
+                text.push_str(t);
+                text.push('\n');
+            }
+        }
+        for call in &m.tool_calls {
+            text.push_str(&call.name);
+            text.push_str(&call.arguments);
+            text.push('\n');
+        }
+    }
+    text
+}
+
 #[async_trait::async_trait]
 pub trait TokenCounter: Send + Sync {
-    async fn count_prompt(&self, model_tokenizer: &str, messages: &[UnifiedMessage]) -> anyhow::Result<u64>;
+    async fn count_text(&self, model_tokenizer: &str, text: &str) -> anyhow::Result<u64>;
+}
+
+/// Counter for a route tokenizer name (`claude`, `o200k_base`, `cl100k_base`)
+pub fn counter_for(model_tokenizer: &str) -> &'static dyn TokenCounter {
+    match model_tokenizer {
+        "claude" => &ClaudeTokenCounter,
+        _ => &GptTokenCounter,
+    }
 }
 
 pub struct GptTokenCounter;
 
 #[async_trait::async_trait]
 impl TokenCounter for GptTokenCounter {
-    async fn count_prompt(&self, model_tokenizer: &str, messages: &[UnifiedMessage]) -> anyhow::Result<u64> {
-        use tiktoken_rs::{o200k_base, cl100k_base, CoreBPE};
-        let enc: CoreBPE = match model_tokenizer {
-            "o200k_base" | "gpt-4o" | "gpt-4.1" | "gpt-5" => o200k_base()?,
-            _ => cl100k_base()?,
+    async fn count_text(&self, model_tokenizer: &str, text: &str) -> anyhow::Result<u64> {
+        use tiktoken_rs::{cl100k_base_singleton, o200k_base_singleton};
+        let enc = match model_tokenizer {
+            "o200k_base" | "gpt-4o" | "gpt-4.1" | "gpt-5" => o200k_base_singleton(),
+            _ => cl100k_base_singleton(),
         };
-        let mut text = String::new();
-        for m in messages {
-            for p in &m.content {
-                if let ContentPart::Text { text: t } = p {
-                    text.push_str(t);
-                    text.push('\n');
-                }
-            }
-        }
-        let n = enc.encode_with_special_tokens(&text).len() as u64;
+        let n = enc.encode_with_special_tokens(text).len() as u64;
         Ok(n)
     }
 }
@@ -44,17 +62,8 @@ pub struct ClaudeTokenCounter;
 
 #[async_trait::async_trait]
 impl TokenCounter for ClaudeTokenCounter {
-    async fn count_prompt(&self, _model_tokenizer: &str, messages: &[UnifiedMessage]) -> anyhow::Result<u64> {
-        let mut text = String::new();
-        for m in messages {
-            for p in &m.content {
-                if let ContentPart::Text { text: t } = p {
-                    text.push_str(t);
-                    text.push('\n');
-                }
-            }
-        }
-        let n = claude_tokenizer::count_tokens(&text)? as u64;
+    async fn count_text(&self, _model_tokenizer: &str, text: &str) -> anyhow::Result<u64> {
+        let n = claude_tokenizer::count_tokens(text)? as u64;
         Ok(n)
     }
 }
@@ -0,0 +1,207 @@
+use dashmap::DashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Tokens-per-minute limits of a request's API key and tenant; `None` is unlimited
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TpmLimits {
+    pub api_key: Option<u64>,
+    pub tenant: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Scope {
+    ApiKey(Uuid),
+    Tenant(String),
+}
+
+/// Holds up to a minute's worth of tokens and refills continuously. The
+/// balance goes negative when a request used more than it was charged, which
+/// delays the next requests until the bucket has recovered.
+#[derive(Debug)]
+struct Bucket {
+    per_minute: u64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(per_minute: u64, now: Instant) -> Self {
+        Self {
+            per_minute,
+            tokens: per_minute as f64,
+            updated: now,
+        }
+    }
+
+    /// Add the tokens accrued since the last update, picking up limit changes
+    fn refill(&mut self, per_minute: u64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.per_minute = per_minute;
+        self.tokens = (self.tokens + elapsed * per_minute as f64 / 60.0).min(per_minute as f64);
+        self.updated = now;
+    }
+
+    /// Time until `tokens` can be charged. A request larger than the whole
+    /// bucket waits for a full bucket instead of never running.
+    fn wait_for(&self, tokens: u64) -> Duration {
+        if self.per_minute == 0 {
+            return Duration::from_secs(60);
+        }
+        let missing = tokens.min(self.per_minute) as f64 - self.tokens;
+        if missing <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(missing * 60.0 / self.per_minute as f64)
+        }
+    }
+
+    fn charge(&mut self, tokens: f64) {
+        self.tokens = (self.tokens - tokens).min(self.per_minute as f64);
+    }
+}
+
+/// Tokens charged to a request up front, settled against its actual usage
+#[derive(Clone, Debug)]
+pub struct TokenHold {
+    scopes: Vec<Scope>,
+    tokens: u64,
+}
+
+/// In-memory token buckets per API key and per tenant
+pub struct TpmLimiter {
+    buckets: DashMap<Scope, Arc<Mutex<Bucket>>>,
+}
+
+impl TpmLimiter {
+    pub fn new() -> Self {
+        Self {
+            buckets: DashMap::new(),
+        }
+    }
+
+    fn bucket(&self, scope: &Scope, per_minute: u64, now: Instant) -> Arc<Mutex<Bucket>> {
+        self.buckets
+            .entry(scope.clone())
+            .or_insert_with(|| Arc::new(Mutex::new(Bucket::new(per_minute, now))))
+            .clone()
+    }
+
+    /// Charge the estimated tokens of a request to its key's and tenant's
+    /// buckets. Nothing is charged when either cannot take them; the error is
+    /// the time until both can.
+    pub fn acquire(
+        &self,
+        tenant_id: &str,
+        api_key_id: Uuid,
+        limits: TpmLimits,
+        tokens: u64,
+    ) -> Result<Option<TokenHold>, Duration> {
+        let scopes: Vec<(Scope, u64)> = [
+            (Scope::ApiKey(api_key_id), limits.api_key),
+            (Scope::Tenant(tenant_id.to_string()), limits.tenant),
+        ]
+        .into_iter()
+        .filter_map(|(scope, limit)| Some((scope, limit?)))
+        .collect();
+        if scopes.is_empty() {
+            return Ok(None);
+        }
+
+        let now = Instant::now();
+        let buckets: Vec<_> = scopes
+            .iter()
+            .map(|(scope, limit)| self.bucket(scope, *limit, now))
+            .collect();
+        // Always key before tenant, so concurrent requests lock in the same order
+        let mut guards: Vec<_> = buckets
+            .iter()
+            .map(|b| b.lock().unwrap_or_else(|e| e.into_inner()))
+            .collect();
+        for (bucket, (_, limit)) in guards.iter_mut().zip(&scopes) {
+            bucket.refill(*limit, now);
+        }
+        let wait = guards
+            .iter()
+            .map(|bucket| bucket.wait_for(tokens))
+            .max()
+            .unwrap_or_default();
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        for bucket in guards.iter_mut() {
+            bucket.charge(tokens as f64);
+        }
+
+        Ok(Some(TokenHold {
+            scopes: scopes.into_iter().map(|(scope, _)| scope).collect(),
+            tokens,
+        }))
+    }
+
+    /// Correct a charge by the difference between actual and estimated tokens
+    pub fn settle(&self, hold: TokenHold, actual: u64) {
+        let delta = actual as f64 - hold.tokens as f64;
+        for scope in &hold.scopes {
+            let bucket = self.buckets.get(scope).map(|b| Arc::clone(&b));
+            if let Some(bucket) = bucket {
+                bucket.lock().unwrap_or_else(|e| e.into_inner()).charge(delta);
+            }
+        }
+    }
+
+    /// Return the charge of a request that never ran
+    pub fn release(&self, hold: TokenHold) {
+        self.settle(hold, 0);
+    }
+}
+
+impl Default for TpmLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_refills_over_a_minute() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(600, start);
+        bucket.charge(600.0);
+        assert_eq!(bucket.wait_for(60), Duration::from_secs(6));
+
+        bucket.refill(600, start + Duration::from_secs(3));
+        assert_eq!(bucket.tokens, 30.0);
+        // Oversized requests wait for a full bucket
+        assert_eq!(bucket.wait_for(10_000), Duration::from_secs(57));
+
+        bucket.refill(600, start + Duration::from_secs(600));
+        assert_eq!(bucket.tokens, 600.0);
+    }
+
+    #[test]
+    fn test_acquire_settle_and_release() {
+        let limiter = TpmLimiter::new();
+        let key = Uuid::new_v4();
+        let limits = TpmLimits {
+            api_key: Some(1_000),
+            tenant: Some(100_000),
+        };
+
+        assert!(limiter.acquire("acme", key, TpmLimits::default(), 1_000_000).unwrap().is_none());
+
+        let hold = limiter.acquire("acme", key, limits, 800).unwrap().unwrap();
+        let wait = limiter.acquire("acme", key, limits, 500).unwrap_err();
+        assert!(wait > Duration::from_secs(17) && wait <= Duration::from_secs(18));
+
+        // Used 300 tokens less than estimated
+        limiter.settle(hold, 500);
+        let hold = limiter.acquire("acme", key, limits, 500).unwrap().unwrap();
+        limiter.release(hold);
+        assert!(limiter.acquire("acme", key, limits, 500).is_ok());
+    }
+}
@@ -1,3 +1,4 @@
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
@@ -23,7 +24,7 @@ pub struct UsageFields {
     #[serde(default)]
     pub total_tokens: Option<u64>,
     #[serde(default)]
-    pub cost: Option<f64>,
+    pub cost: Option<Decimal>,
 }
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
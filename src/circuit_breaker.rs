@@ -0,0 +1,302 @@
+use dashmap::DashMap;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+use crate::connectors::ConnectorError;
+use crate::metrics::CIRCUIT_BREAKER_STATE;
+use crate::registry::{CircuitBreakerConfig, EgressRoute, ProviderKind};
+
+#[derive(Clone, Copy, Debug)]
+enum BreakerState {
+    Closed { consecutive_failures: u32 },
+    Open { until: Instant },
+    /// One probe request is allowed through; `probe_started` guards against a
+    /// probe whose caller went away without reporting back
+    HalfOpen { probe_started: Instant },
+}
+
+impl BreakerState {
+    fn name(&self) -> &'static str {
+        match self {
+            BreakerState::Closed { .. } => "closed",
+            BreakerState::Open { .. } => "open",
+            BreakerState::HalfOpen { .. } => "half_open",
+        }
+    }
+
+    /// Gauge value exported to Prometheus: 0 closed, 1 half-open, 2 open
+    fn gauge_value(&self) -> i64 {
+        match self {
+            BreakerState::Closed { .. } => 0,
+            BreakerState::HalfOpen { .. } => 1,
+            BreakerState::Open { .. } => 2,
+        }
+    }
+}
+
+/// Route id, provider and provider_model_id; the id alone identifies the route
+type BreakerKey = (String, ProviderKind, String);
+
+/// Snapshot of one breaker for the internal status endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct BreakerStatus {
+    pub route: String,
+    pub provider: String,
+    pub provider_model_id: String,
+    pub state: &'static str,
+    pub consecutive_failures: u32,
+    /// Milliseconds until an open breaker lets a probe through
+    pub retry_in_ms: Option<u64>,
+}
+
+/// Circuit breakers per route
+pub struct CircuitBreakers {
+    breakers: DashMap<BreakerKey, BreakerState>,
+}
+
+impl CircuitBreakers {
+    pub fn new() -> Self {
+        Self {
+            breakers: DashMap::new(),
+        }
+    }
+
+    fn key(route: &EgressRoute) -> BreakerKey {
+        (route.id.clone(), route.provider.clone(), route.provider_model_id.clone())
+    }
+
+    fn set(key: &BreakerKey, slot: &mut BreakerState, state: BreakerState) {
+        *slot = state;
+        CIRCUIT_BREAKER_STATE
+            .with_label_values(&[&key.0, &key.1.to_string(), &key.2])
+            .set(state.gauge_value());
+    }
+
+    /// Check whether a request may be sent to this route. An open breaker whose
+    /// cool-down has elapsed moves to half-open and admits a single probe.
+    pub fn try_acquire(&self, route: &EgressRoute, cfg: &CircuitBreakerConfig) -> bool {
+        if cfg.failure_threshold == 0 {
+            return true;
+        }
+        let key = Self::key(route);
+        let mut slot = self.breakers.entry(key.clone()).or_insert(BreakerState::Closed {
+            consecutive_failures: 0,
+        });
+        let now = Instant::now();
+        match *slot {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until } if now < until => false,
+            BreakerState::HalfOpen { probe_started }
+                if now.duration_since(probe_started) < Duration::from_millis(cfg.open_ms) =>
+            {
+                false
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => {
+                tracing::info!(
+                    "circuit breaker for route {} ({}/{}) half-open, sending probe",
+                    key.0,
+                    key.1,
+                    key.2
+                );
+                Self::set(&key, &mut slot, BreakerState::HalfOpen { probe_started: now });
+                true
+            }
+        }
+    }
+
+    /// Record a successful call, closing the breaker
+    pub fn record_success(&self, route: &EgressRoute) {
+        let key = Self::key(route);
+        if let Some(mut slot) = self.breakers.get_mut(&key) {
+            match *slot {
+                BreakerState::Closed {
+                    consecutive_failures: 0,
+                } => return,
+                BreakerState::Closed { .. } => {}
+                BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => {
+                    tracing::info!("circuit breaker for route {} ({}/{}) closed", key.0, key.1, key.2);
+                }
+            }
+            Self::set(
+                &key,
+                &mut slot,
+                BreakerState::Closed {
+                    consecutive_failures: 0,
+                },
+            );
+        }
+    }
+
+    /// Record a call that returned an error. Transient errors count as
+    /// failures; any other error (a 400, bad credentials) means the upstream
+    /// answered, so it counts as a success and a half-open probe never keeps
+    /// its slot.
+    pub fn record_error(&self, route: &EgressRoute, cfg: &CircuitBreakerConfig, err: &ConnectorError) {
+        if err.retry_class().is_some() {
+            self.record_failure(route, cfg);
+        } else {
+            self.record_success(route);
+        }
+    }
+
+    /// Record a failed call; trips the breaker after `failure_threshold`
+    /// consecutive failures, or immediately when a half-open probe fails
+    pub fn record_failure(&self, route: &EgressRoute, cfg: &CircuitBreakerConfig) {
+        if cfg.failure_threshold == 0 {
+            return;
+        }
+        let key = Self::key(route);
+        let mut slot = self.breakers.entry(key.clone()).or_insert(BreakerState::Closed {
+            consecutive_failures: 0,
+        });
+        let open = BreakerState::Open {
+            until: Instant::now() + Duration::from_millis(cfg.open_ms),
+        };
+        let next = match *slot {
+            BreakerState::Closed {
+                consecutive_failures,
+            } if consecutive_failures + 1 < cfg.failure_threshold => BreakerState::Closed {
+                consecutive_failures: consecutive_failures + 1,
+            },
+            BreakerState::Open { .. } => return,
+            _ => {
+                tracing::warn!(
+                    "circuit breaker for route {} ({}/{}) opened for {}ms",
+                    key.0,
+                    key.1,
+                    key.2,
+                    cfg.open_ms
+                );
+                open
+            }
+        };
+        Self::set(&key, &mut slot, next);
+    }
+
+    /// Current state of every breaker that has seen traffic
+    pub fn snapshot(&self) -> Vec<BreakerStatus> {
+        let now = Instant::now();
+        let mut out: Vec<BreakerStatus> = self
+            .breakers
+            .iter()
+            .map(|entry| {
+                let ((route, provider, model), state) = entry.pair();
+                BreakerStatus {
+                    route: route.clone(),
+                    provider: provider.to_string(),
+                    provider_model_id: model.clone(),
+                    state: state.name(),
+                    consecutive_failures: match state {
+                        BreakerState::Closed {
+                            consecutive_failures,
+                        } => *consecutive_failures,
+                        _ => 0,
+                    },
+                    retry_in_ms: match state {
+                        BreakerState::Open { until } => {
+                            Some(until.saturating_duration_since(now).as_millis() as u64)
+                        }
+                        _ => None,
+                    },
+                }
+            })
+            .collect();
+        out.sort_by(|a, b| {
+            (&a.provider, &a.provider_model_id, &a.route).cmp(&(&b.provider, &b.provider_model_id, &b.route))
+        });
+        out
+    }
+}
+
+impl Default for CircuitBreakers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route() -> EgressRoute {
+        EgressRoute {
+            id: String::new(),
+            provider: ProviderKind::Vertex,
+            provider_model_id: "gemini".into(),
+            region: None,
+            project: None,
+            extra: Default::default(),
+            timeouts_ms: None,
+            retry: None,
+            weight: 1,
+            capabilities: Default::default(),
+            tokenizer: None,
+            price: None,
+            max_inflight: None,
+            api_key_secret: None,
+        }
+    }
+
+    #[test]
+    fn test_trips_and_probes() {
+        let breakers = CircuitBreakers::new();
+        let cfg = CircuitBreakerConfig {
+            failure_threshold: 2,
+            open_ms: 20,
+        };
+        let r = route();
+
+        assert!(breakers.try_acquire(&r, &cfg));
+        breakers.record_failure(&r, &cfg);
+        assert!(breakers.try_acquire(&r, &cfg));
+        breakers.record_failure(&r, &cfg);
+        assert!(!breakers.try_acquire(&r, &cfg));
+
+        std::thread::sleep(Duration::from_millis(30));
+        // Exactly one probe is admitted while half-open
+        assert!(breakers.try_acquire(&r, &cfg));
+        assert!(!breakers.try_acquire(&r, &cfg));
+
+        breakers.record_success(&r);
+        assert!(breakers.try_acquire(&r, &cfg));
+        assert_eq!(breakers.snapshot()[0].state, "closed");
+    }
+
+    #[test]
+    fn test_failed_probe_reopens() {
+        let breakers = CircuitBreakers::new();
+        let cfg = CircuitBreakerConfig {
+            failure_threshold: 1,
+            open_ms: 20,
+        };
+        let r = route();
+
+        breakers.record_failure(&r, &cfg);
+        assert!(!breakers.try_acquire(&r, &cfg));
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breakers.try_acquire(&r, &cfg));
+        breakers.record_failure(&r, &cfg);
+        assert!(!breakers.try_acquire(&r, &cfg));
+        assert_eq!(breakers.snapshot()[0].state, "open");
+    }
+
+    #[test]
+    fn test_non_transient_probe_error_releases_the_probe() {
+        let breakers = CircuitBreakers::new();
+        let cfg = CircuitBreakerConfig {
+            failure_threshold: 1,
+            open_ms: 20,
+        };
+        let r = route();
+
+        breakers.record_failure(&r, &cfg);
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breakers.try_acquire(&r, &cfg));
+        breakers.record_error(&r, &cfg, &ConnectorError::Invalid("bad request".into()));
+        assert!(breakers.try_acquire(&r, &cfg));
+        assert_eq!(breakers.snapshot()[0].state, "closed");
+
+        breakers.record_error(&r, &cfg, &ConnectorError::Timeout);
+        assert_eq!(breakers.snapshot()[0].state, "open");
+    }
+}
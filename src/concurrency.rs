@@ -0,0 +1,606 @@
+use axum::body::{Body, Bytes, HttpBody};
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use crate::db::{self, KeyInfo};
+use crate::ratelimit::RateLimitError;
+
+const DEFAULT_MAX_QUEUED: usize = 1000;
+const DEFAULT_QUEUE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Requests in flight a request is limited by; `None` is unlimited
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConcurrencyLimits {
+    pub api_key: Option<u32>,
+    pub tenant: Option<u32>,
+    /// Tenant's share of the gateway while requests queue for it
+    pub weight: u32,
+}
+
+impl Default for ConcurrencyLimits {
+    fn default() -> Self {
+        Self {
+            api_key: None,
+            tenant: None,
+            weight: 1,
+        }
+    }
+}
+
+impl ConcurrencyLimits {
+    /// Limits of the key's and the tenant's quotas
+    pub fn from_quotas(key: &KeyInfo, quotas: &[db::Quota]) -> Self {
+        let quota = |api_key_id: Option<Uuid>| quotas.iter().find(|q| q.api_key_id == api_key_id);
+        let max_concurrent = |api_key_id| {
+            quota(api_key_id)
+                .and_then(|q| q.max_concurrent_requests)
+                .map(|limit| limit.max(0) as u32)
+        };
+        Self {
+            api_key: max_concurrent(Some(key.id)),
+            tenant: max_concurrent(None),
+            weight: quota(None)
+                .and_then(|q| q.scheduling_weight)
+                .map_or(1, |weight| weight.max(1) as u32),
+        }
+    }
+}
+
+/// Gateway-wide concurrency settings
+#[derive(Clone, Debug)]
+pub struct ConcurrencyConfig {
+    /// Requests in flight across all tenants; `None` is unlimited
+    pub max_concurrent: Option<usize>,
+    /// Requests waiting for a slot across all tenants
+    pub max_queued: usize,
+    pub queue_timeout: Duration,
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent: None,
+            max_queued: DEFAULT_MAX_QUEUED,
+            queue_timeout: DEFAULT_QUEUE_TIMEOUT,
+        }
+    }
+}
+
+impl ConcurrencyConfig {
+    /// `XJP_MAX_CONCURRENT_REQUESTS` (unset or 0 is unlimited),
+    /// `XJP_MAX_QUEUED_REQUESTS` and `XJP_QUEUE_TIMEOUT_MS`
+    pub fn from_env() -> anyhow::Result<Self> {
+        fn var<T: std::str::FromStr>(name: &str) -> anyhow::Result<Option<T>> {
+            match std::env::var(name) {
+                Ok(value) => value
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| anyhow::anyhow!("invalid {}: '{}'", name, value)),
+                Err(_) => Ok(None),
+            }
+        }
+
+        let defaults = Self::default();
+        Ok(Self {
+            max_concurrent: var::<usize>("XJP_MAX_CONCURRENT_REQUESTS")?.filter(|max| *max > 0),
+            max_queued: var("XJP_MAX_QUEUED_REQUESTS")?.unwrap_or(defaults.max_queued),
+            queue_timeout: var("XJP_QUEUE_TIMEOUT_MS")?
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.queue_timeout),
+        })
+    }
+}
+
+/// What a request holds or waits for
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Slot {
+    /// A place among the requests in flight of its key, tenant and the gateway
+    Request(ConcurrencyLimits),
+    /// A place among the requests in flight to an upstream route
+    Route { id: String, max_inflight: u32 },
+}
+
+struct Waiter {
+    id: u64,
+    api_key_id: Uuid,
+    slot: Slot,
+    granted: oneshot::Sender<()>,
+}
+
+#[derive(Default)]
+struct TenantState {
+    inflight: u32,
+    /// Scheduling weight of the tenant's latest request
+    weight: u32,
+    /// Virtual time of the tenant's next grant; each grant advances it by `1 / weight`
+    vtime: f64,
+    queue: VecDeque<Waiter>,
+}
+
+#[derive(Default)]
+struct State {
+    inflight: usize,
+    queued: usize,
+    /// Virtual time of the last grant; idle tenants resume from here so they
+    /// cannot bank credit while they send nothing
+    vclock: f64,
+    next_id: u64,
+    keys: HashMap<Uuid, u32>,
+    tenants: HashMap<String, TenantState>,
+    /// Requests in flight per route id
+    routes: HashMap<String, u32>,
+}
+
+impl State {
+    fn fits(&self, max_concurrent: Option<usize>, tenant_id: &str, api_key_id: Uuid, slot: &Slot) -> bool {
+        match slot {
+            Slot::Request(limits) => {
+                let tenant_inflight = self.tenants.get(tenant_id).map_or(0, |t| t.inflight);
+                let key_inflight = self.keys.get(&api_key_id).copied().unwrap_or(0);
+                max_concurrent.is_none_or(|max| self.inflight < max)
+                    && limits.tenant.is_none_or(|max| tenant_inflight < max)
+                    && limits.api_key.is_none_or(|max| key_inflight < max)
+            }
+            Slot::Route { id, max_inflight } => self.routes.get(id).copied().unwrap_or(0) < *max_inflight,
+        }
+    }
+
+    fn grant(&mut self, tenant_id: &str, api_key_id: Uuid, slot: &Slot) {
+        let vclock = self.vclock;
+        let tenant = self.tenants.entry(tenant_id.to_string()).or_default();
+        if let Slot::Request(limits) = slot {
+            tenant.weight = limits.weight;
+        }
+        let start = tenant.vtime.max(vclock);
+        tenant.vtime = start + 1.0 / f64::from(tenant.weight.max(1));
+        self.vclock = start;
+        match slot {
+            Slot::Request(_) => {
+                tenant.inflight += 1;
+                self.inflight += 1;
+                *self.keys.entry(api_key_id).or_default() += 1;
+                crate::metrics::ACTIVE_CONNECTIONS
+                    .with_label_values(&[tenant_id])
+                    .inc();
+            }
+            Slot::Route { id, .. } => *self.routes.entry(id.clone()).or_default() += 1,
+        }
+    }
+
+    fn release(&mut self, tenant_id: &str, api_key_id: Uuid, slot: &Slot) {
+        match slot {
+            Slot::Request(_) => {
+                self.inflight = self.inflight.saturating_sub(1);
+                if let Some(inflight) = self.keys.get_mut(&api_key_id) {
+                    *inflight = inflight.saturating_sub(1);
+                    if *inflight == 0 {
+                        self.keys.remove(&api_key_id);
+                    }
+                }
+                if let Some(tenant) = self.tenants.get_mut(tenant_id) {
+                    tenant.inflight = tenant.inflight.saturating_sub(1);
+                }
+                crate::metrics::ACTIVE_CONNECTIONS
+                    .with_label_values(&[tenant_id])
+                    .dec();
+            }
+            Slot::Route { id, .. } => {
+                if let Some(inflight) = self.routes.get_mut(id) {
+                    *inflight = inflight.saturating_sub(1);
+                    if *inflight == 0 {
+                        self.routes.remove(id);
+                    }
+                }
+            }
+        }
+        self.forget_if_idle(tenant_id);
+    }
+
+    /// Drop the state of a tenant with nothing in flight or queued, unless
+    /// it is still ahead of the virtual clock and would lose that penalty
+    fn forget_if_idle(&mut self, tenant_id: &str) {
+        let vclock = self.vclock;
+        if let Some(tenant) = self.tenants.get(tenant_id) {
+            if tenant.inflight == 0 && tenant.queue.is_empty() && tenant.vtime <= vclock {
+                self.tenants.remove(tenant_id);
+            }
+        }
+    }
+
+    /// Remove a waiter that gave up; false if it was granted a slot already
+    fn cancel(&mut self, tenant_id: &str, id: u64) -> bool {
+        let Some(tenant) = self.tenants.get_mut(tenant_id) else {
+            return false;
+        };
+        let Some(index) = tenant.queue.iter().position(|w| w.id == id) else {
+            return false;
+        };
+        tenant.queue.remove(index);
+        self.queued -= 1;
+        self.forget_if_idle(tenant_id);
+        true
+    }
+
+    /// Grant slots to queued requests until none fits: each time to the
+    /// first request that fits of the tenant furthest behind in virtual time
+    fn dispatch(&mut self, max_concurrent: Option<usize>) {
+        loop {
+            let vclock = self.vclock;
+            let next = self
+                .tenants
+                .iter()
+                .filter_map(|(tenant_id, tenant)| {
+                    let index = tenant
+                        .queue
+                        .iter()
+                        .position(|w| self.fits(max_concurrent, tenant_id, w.api_key_id, &w.slot))?;
+                    Some((tenant.vtime.max(vclock), tenant_id, index))
+                })
+                .min_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(b.1)))
+                .map(|(_, tenant_id, index)| (tenant_id.clone(), index));
+            let Some((tenant_id, index)) = next else {
+                return;
+            };
+
+            let waiter = self
+                .tenants
+                .get_mut(&tenant_id)
+                .and_then(|t| t.queue.remove(index))
+                .expect("waiter was just found");
+            self.queued -= 1;
+            self.grant(&tenant_id, waiter.api_key_id, &waiter.slot);
+            // The receiver outlives the waiter's entry, see `QueuedRequest`
+            if waiter.granted.send(()).is_err() {
+                self.release(&tenant_id, waiter.api_key_id, &waiter.slot);
+            }
+        }
+    }
+}
+
+struct Shared {
+    config: ConcurrencyConfig,
+    state: Mutex<State>,
+}
+
+impl Shared {
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn release(&self, tenant_id: &str, api_key_id: Uuid, slot: &Slot) {
+        let mut state = self.state();
+        state.release(tenant_id, api_key_id, slot);
+        state.dispatch(self.config.max_concurrent);
+    }
+}
+
+/// Limits the requests in flight per API key, per tenant and across the
+/// gateway, and to each upstream route with `max_inflight`. Requests over a
+/// limit wait in a bounded queue; when capacity frees up it goes to the
+/// tenants in proportion to their scheduling weight, so one busy tenant
+/// cannot starve the others. Counted per gateway instance.
+#[derive(Clone)]
+pub struct ConcurrencyLimiter {
+    shared: Arc<Shared>,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(config: ConcurrencyConfig) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                config,
+                state: Mutex::new(State::default()),
+            }),
+        }
+    }
+
+    fn permit(&self, tenant_id: &str, api_key_id: Uuid, slot: Slot) -> ConcurrencyPermit {
+        ConcurrencyPermit {
+            shared: Arc::clone(&self.shared),
+            tenant_id: tenant_id.to_string(),
+            api_key_id,
+            slot,
+        }
+    }
+
+    /// Take a slot for a request, waiting in the queue while the request is
+    /// over a limit. Fails when the queue is full or the wait times out.
+    pub async fn acquire(
+        &self,
+        tenant_id: &str,
+        api_key_id: Uuid,
+        limits: ConcurrencyLimits,
+    ) -> Result<ConcurrencyPermit, RateLimitError> {
+        self.wait_for(tenant_id, api_key_id, Slot::Request(limits)).await
+    }
+
+    /// Take one of the `max_inflight` slots of an upstream route for a
+    /// request of the tenant, queued like `acquire` while the route is full
+    pub async fn acquire_route(
+        &self,
+        tenant_id: &str,
+        api_key_id: Uuid,
+        route_id: &str,
+        max_inflight: u32,
+    ) -> Result<ConcurrencyPermit, RateLimitError> {
+        let slot = Slot::Route {
+            id: route_id.to_string(),
+            max_inflight,
+        };
+        self.wait_for(tenant_id, api_key_id, slot).await
+    }
+
+    async fn wait_for(
+        &self,
+        tenant_id: &str,
+        api_key_id: Uuid,
+        slot: Slot,
+    ) -> Result<ConcurrencyPermit, RateLimitError> {
+        let config = &self.shared.config;
+        let (granted, mut rx) = oneshot::channel();
+        let id = {
+            let mut state = self.shared.state();
+            // Queued requests never fit, since every release dispatches them,
+            // so a request that fits does not jump ahead of anyone
+            if state.fits(config.max_concurrent, tenant_id, api_key_id, &slot) {
+                state.grant(tenant_id, api_key_id, &slot);
+                return Ok(self.permit(tenant_id, api_key_id, slot));
+            }
+            if state.queued >= config.max_queued {
+                return Err(RateLimitError::ConcurrencyExceeded {
+                    queue_full: true,
+                    retry_after: 1,
+                });
+            }
+
+            let id = state.next_id;
+            state.next_id += 1;
+            state.queued += 1;
+            state
+                .tenants
+                .entry(tenant_id.to_string())
+                .or_default()
+                .queue
+                .push_back(Waiter {
+                    id,
+                    api_key_id,
+                    slot: slot.clone(),
+                    granted,
+                });
+            id
+        };
+
+        let mut queued = QueuedRequest {
+            limiter: self,
+            tenant_id,
+            api_key_id,
+            slot: &slot,
+            id,
+            waiting: true,
+        };
+        let result = tokio::time::timeout(config.queue_timeout, &mut rx).await;
+        queued.waiting = false;
+        drop(queued);
+        match result {
+            Ok(Ok(())) => Ok(self.permit(tenant_id, api_key_id, slot)),
+            // The slot may have been granted just as the wait timed out
+            _ if !self.shared.state().cancel(tenant_id, id) => Ok(self.permit(tenant_id, api_key_id, slot)),
+            _ => Err(RateLimitError::ConcurrencyExceeded {
+                queue_full: false,
+                retry_after: config.queue_timeout.as_secs().max(1),
+            }),
+        }
+    }
+}
+
+/// Leaves the queue when a waiting request is dropped, e.g. because the
+/// client went away, giving back the slot if it was granted meanwhile
+struct QueuedRequest<'a> {
+    limiter: &'a ConcurrencyLimiter,
+    tenant_id: &'a str,
+    api_key_id: Uuid,
+    slot: &'a Slot,
+    id: u64,
+    waiting: bool,
+}
+
+impl Drop for QueuedRequest<'_> {
+    fn drop(&mut self) {
+        if self.waiting && !self.limiter.shared.state().cancel(self.tenant_id, self.id) {
+            self.limiter.shared.release(self.tenant_id, self.api_key_id, self.slot);
+        }
+    }
+}
+
+/// A request's slot, given back to the next queued request when dropped
+pub struct ConcurrencyPermit {
+    shared: Arc<Shared>,
+    tenant_id: String,
+    api_key_id: Uuid,
+    slot: Slot,
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        self.shared.release(&self.tenant_id, self.api_key_id, &self.slot);
+    }
+}
+
+/// Response body holding a request's permit until it has been sent, so
+/// streamed responses count as in flight until their last chunk
+pub struct PermitBody {
+    inner: Body,
+    permit: Option<ConcurrencyPermit>,
+}
+
+impl PermitBody {
+    pub fn new(inner: Body, permit: ConcurrencyPermit) -> Self {
+        Self {
+            inner,
+            permit: Some(permit),
+        }
+    }
+}
+
+impl HttpBody for PermitBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
+        let frame = Pin::new(&mut self.inner).poll_frame(cx);
+        if matches!(frame, Poll::Ready(None | Some(Err(_)))) {
+            self.permit = None;
+        }
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(max_concurrent: usize, max_queued: usize) -> ConcurrencyLimiter {
+        ConcurrencyLimiter::new(ConcurrencyConfig {
+            max_concurrent: Some(max_concurrent),
+            max_queued,
+            queue_timeout: Duration::from_millis(200),
+        })
+    }
+
+    fn weighted(weight: u32) -> ConcurrencyLimits {
+        ConcurrencyLimits {
+            weight,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_key_and_tenant_limits() {
+        let limiter = limiter(100, 0);
+        let (key, other_key) = (Uuid::new_v4(), Uuid::new_v4());
+        let limits = ConcurrencyLimits {
+            api_key: Some(1),
+            tenant: Some(2),
+            weight: 1,
+        };
+
+        let first = limiter.acquire("acme", key, limits).await.unwrap();
+        assert!(limiter.acquire("acme", key, limits).await.is_err());
+        let _second = limiter.acquire("acme", other_key, limits).await.unwrap();
+        assert!(limiter.acquire("acme", Uuid::new_v4(), limits).await.is_err());
+        // Other tenants are not affected
+        let _other = limiter.acquire("globex", Uuid::new_v4(), limits).await.unwrap();
+
+        drop(first);
+        assert!(limiter.acquire("acme", key, limits).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_queue_timeout_and_full_queue() {
+        let limiter = limiter(1, 1);
+        let _held = limiter.acquire("acme", Uuid::new_v4(), weighted(1)).await.unwrap();
+
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire("acme", Uuid::new_v4(), weighted(1)).await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        match limiter.acquire("globex", Uuid::new_v4(), weighted(1)).await {
+            Err(RateLimitError::ConcurrencyExceeded { queue_full, .. }) => assert!(queue_full),
+            _ => panic!("queue should be full"),
+        }
+        match waiting.await.unwrap() {
+            Err(RateLimitError::ConcurrencyExceeded { queue_full, .. }) => assert!(!queue_full),
+            _ => panic!("wait should time out"),
+        }
+        assert_eq!(limiter.shared.state().queued, 0);
+    }
+
+    #[tokio::test]
+    async fn test_released_slots_go_to_queued_requests() {
+        let limiter = limiter(1, 10);
+        let held = limiter.acquire("acme", Uuid::new_v4(), weighted(1)).await.unwrap();
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire("globex", Uuid::new_v4(), weighted(1)).await.is_ok() }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(held);
+        assert!(waiting.await.unwrap());
+        assert_eq!(limiter.shared.state().inflight, 0);
+    }
+
+    #[tokio::test]
+    async fn test_route_slots_queue_per_route_id() {
+        let limiter = limiter(100, 10);
+        let key = Uuid::new_v4();
+        let held = limiter.acquire_route("acme", key, "gpt-4o#0", 1).await.unwrap();
+        // Same model behind another key is a separate route
+        let _other = limiter.acquire_route("acme", key, "gpt-4o-team-b", 1).await.unwrap();
+
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire_route("globex", Uuid::new_v4(), "gpt-4o#0", 1).await.is_ok() }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(held);
+        assert!(waiting.await.unwrap());
+
+        // Route slots do not count against the request limits
+        assert_eq!(limiter.shared.state().inflight, 0);
+        let _busy = limiter.acquire_route("acme", key, "gpt-4o#0", 1).await.unwrap();
+        match limiter.acquire_route("acme", key, "gpt-4o#0", 1).await {
+            Err(RateLimitError::ConcurrencyExceeded { queue_full, .. }) => assert!(!queue_full),
+            _ => panic!("wait should time out"),
+        }
+    }
+
+    #[test]
+    fn test_saturated_capacity_is_shared_by_weight() {
+        let mut state = State::default();
+        let max = Some(1);
+        let (heavy, light) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut receivers = Vec::new();
+        for (tenant_id, key, weight) in [("heavy", heavy, 2), ("light", light, 1)] {
+            for _ in 0..6 {
+                let (granted, rx) = oneshot::channel();
+                receivers.push(rx);
+                let id = state.next_id;
+                state.next_id += 1;
+                state.queued += 1;
+                state.tenants.entry(tenant_id.to_string()).or_default().queue.push_back(Waiter {
+                    id,
+                    api_key_id: key,
+                    slot: Slot::Request(weighted(weight)),
+                    granted,
+                });
+            }
+        }
+
+        let mut order = Vec::new();
+        for _ in 0..6 {
+            state.dispatch(max);
+            let (&key, _) = state.keys.iter().next().unwrap();
+            order.push(if key == heavy { "heavy" } else { "light" });
+            state.release(if key == heavy { "heavy" } else { "light" }, key, &Slot::Request(weighted(1)));
+        }
+        assert_eq!(order.iter().filter(|t| **t == "heavy").count(), 4);
+        assert_eq!(order.iter().filter(|t| **t == "light").count(), 2);
+    }
+}
@@ -6,7 +6,9 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
-use crate::connectors::{Connector, ConnectorCapabilities, ConnectorError, ConnectorResponse};
+use crate::connectors::{
+    self, Connector, ConnectorCapabilities, ConnectorError, ConnectorResponse,
+};
 use crate::core::entities::{ContentPart, UnifiedChunk, UnifiedMessage, UnifiedRequest};
 use crate::registry::EgressRoute;
 use crate::secret_store::SecretProvider;
@@ -120,19 +122,10 @@ impl Connector for ClewdrConnector {
 
         if req.stream {
             // Streaming mode (OpenAI-compatible SSE)
-            let response = rb
-                .json(&body)
-                .send()
-                .await
-                .map_err(|e| ConnectorError::Upstream(e.to_string()))?;
-
-            let status = response.status();
-            if !status.is_success() {
-                let text = response.text().await.unwrap_or_default();
-                return Err(ConnectorError::Upstream(format!(
-                    "status {}: {}",
-                    status, text
-                )));
+            let response = rb.json(&body).send().await?;
+
+            if !response.status().is_success() {
+                return Err(connectors::status_error(response).await);
             }
 
             let stream =
@@ -145,7 +138,8 @@ impl Connector for ClewdrConnector {
                             if data == "[DONE]" {
                                 return Ok(UnifiedChunk {
                                     text_delta: None,
-                                    tool_call_delta: None,
+                                    tool_calls: Vec::new(),
+                                    finish_reason: None,
                                     done: true,
                                     provider_events: None,
                                 });
@@ -156,7 +150,10 @@ impl Connector for ClewdrConnector {
                             let text_delta = delta["content"].as_str().map(String::from);
                             Ok(UnifiedChunk {
                                 text_delta,
-                                tool_call_delta: None,
+                                tool_calls: Vec::new(),
+                                finish_reason: connectors::openai_finish_reason(
+                                    &json_val["choices"][0]["finish_reason"],
+                                ),
                                 done: false,
                                 provider_events: Some(json_val),
                             })
@@ -168,13 +165,8 @@ impl Connector for ClewdrConnector {
         } else {
             // Non-streaming mode
             let resp = rb.json(&body).send().await?;
-            let status = resp.status();
-            if !status.is_success() {
-                let text = resp.text().await.unwrap_or_default();
-                return Err(ConnectorError::Upstream(format!(
-                    "status {}: {}",
-                    status, text
-                )));
+            if !resp.status().is_success() {
+                return Err(connectors::status_error(resp).await);
             }
             let v: serde_json::Value = resp.json().await?;
             let content = v
@@ -184,7 +176,10 @@ impl Connector for ClewdrConnector {
                 .to_string();
             let chunk = UnifiedChunk {
                 text_delta: Some(content),
-                tool_call_delta: None,
+                tool_calls: Vec::new(),
+                finish_reason: v
+                    .pointer("/choices/0/finish_reason")
+                    .and_then(connectors::openai_finish_reason),
                 done: true,
                 provider_events: Some(v),
             };
@@ -1,12 +1,15 @@
 use futures_util::stream::BoxStream;
+use std::time::Duration;
 use thiserror::Error;
 
 pub mod clewdr;
 pub mod openrouter;
 pub mod vertex;
 
-use crate::core::entities::{UnifiedChunk, UnifiedRequest};
-use crate::registry::EgressRoute;
+use crate::core::entities::{
+    ContentPart, FinishReason, ToolCallDelta, UnifiedChunk, UnifiedRequest,
+};
+use crate::registry::{CapabilityOverrides, EgressRoute, RetryClass};
 
 #[derive(Clone, Debug)]
 pub struct ConnectorCapabilities {
@@ -17,6 +20,48 @@ pub struct ConnectorCapabilities {
     pub stream: bool,
 }
 
+impl ConnectorCapabilities {
+    /// Apply per-route overrides from the routing config
+    pub fn with_overrides(self, o: &CapabilityOverrides) -> Self {
+        Self {
+            text: o.text.unwrap_or(self.text),
+            vision: o.vision.unwrap_or(self.vision),
+            video: o.video.unwrap_or(self.video),
+            tools: o.tools.unwrap_or(self.tools),
+            stream: o.stream.unwrap_or(self.stream),
+        }
+    }
+
+    /// Capabilities `req` needs that are not supported here
+    pub fn missing_for(&self, req: &UnifiedRequest) -> Vec<&'static str> {
+        let parts = || req.messages.iter().flat_map(|m| m.content.iter());
+        let needs_text = parts().any(|p| matches!(p, ContentPart::Text { .. }));
+        let needs_vision = parts().any(|p| {
+            matches!(p, ContentPart::ImageUrl { .. } | ContentPart::ImageB64 { .. })
+        });
+        let needs_video = parts().any(|p| matches!(p, ContentPart::VideoUrl { .. }));
+        let needs_tools = req.tools.as_ref().is_some_and(|t| !t.is_empty())
+            || req
+                .messages
+                .iter()
+                .any(|m| !m.tool_calls.is_empty() || m.tool_call_id.is_some());
+
+        let mut missing = Vec::new();
+        for (needed, supported, name) in [
+            (needs_text, self.text, "text"),
+            (needs_vision, self.vision, "vision"),
+            (needs_video, self.video, "video"),
+            (needs_tools, self.tools, "tools"),
+            (req.stream, self.stream, "stream"),
+        ] {
+            if needed && !supported {
+                missing.push(name);
+            }
+        }
+        missing
+    }
+}
+
 #[async_trait::async_trait]
 pub trait Connector: Send + Sync {
     fn name(&self) -> &'static str;
@@ -38,17 +83,151 @@ pub enum ConnectorError {
     #[error("auth error: {0}")]
     Auth(String),
     #[error("rate_limited")]
-    RateLimited,
+    RateLimited { retry_after: Option<Duration> },
     #[error("upstream_timeout")]
     Timeout,
     #[error("upstream_error: {0}")]
     Upstream(String),
+    #[error("upstream_status {status}: {message}")]
+    UpstreamStatus {
+        status: u16,
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    #[error("circuit_open: {0}")]
+    CircuitOpen(String),
+    /// The route kept its `max_inflight` requests in flight for as long as
+    /// the request could wait in the queue
+    #[error("route_saturated: {route}, retry after {} seconds", retry_after_secs(*.retry_after))]
+    RouteSaturated { route: String, retry_after: Duration },
     #[error("invalid_request: {0}")]
     Invalid(String),
+    #[error("insufficient_credit: {0}")]
+    InsufficientCredit(String),
+    /// The key's or tenant's tokens-per-minute budget cannot take the request yet
+    #[error("token_rate_limited: retry after {} seconds", retry_after_secs(*.retry_after))]
+    TokenRateLimited { retry_after: Duration },
     #[error("internal: {0}")]
     Internal(String),
 }
 
+impl ConnectorError {
+    /// Whether the next route in a fallback chain should be tried after this error
+    pub fn is_failover(&self) -> bool {
+        matches!(
+            self,
+            ConnectorError::Upstream(_)
+                | ConnectorError::Timeout
+                | ConnectorError::RateLimited { .. }
+                | ConnectorError::CircuitOpen(_)
+                | ConnectorError::RouteSaturated { .. }
+        ) || self.is_transient_status()
+    }
+
+    /// Upstream status worth trying elsewhere: server errors and request
+    /// timeouts. Client errors would fail the same way on every route.
+    fn is_transient_status(&self) -> bool {
+        matches!(self, ConnectorError::UpstreamStatus { status, .. } if *status >= 500 || *status == 408)
+    }
+
+    /// Retry class of this error, if it is transient at all
+    pub fn retry_class(&self) -> Option<RetryClass> {
+        match self {
+            ConnectorError::Timeout => Some(RetryClass::Timeout),
+            ConnectorError::RateLimited { .. } => Some(RetryClass::RateLimited),
+            ConnectorError::Upstream(_) => Some(RetryClass::Connection),
+            ConnectorError::UpstreamStatus { status: 408, .. } => Some(RetryClass::Timeout),
+            ConnectorError::UpstreamStatus { status, .. } if *status >= 500 => {
+                Some(RetryClass::ServerError)
+            }
+            _ => None,
+        }
+    }
+
+    /// Upstream `Retry-After` hint carried by this error
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ConnectorError::RateLimited { retry_after }
+            | ConnectorError::UpstreamStatus { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+/// Whole seconds to wait, rounded up so clients never retry too early
+fn retry_after_secs(wait: Duration) -> u64 {
+    wait.as_secs() + u64::from(wait.subsec_nanos() > 0)
+}
+
+/// Parse OpenAI-style `tool_calls` (a streaming delta or a complete message)
+pub(crate) fn openai_tool_calls(v: Option<&serde_json::Value>) -> Vec<ToolCallDelta> {
+    v.and_then(|v| v.as_array())
+        .map(|calls| {
+            calls
+                .iter()
+                .enumerate()
+                .map(|(i, call)| ToolCallDelta {
+                    index: call["index"].as_u64().unwrap_or(i as u64) as u32,
+                    id: call["id"].as_str().map(String::from),
+                    name: call["function"]["name"].as_str().map(String::from),
+                    arguments: call["function"]["arguments"].as_str().map(String::from),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Map an OpenAI-style `finish_reason`
+pub(crate) fn openai_finish_reason(v: &serde_json::Value) -> Option<FinishReason> {
+    match v.as_str()? {
+        "stop" => Some(FinishReason::Stop),
+        "length" => Some(FinishReason::Length),
+        "tool_calls" | "function_call" => Some(FinishReason::ToolCalls),
+        "content_filter" => Some(FinishReason::ContentFilter),
+        _ => None,
+    }
+}
+
+/// Merge the route's `extra` table (validated against
+/// `registry::known_extra_keys`) into an upstream request body
+pub(crate) fn apply_route_extra(body: &mut serde_json::Value, route: &EgressRoute) {
+    for (k, v) in &route.extra {
+        body[k] = v.clone();
+    }
+}
+
+/// Turn a non-success upstream response into a `ConnectorError`, keeping the
+/// status and any `Retry-After` hint so the routing layer can retry correctly
+pub(crate) async fn status_error(resp: reqwest::Response) -> ConnectorError {
+    let status = resp.status();
+    let retry_after = resp
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_retry_after);
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return ConnectorError::RateLimited { retry_after };
+    }
+    let message = resp.text().await.unwrap_or_default();
+    ConnectorError::UpstreamStatus {
+        status: status.as_u16(),
+        message,
+        retry_after,
+    }
+}
+
+/// Parse a `Retry-After` header value: delta-seconds or an HTTP-date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = time::OffsetDateTime::parse(value, &time::format_description::well_known::Rfc2822)
+        .ok()?;
+    let delta = at - time::OffsetDateTime::now_utc();
+    Some(Duration::from_secs(delta.whole_seconds().max(0) as u64))
+}
+
 impl From<reqwest::Error> for ConnectorError {
     fn from(e: reqwest::Error) -> Self {
         if e.is_timeout() {
@@ -65,15 +244,28 @@ impl From<anyhow::Error> for ConnectorError {
     }
 }
 
+/// Response extension of a request refused by its tokens-per-minute limit
+/// before any route was tried
+#[derive(Clone, Copy, Debug)]
+pub struct TokenRateLimitedResponse;
+
 impl axum::response::IntoResponse for ConnectorError {
     fn into_response(self) -> axum::response::Response {
         use axum::{http::StatusCode, Json};
         let (code, msg) = match &self {
             ConnectorError::Auth(_) => (StatusCode::UNAUTHORIZED, self.to_string()),
-            ConnectorError::RateLimited => (StatusCode::TOO_MANY_REQUESTS, self.to_string()),
+            ConnectorError::RateLimited { .. } => {
+                (StatusCode::TOO_MANY_REQUESTS, self.to_string())
+            }
             ConnectorError::Timeout => (StatusCode::GATEWAY_TIMEOUT, self.to_string()),
-            ConnectorError::Upstream(_) => (StatusCode::BAD_GATEWAY, self.to_string()),
+            ConnectorError::Upstream(_) | ConnectorError::UpstreamStatus { .. } => {
+                (StatusCode::BAD_GATEWAY, self.to_string())
+            }
+            ConnectorError::CircuitOpen(_) => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            ConnectorError::RouteSaturated { .. } => (StatusCode::TOO_MANY_REQUESTS, self.to_string()),
             ConnectorError::Invalid(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+            ConnectorError::InsufficientCredit(_) => (StatusCode::PAYMENT_REQUIRED, self.to_string()),
+            ConnectorError::TokenRateLimited { .. } => (StatusCode::TOO_MANY_REQUESTS, self.to_string()),
             ConnectorError::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
         };
         let body = serde_json::json!({
@@ -82,6 +274,80 @@ impl axum::response::IntoResponse for ConnectorError {
                 "type": "xjp_error"
             }
         });
-        (code, Json(body)).into_response()
+        let mut response = (code, Json(body)).into_response();
+        if let ConnectorError::TokenRateLimited { .. } = self {
+            response.extensions_mut().insert(TokenRateLimitedResponse);
+        }
+        if let ConnectorError::TokenRateLimited { retry_after } | ConnectorError::RouteSaturated { retry_after, .. } = self {
+            if let Ok(value) = axum::http::HeaderValue::from_str(&retry_after_secs(retry_after).to_string()) {
+                response.headers_mut().insert(axum::http::header::RETRY_AFTER, value);
+            }
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::entities::UnifiedMessage;
+
+    #[test]
+    fn test_missing_capabilities_with_overrides() {
+        let req = UnifiedRequest {
+            logical_model: "m".into(),
+            messages: vec![UnifiedMessage {
+                role: "user".into(),
+                content: vec![
+                    ContentPart::Text {
+                        text: "describe".into(),
+                    },
+                    ContentPart::VideoUrl {
+                        url: "gs://clip.mp4".into(),
+                        mime: None,
+                    },
+                ],
+                name: None,
+                tool_calls: Vec::new(),
+                tool_call_id: None,
+            }],
+            tools: None,
+            tool_choice: None,
+            max_output_tokens: None,
+            temperature: None,
+            top_p: None,
+            stream: true,
+            extra: serde_json::Value::Null,
+        };
+        let caps = ConnectorCapabilities {
+            text: true,
+            vision: true,
+            video: false,
+            tools: false,
+            stream: true,
+        };
+        assert_eq!(caps.missing_for(&req), ["video"]);
+
+        let overrides = CapabilityOverrides {
+            video: Some(true),
+            stream: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(caps.with_overrides(&overrides).missing_for(&req), ["stream"]);
+    }
+
+    #[test]
+    fn test_client_errors_do_not_fail_over() {
+        let status = |status| ConnectorError::UpstreamStatus {
+            status,
+            message: String::new(),
+            retry_after: None,
+        };
+        for code in [400, 401, 403, 404, 422] {
+            assert!(!status(code).is_failover(), "{} should not fail over", code);
+        }
+        assert!(status(408).is_failover());
+        assert!(status(503).is_failover());
+        assert!(ConnectorError::Timeout.is_failover());
     }
 }
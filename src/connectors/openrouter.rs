@@ -1,3 +1,4 @@
+use dashmap::DashMap;
 use eventsource_stream::Eventsource;
 use futures_util::StreamExt;
 use reqwest::{header, Client};
@@ -6,8 +7,10 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
-use crate::connectors::{Connector, ConnectorCapabilities, ConnectorError, ConnectorResponse};
-use crate::core::entities::{ContentPart, UnifiedChunk, UnifiedRequest};
+use crate::connectors::{
+    self, Connector, ConnectorCapabilities, ConnectorError, ConnectorResponse,
+};
+use crate::core::entities::{ContentPart, ToolChoice, UnifiedChunk, UnifiedRequest};
 use crate::registry::EgressRoute;
 use crate::secret_store::SecretProvider;
 
@@ -15,11 +18,14 @@ pub struct OpenRouterConnector {
     client: Client,
     api_key: Option<String>,
     base_url: String,
+    secret_provider: Arc<dyn SecretProvider>,
+    /// Keys of routes with their own `api_key_secret`, by secret name
+    route_keys: DashMap<String, String>,
 }
 
 impl OpenRouterConnector {
     pub fn new(
-        _secret_provider: Arc<dyn SecretProvider>,
+        secret_provider: Arc<dyn SecretProvider>,
         preloaded_secrets: &HashMap<String, String>,
     ) -> Result<Self, ConnectorError> {
         let client = Client::builder()
@@ -46,12 +52,40 @@ impl OpenRouterConnector {
             .or_else(|| std::env::var("OPENROUTER_BASE_URL").ok())
             .unwrap_or_else(|| "https://openrouter.ai/api/v1".to_string());
 
+        // Preloaded OpenRouter secrets can serve as route keys without a lookup
+        let route_keys = preloaded_secrets
+            .iter()
+            .filter(|(name, _)| name.starts_with("providers/openrouter/"))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+
         Ok(Self {
             client,
             api_key,
             base_url,
+            secret_provider,
+            route_keys,
         })
     }
+
+    /// API key for a route: the secret named by its `api_key_secret`, fetched
+    /// once and cached, or the provider's default key
+    async fn api_key_for(&self, route: &EgressRoute) -> Result<String, ConnectorError> {
+        let Some(name) = &route.api_key_secret else {
+            return self
+                .api_key
+                .clone()
+                .ok_or_else(|| ConnectorError::Auth("OpenRouter API key not configured".into()));
+        };
+        if let Some(key) = self.route_keys.get(name) {
+            return Ok(key.clone());
+        }
+        let key = self.secret_provider.get_secret(name).await.map_err(|e| {
+            ConnectorError::Auth(format!("OpenRouter API key secret '{}' unavailable: {}", name, e))
+        })?;
+        self.route_keys.insert(name.clone(), key.clone());
+        Ok(key)
+    }
 }
 
 #[async_trait::async_trait]
@@ -64,7 +98,8 @@ impl Connector for OpenRouterConnector {
         ConnectorCapabilities {
             text: true,
             vision: true,
-            video: true,
+            // Video parts are sent as a text placeholder
+            video: false,
             tools: true,
             stream: true,
         }
@@ -125,7 +160,28 @@ impl Connector for OpenRouterConnector {
                     json!(parts)
                 };
 
-                json!({"role": msg.role, "content": content})
+                let mut out = json!({"role": msg.role, "content": content});
+                if !msg.tool_calls.is_empty() {
+                    let calls: Vec<serde_json::Value> = msg
+                        .tool_calls
+                        .iter()
+                        .map(|call| {
+                            json!({
+                                "id": call.id,
+                                "type": "function",
+                                "function": {"name": call.name, "arguments": call.arguments}
+                            })
+                        })
+                        .collect();
+                    out["tool_calls"] = json!(calls);
+                    if msg.content.is_empty() {
+                        out["content"] = serde_json::Value::Null;
+                    }
+                }
+                if let Some(id) = &msg.tool_call_id {
+                    out["tool_call_id"] = json!(id);
+                }
+                out
             })
             .collect();
 
@@ -162,7 +218,14 @@ impl Connector for OpenRouterConnector {
             body["tools"] = json!(tools_json);
         }
         if let Some(choice) = &req.tool_choice {
-            body["tool_choice"] = json!(choice);
+            body["tool_choice"] = match choice {
+                ToolChoice::Auto => json!("auto"),
+                ToolChoice::None => json!("none"),
+                ToolChoice::Required => json!("required"),
+                ToolChoice::Tool { name } => {
+                    json!({"type": "function", "function": {"name": name}})
+                }
+            };
         }
         for (k, v) in req
             .extra
@@ -172,24 +235,19 @@ impl Connector for OpenRouterConnector {
         {
             body[k] = v.clone();
         }
+        connectors::apply_route_extra(&mut body, route);
 
-        let mut rb = self
+        let rb = self
             .client
             .post(&url)
-            .header(header::CONTENT_TYPE, "application/json");
-
-        if let Some(k) = &self.api_key {
-            rb = rb.bearer_auth(k);
-        } else {
-            return Err(ConnectorError::Auth("OpenRouter API key not configured".into()));
-        }
+            .header(header::CONTENT_TYPE, "application/json")
+            .bearer_auth(self.api_key_for(route).await?);
 
         if req.stream {
-            let response = rb
-                .json(&body)
-                .send()
-                .await
-                .map_err(|e| ConnectorError::Upstream(e.to_string()))?;
+            let response = rb.json(&body).send().await?;
+            if !response.status().is_success() {
+                return Err(connectors::status_error(response).await);
+            }
 
             let stream =
                 response
@@ -201,7 +259,8 @@ impl Connector for OpenRouterConnector {
                             if data == "[DONE]" {
                                 return Ok(UnifiedChunk {
                                     text_delta: None,
-                                    tool_call_delta: None,
+                                    tool_calls: Vec::new(),
+                                    finish_reason: None,
                                     done: true,
                                     provider_events: None,
                                 });
@@ -211,12 +270,12 @@ impl Connector for OpenRouterConnector {
                             let delta = &json_val["choices"][0]["delta"];
                             let text_delta = delta["content"].as_str().map(String::from);
 
-                            // Check for tool_calls in delta
-                            let tool_call_delta = delta.get("tool_calls").cloned();
-
                             Ok(UnifiedChunk {
                                 text_delta,
-                                tool_call_delta,
+                                tool_calls: connectors::openai_tool_calls(delta.get("tool_calls")),
+                                finish_reason: connectors::openai_finish_reason(
+                                    &json_val["choices"][0]["finish_reason"],
+                                ),
                                 done: false,
                                 provider_events: Some(json_val),
                             })
@@ -226,11 +285,10 @@ impl Connector for OpenRouterConnector {
 
             Ok(ConnectorResponse::Streaming(Box::pin(stream)))
         } else {
-            let resp = rb
-                .json(&body)
-                .send()
-                .await
-                .map_err(|e| ConnectorError::Upstream(e.to_string()))?;
+            let resp = rb.json(&body).send().await?;
+            if !resp.status().is_success() {
+                return Err(connectors::status_error(resp).await);
+            }
 
             let json: serde_json::Value = resp
                 .json()
@@ -243,12 +301,14 @@ impl Connector for OpenRouterConnector {
                 .unwrap_or("")
                 .to_string();
 
-            // Check for tool_calls in message
-            let tool_call_delta = json.pointer("/choices/0/message/tool_calls").cloned();
-
             let chunk = UnifiedChunk {
                 text_delta: if text.is_empty() { None } else { Some(text) },
-                tool_call_delta,
+                tool_calls: connectors::openai_tool_calls(
+                    json.pointer("/choices/0/message/tool_calls"),
+                ),
+                finish_reason: json
+                    .pointer("/choices/0/finish_reason")
+                    .and_then(connectors::openai_finish_reason),
                 done: true,
                 provider_events: Some(json),
             };
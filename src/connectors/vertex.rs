@@ -6,8 +6,13 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
-use crate::connectors::{Connector, ConnectorCapabilities, ConnectorError, ConnectorResponse};
-use crate::core::entities::{ContentPart, UnifiedChunk, UnifiedMessage, UnifiedRequest};
+use crate::connectors::{
+    self, Connector, ConnectorCapabilities, ConnectorError, ConnectorResponse,
+};
+use crate::core::entities::{
+    ContentPart, FinishReason, ToolCallDelta, ToolChoice, ToolSpec, UnifiedChunk, UnifiedMessage,
+    UnifiedRequest,
+};
 use crate::registry::EgressRoute;
 use crate::secret_store::SecretProvider;
 
@@ -68,29 +73,164 @@ impl VertexConnector {
         })
     }
 
-    fn map_messages(messages: &[UnifiedMessage]) -> serde_json::Value {
-        // Vertex: contents: [{role, parts:[{text}|{fileData}|{inlineData}]}]
-        let mut contents = Vec::new();
+    fn map_messages(messages: &[UnifiedMessage]) -> Result<serde_json::Value, ConnectorError> {
+        // Vertex: contents: [{role, parts:[{text}|{fileData}|{inlineData}|{functionCall}|{functionResponse}]}]
+        let mut contents: Vec<serde_json::Value> = Vec::new();
+        // functionResponse is matched by function name, not call id
+        let mut call_names: HashMap<&str, &str> = HashMap::new();
+        let mut last_was_tool = false;
         for m in messages {
             let mut parts = Vec::new();
             for p in &m.content {
                 match p {
+                    ContentPart::Text { text } if m.role == "tool" => {
+                        let id = m.tool_call_id.as_deref().unwrap_or_default();
+                        let name = call_names.get(id).copied().ok_or_else(|| {
+                            ConnectorError::Invalid(format!(
+                                "tool result for unknown tool_call_id \"{}\"",
+                                id
+                            ))
+                        })?;
+                        // response must be an object; wrap non-object results
+                        let response = match serde_json::from_str::<serde_json::Value>(text) {
+                            Ok(v) if v.is_object() => v,
+                            _ => json!({"content": text}),
+                        };
+                        parts.push(json!({"functionResponse": {"name": name, "response": response}}));
+                    }
                     ContentPart::Text { text } => parts.push(json!({"text": text})),
                     ContentPart::ImageUrl { url, mime } => parts.push(json!({"fileData": {"fileUri": url, "mimeType": mime.clone().unwrap_or("image/*".into())}})),
                     ContentPart::ImageB64 { b64, mime } => parts.push(json!({"inlineData": {"data": b64, "mimeType": mime}})),
                     ContentPart::VideoUrl { url, mime } => parts.push(json!({"fileData": {"fileUri": url, "mimeType": mime.clone().unwrap_or("video/*".into())}})),
                 }
             }
-            if !parts.is_empty() {
-                contents.push(json!({
-                    "role": if m.role == "assistant" { "model" } else { "user" },
-                    "parts": parts
-                }));
+            for call in &m.tool_calls {
+                call_names.insert(&call.id, &call.name);
+                let args = serde_json::from_str::<serde_json::Value>(&call.arguments)
+                    .unwrap_or(json!({}));
+                parts.push(json!({"functionCall": {"name": call.name, "args": args}}));
+            }
+            if parts.is_empty() {
+                continue;
+            }
+            // Responses to parallel calls must arrive in one turn
+            let is_tool = m.role == "tool";
+            if is_tool && last_was_tool {
+                if let Some(prev) = contents.last_mut().and_then(|c| c["parts"].as_array_mut()) {
+                    prev.extend(parts);
+                    continue;
+                }
             }
+            last_was_tool = is_tool;
+            contents.push(json!({
+                "role": if m.role == "assistant" { "model" } else { "user" },
+                "parts": parts
+            }));
         }
-        json!(contents)
+        Ok(json!(contents))
+    }
+
+    /// Gemini `tools` entry for the request's tool specs
+    fn map_tools(tools: &[ToolSpec]) -> serde_json::Value {
+        let declarations: Vec<serde_json::Value> = tools
+            .iter()
+            .map(|t| {
+                let mut decl = json!({
+                    "name": t.name,
+                    "parameters": Self::sanitize_schema(t.json_schema.clone())
+                });
+                if let Some(d) = &t.description {
+                    decl["description"] = json!(d);
+                }
+                decl
+            })
+            .collect();
+        json!([{ "functionDeclarations": declarations }])
     }
 
+    /// Gemini accepts an OpenAPI subset of JSON Schema and rejects requests
+    /// containing these keywords
+    fn sanitize_schema(mut schema: serde_json::Value) -> serde_json::Value {
+        match &mut schema {
+            serde_json::Value::Object(map) => {
+                map.remove("$schema");
+                map.remove("additionalProperties");
+                for v in map.values_mut() {
+                    *v = Self::sanitize_schema(v.take());
+                }
+            }
+            serde_json::Value::Array(items) => {
+                for v in items.iter_mut() {
+                    *v = Self::sanitize_schema(v.take());
+                }
+            }
+            _ => {}
+        }
+        schema
+    }
+
+    fn map_tool_choice(choice: &ToolChoice) -> serde_json::Value {
+        let config = match choice {
+            ToolChoice::Auto => json!({"mode": "AUTO"}),
+            ToolChoice::None => json!({"mode": "NONE"}),
+            ToolChoice::Required => json!({"mode": "ANY"}),
+            ToolChoice::Tool { name } => json!({"mode": "ANY", "allowedFunctionNames": [name]}),
+        };
+        json!({ "functionCallingConfig": config })
+    }
+
+    /// Text and function calls from `candidates[0].content.parts`. Gemini sends
+    /// each call whole, so every call becomes one complete delta; `next_index`
+    /// keeps indices unique across the chunks of a stream.
+    fn parse_parts(v: &serde_json::Value, next_index: &mut u32) -> (String, Vec<ToolCallDelta>) {
+        let mut text_out = String::new();
+        let mut calls = Vec::new();
+        if let Some(parts) = v
+            .pointer("/candidates/0/content/parts")
+            .and_then(|x| x.as_array())
+        {
+            for p in parts {
+                if let Some(t) = p.get("text").and_then(|x| x.as_str()) {
+                    text_out.push_str(t);
+                }
+                if let Some(call) = p.get("functionCall") {
+                    calls.push(ToolCallDelta {
+                        index: *next_index,
+                        id: Some(
+                            call.get("id")
+                                .and_then(|x| x.as_str())
+                                .map(String::from)
+                                .unwrap_or_else(|| format!("call_{}", uuid::Uuid::new_v4().simple())),
+                        ),
+                        name: call.get("name").and_then(|x| x.as_str()).map(String::from),
+                        arguments: Some(call.get("args").cloned().unwrap_or(json!({})).to_string()),
+                    });
+                    *next_index += 1;
+                }
+            }
+        }
+        (text_out, calls)
+    }
+
+    /// Map Gemini `finishReason` of the first candidate
+    fn finish_reason(v: &serde_json::Value) -> Option<FinishReason> {
+        match v.pointer("/candidates/0/finishReason")?.as_str()? {
+            "STOP" => Some(FinishReason::Stop),
+            "MAX_TOKENS" => Some(FinishReason::Length),
+            "SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII" => {
+                Some(FinishReason::ContentFilter)
+            }
+            _ => None,
+        }
+    }
+
+    /// Gemini reports `STOP` after function calls; surface it as a tool-call stop
+    fn finish_with_calls(reason: Option<FinishReason>, saw_calls: bool) -> Option<FinishReason> {
+        match reason {
+            Some(FinishReason::Stop) if saw_calls => Some(FinishReason::ToolCalls),
+            other => other,
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -104,7 +244,7 @@ impl Connector for VertexConnector {
             text: true,
             vision: true,
             video: true,
-            tools: false,
+            tools: true,
             stream: true,
         }
     }
@@ -162,7 +302,7 @@ impl Connector for VertexConnector {
 
         // Request body
         let mut body = json!({
-            "contents": Self::map_messages(&req.messages)
+            "contents": Self::map_messages(&req.messages)?
         });
 
         let mut gen_config = serde_json::Map::new();
@@ -178,29 +318,29 @@ impl Connector for VertexConnector {
         if !gen_config.is_empty() {
             body["generationConfig"] = json!(gen_config);
         }
+        if let Some(tools) = req.tools.as_deref().filter(|t| !t.is_empty()) {
+            body["tools"] = Self::map_tools(tools);
+        }
+        if let Some(choice) = &req.tool_choice {
+            body["toolConfig"] = Self::map_tool_choice(choice);
+        }
+        connectors::apply_route_extra(&mut body, route);
 
         if req.stream {
             // Streaming mode
-            let response = rb
-                .json(&body)
-                .send()
-                .await
-                .map_err(|e| ConnectorError::Upstream(e.to_string()))?;
-
-            let status = response.status();
-            if !status.is_success() {
-                let text = response.text().await.unwrap_or_default();
-                return Err(ConnectorError::Upstream(format!(
-                    "status {}: {}",
-                    status, text
-                )));
+            let response = rb.json(&body).send().await?;
+
+            if !response.status().is_success() {
+                return Err(connectors::status_error(response).await);
             }
 
+            let mut next_index = 0;
+            let mut saw_calls = false;
             let stream =
                 response
                     .bytes_stream()
                     .eventsource()
-                    .map(|event_result| match event_result {
+                    .map(move |event_result| match event_result {
                         Ok(event) => {
                             let data = event.data;
 
@@ -208,29 +348,20 @@ impl Connector for VertexConnector {
                             let json_val: serde_json::Value =
                                 serde_json::from_str(&data).unwrap_or_default();
 
-                            // Extract text from candidates[0].content.parts[].text
-                            let mut text_delta = None;
-                            if let Some(parts) = json_val
-                                .pointer("/candidates/0/content/parts")
-                                .and_then(|x| x.as_array())
-                            {
-                                let mut text_out = String::new();
-                                for p in parts {
-                                    if let Some(t) = p.get("text").and_then(|x| x.as_str()) {
-                                        text_out.push_str(t);
-                                    }
-                                }
-                                if !text_out.is_empty() {
-                                    text_delta = Some(text_out);
-                                }
-                            }
+                            let (text_out, tool_calls) =
+                                Self::parse_parts(&json_val, &mut next_index);
+                            saw_calls |= !tool_calls.is_empty();
 
                             // Check if this is the final chunk
                             let done = json_val.pointer("/candidates/0/finishReason").is_some();
 
                             Ok(UnifiedChunk {
-                                text_delta,
-                                tool_call_delta: None,
+                                text_delta: (!text_out.is_empty()).then_some(text_out),
+                                tool_calls,
+                                finish_reason: Self::finish_with_calls(
+                                    Self::finish_reason(&json_val),
+                                    saw_calls,
+                                ),
                                 done,
                                 provider_events: Some(json_val),
                             })
@@ -242,32 +373,19 @@ impl Connector for VertexConnector {
         } else {
             // Non-streaming mode
             let resp = rb.json(&body).send().await?;
-            let status = resp.status();
-            if !status.is_success() {
-                let text = resp.text().await.unwrap_or_default();
-                return Err(ConnectorError::Upstream(format!(
-                    "status {}: {}",
-                    status, text
-                )));
+            if !resp.status().is_success() {
+                return Err(connectors::status_error(resp).await);
             }
             let v: serde_json::Value = resp.json().await?;
 
-            // Aggregate text
-            let mut text_out = String::new();
-            if let Some(parts) = v
-                .pointer("/candidates/0/content/parts")
-                .and_then(|x| x.as_array())
-            {
-                for p in parts {
-                    if let Some(t) = p.get("text").and_then(|x| x.as_str()) {
-                        text_out.push_str(t);
-                    }
-                }
-            }
+            let (text_out, tool_calls) = Self::parse_parts(&v, &mut 0);
+            let finish_reason =
+                Self::finish_with_calls(Self::finish_reason(&v), !tool_calls.is_empty());
 
             let chunk = UnifiedChunk {
                 text_delta: Some(text_out),
-                tool_call_delta: None,
+                tool_calls,
+                finish_reason,
                 done: true,
                 provider_events: Some(v),
             };
@@ -275,3 +393,81 @@ impl Connector for VertexConnector {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::entities::ToolCall;
+
+    fn msg(role: &str, text: Option<&str>) -> UnifiedMessage {
+        UnifiedMessage {
+            role: role.into(),
+            content: text
+                .map(|t| vec![ContentPart::Text { text: t.into() }])
+                .unwrap_or_default(),
+            name: None,
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+
+    #[test]
+    fn test_function_call_round_trip() {
+        let mut assistant = msg("assistant", None);
+        assistant.tool_calls = vec![
+            ToolCall {
+                id: "call_a".into(),
+                name: "get_weather".into(),
+                arguments: r#"{"city":"Paris"}"#.into(),
+            },
+            ToolCall {
+                id: "call_b".into(),
+                name: "get_time".into(),
+                arguments: "{}".into(),
+            },
+        ];
+        let mut weather = msg("tool", Some(r#"{"temp":18}"#));
+        weather.tool_call_id = Some("call_a".into());
+        let mut time = msg("tool", Some("12:00"));
+        time.tool_call_id = Some("call_b".into());
+
+        let contents = VertexConnector::map_messages(&[
+            msg("user", Some("hi")),
+            assistant.clone(),
+            weather.clone(),
+            time,
+        ])
+        .unwrap();
+        assert_eq!(contents.as_array().unwrap().len(), 3);
+        assert_eq!(contents[1]["parts"][0]["functionCall"]["args"]["city"], "Paris");
+        let responses = &contents[2]["parts"];
+        assert_eq!(responses[0]["functionResponse"]["name"], "get_weather");
+        assert_eq!(responses[0]["functionResponse"]["response"]["temp"], 18);
+        assert_eq!(responses[1]["functionResponse"]["response"]["content"], "12:00");
+
+        let mut orphan = msg("tool", Some("12:00"));
+        orphan.tool_call_id = Some("call_c".into());
+        let err =
+            VertexConnector::map_messages(&[msg("user", Some("hi")), assistant, weather, orphan])
+                .unwrap_err();
+        assert!(
+            matches!(&err, ConnectorError::Invalid(m) if m.contains("tool_call_id \"call_c\"")),
+            "{:?}",
+            err
+        );
+
+        let reply = json!({"candidates": [{
+            "content": {"parts": [{"functionCall": {"name": "get_weather", "args": {"city": "Rome"}}}]},
+            "finishReason": "STOP"
+        }]});
+        let mut next = 0;
+        let (text, calls) = VertexConnector::parse_parts(&reply, &mut next);
+        assert!(text.is_empty());
+        assert_eq!(calls[0].name.as_deref(), Some("get_weather"));
+        assert_eq!(calls[0].arguments.as_deref(), Some(r#"{"city":"Rome"}"#));
+        assert_eq!(
+            VertexConnector::finish_with_calls(VertexConnector::finish_reason(&reply), true),
+            Some(FinishReason::ToolCalls)
+        );
+    }
+}
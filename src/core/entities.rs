@@ -21,6 +21,15 @@ pub enum ContentPart {
     },
 }
 
+/// A tool invocation made by the assistant
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    /// JSON-encoded arguments, as produced by the model
+    pub arguments: String,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct UnifiedMessage {
     pub role: String, // "system" | "user" | "assistant" | "tool"
@@ -28,6 +37,12 @@ pub struct UnifiedMessage {
     pub content: Vec<ContentPart>,
     #[serde(default)]
     pub name: Option<String>,
+    /// Tools the assistant called in this turn (role "assistant")
+    #[serde(default)]
+    pub tool_calls: Vec<ToolCall>,
+    /// Call this message answers (role "tool")
+    #[serde(default)]
+    pub tool_call_id: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -38,6 +53,19 @@ pub struct ToolSpec {
     pub json_schema: serde_json::Value,
 }
 
+/// Whether and which tool the model must call
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ToolChoice {
+    Auto,
+    None,
+    /// Any tool, but at least one
+    Required,
+    Tool {
+        name: String,
+    },
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct UnifiedRequest {
     pub logical_model: String,
@@ -45,7 +73,7 @@ pub struct UnifiedRequest {
     #[serde(default)]
     pub tools: Option<Vec<ToolSpec>>,
     #[serde(default)]
-    pub tool_choice: Option<String>,
+    pub tool_choice: Option<ToolChoice>,
     #[serde(default)]
     pub max_output_tokens: Option<u32>,
     #[serde(default)]
@@ -58,12 +86,39 @@ pub struct UnifiedRequest {
     pub extra: serde_json::Value,
 }
 
+/// Incremental piece of a tool call. Streaming connectors send the id and
+/// name first and the arguments in fragments; `index` ties them together.
+/// Non-streaming responses carry each call complete in a single delta.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ToolCallDelta {
+    pub index: u32,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Fragment of the JSON-encoded arguments
+    #[serde(default)]
+    pub arguments: Option<String>,
+}
+
+/// Why the model stopped generating
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    Stop,
+    Length,
+    ToolCalls,
+    ContentFilter,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct UnifiedChunk {
     #[serde(default)]
     pub text_delta: Option<String>,
     #[serde(default)]
-    pub tool_call_delta: Option<serde_json::Value>,
+    pub tool_calls: Vec<ToolCallDelta>,
+    #[serde(default)]
+    pub finish_reason: Option<FinishReason>,
     pub done: bool,
     #[serde(default)]
     pub provider_events: Option<serde_json::Value>,
@@ -1,10 +1,13 @@
 use async_trait::async_trait;
+use rust_decimal::Decimal;
 use sqlx::PgPool;
 use uuid::Uuid;
 use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
 
 // Re-export BillingTransaction from billing module
 pub use crate::billing::BillingTransaction;
+use crate::billing::rollup::SummaryPeriod;
 
 /// Summary of costs for a time period
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -13,7 +16,118 @@ pub struct CostSummary {
     pub successful_requests: i64,
     pub failed_requests: i64,
     pub total_tokens: i64,
-    pub total_cost: f64,
+    /// Provider cost of the requests
+    pub upstream_cost: Decimal,
+    /// Cost charged to the tenant
+    pub billed_cost: Decimal,
+}
+
+/// Filters for transaction queries; all given filters must match
+#[derive(Debug, Clone, Default)]
+pub struct TransactionFilter {
+    pub tenant_id: Option<String>,
+    pub api_key_id: Option<Uuid>,
+    /// Inclusive
+    pub start: Option<time::OffsetDateTime>,
+    /// Exclusive
+    pub end: Option<time::OffsetDateTime>,
+    pub logical_model: Option<String>,
+    pub provider: Option<String>,
+    pub status: Option<String>,
+    /// Minimum `billed_cost`
+    pub min_cost: Option<Decimal>,
+}
+
+/// Position after the last transaction of a page. Transactions are ordered
+/// newest first by `(created_at, id)`, so pages stay stable while new rows
+/// are inserted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionCursor {
+    pub created_at: time::OffsetDateTime,
+    pub id: Uuid,
+}
+
+impl TransactionCursor {
+    /// Opaque URL-safe token
+    pub fn encode(&self) -> String {
+        use base64::Engine;
+        base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(format!("{}:{}", self.created_at.unix_timestamp_nanos(), self.id))
+    }
+
+    pub fn decode(token: &str) -> Option<Self> {
+        use base64::Engine;
+        let raw = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(token).ok()?;
+        let (nanos, id) = std::str::from_utf8(&raw).ok()?.split_once(':')?;
+        Some(Self {
+            created_at: time::OffsetDateTime::from_unix_timestamp_nanos(nanos.parse().ok()?).ok()?,
+            id: id.parse().ok()?,
+        })
+    }
+}
+
+/// One page of transactions
+#[derive(Debug, Clone)]
+pub struct TransactionPage {
+    pub transactions: Vec<BillingTransaction>,
+    /// Where the next page starts; `None` on the last page
+    pub next_cursor: Option<TransactionCursor>,
+}
+
+/// Usage of one model or provider within a summary period
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UsageBreakdown {
+    pub requests: i64,
+    pub tokens: i64,
+    pub upstream_cost: Decimal,
+    pub billed_cost: Decimal,
+}
+
+/// One period of `tenant_billing_summary`
+#[derive(Debug, Clone, Serialize)]
+pub struct BillingSummary {
+    pub tenant_id: String,
+    /// `None` when the API keys of the tenant are combined
+    pub api_key_id: Option<Uuid>,
+    pub period_type: SummaryPeriod,
+    #[serde(with = "time::serde::rfc3339")]
+    pub period_start: time::OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub period_end: time::OffsetDateTime,
+    pub total_requests: i64,
+    pub successful_requests: i64,
+    pub failed_requests: i64,
+    pub total_tokens: i64,
+    pub upstream_cost: Decimal,
+    pub billed_cost: Decimal,
+    /// Keyed by logical model
+    pub model_breakdown: HashMap<String, UsageBreakdown>,
+    /// Keyed by provider
+    pub provider_breakdown: HashMap<String, UsageBreakdown>,
+}
+
+impl BillingSummary {
+    /// Add another key's summary of the same period
+    fn absorb(&mut self, other: BillingSummary) {
+        self.total_requests += other.total_requests;
+        self.successful_requests += other.successful_requests;
+        self.failed_requests += other.failed_requests;
+        self.total_tokens += other.total_tokens;
+        self.upstream_cost += other.upstream_cost;
+        self.billed_cost += other.billed_cost;
+        for (mine, theirs) in [
+            (&mut self.model_breakdown, other.model_breakdown),
+            (&mut self.provider_breakdown, other.provider_breakdown),
+        ] {
+            for (name, usage) in theirs {
+                let entry = mine.entry(name).or_default();
+                entry.requests += usage.requests;
+                entry.tokens += usage.tokens;
+                entry.upstream_cost += usage.upstream_cost;
+                entry.billed_cost += usage.billed_cost;
+            }
+        }
+    }
 }
 
 /// Trait for billing data storage
@@ -22,13 +136,13 @@ pub trait BillingStore: Send + Sync {
     /// Insert a billing transaction (idempotent by request_id)
     async fn insert_transaction(&self, tx: BillingTransaction) -> Result<(), sqlx::Error>;
 
-    /// Get transactions for a tenant with pagination
-    async fn get_transactions_by_tenant(
+    /// Transactions matching `filter`, newest first, starting after `cursor`
+    async fn query_transactions(
         &self,
-        tenant_id: &str,
+        filter: &TransactionFilter,
+        cursor: Option<TransactionCursor>,
         limit: i64,
-        offset: i64,
-    ) -> Result<Vec<BillingTransaction>, sqlx::Error>;
+    ) -> Result<TransactionPage, sqlx::Error>;
 
     /// Get cost summary for a tenant within a time range
     async fn get_cost_summary(
@@ -38,13 +152,50 @@ pub trait BillingStore: Send + Sync {
         end: time::OffsetDateTime,
     ) -> Result<CostSummary, sqlx::Error>;
 
-    /// Get transactions by API key
-    async fn get_transactions_by_api_key(
+    /// Get cost summary for a single API key within a time range
+    async fn get_api_key_cost_summary(
         &self,
         api_key_id: Uuid,
-        limit: i64,
-        offset: i64,
-    ) -> Result<Vec<BillingTransaction>, sqlx::Error>;
+        start: time::OffsetDateTime,
+        end: time::OffsetDateTime,
+    ) -> Result<CostSummary, sqlx::Error>;
+
+    /// Rebuild the summary of one period for every tenant and key from its
+    /// transactions (idempotent)
+    async fn rollup_period(
+        &self,
+        period: SummaryPeriod,
+        start: time::OffsetDateTime,
+        end: time::OffsetDateTime,
+    ) -> Result<u64, sqlx::Error>;
+
+    /// UTC days of transactions recorded within `[since, until)`, whenever
+    /// their requests ran
+    async fn days_recorded_between(
+        &self,
+        since: time::OffsetDateTime,
+        until: time::OffsetDateTime,
+    ) -> Result<Vec<time::OffsetDateTime>, sqlx::Error>;
+
+    /// Transactions recorded before this are reflected in the summary
+    async fn rollup_watermark(&self) -> Result<Option<time::OffsetDateTime>, sqlx::Error>;
+
+    async fn set_rollup_watermark(&self, watermark: time::OffsetDateTime) -> Result<(), sqlx::Error>;
+
+    /// Summaries of periods starting within `[start, end)`, oldest first; the
+    /// tenant's keys are combined unless `api_key_id` is given
+    async fn get_billing_summaries(
+        &self,
+        tenant_id: &str,
+        api_key_id: Option<Uuid>,
+        period: SummaryPeriod,
+        start: time::OffsetDateTime,
+        end: time::OffsetDateTime,
+    ) -> Result<Vec<BillingSummary>, sqlx::Error>;
+}
+
+fn breakdown(value: serde_json::Value) -> Result<HashMap<String, UsageBreakdown>, sqlx::Error> {
+    serde_json::from_value(value).map_err(|e| sqlx::Error::Decode(Box::new(e)))
 }
 
 /// PostgreSQL implementation of BillingStore
@@ -56,6 +207,47 @@ impl PgBillingStore {
     pub fn new(pool: PgPool) -> Self {
         Self { pool }
     }
+
+    /// Totals over a tenant, an API key, or both
+    async fn cost_summary(
+        &self,
+        tenant_id: Option<&str>,
+        api_key_id: Option<Uuid>,
+        start: time::OffsetDateTime,
+        end: time::OffsetDateTime,
+    ) -> Result<CostSummary, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT
+                COUNT(*) as total_requests,
+                COALESCE(SUM(total_tokens), 0)::bigint as total_tokens,
+                COALESCE(SUM(upstream_cost), 0) as "upstream_cost!",
+                COALESCE(SUM(billed_cost), 0) as "billed_cost!",
+                COALESCE(SUM(CASE WHEN status = 'success' THEN 1 ELSE 0 END), 0) as successful_requests,
+                COALESCE(SUM(CASE WHEN status != 'success' THEN 1 ELSE 0 END), 0) as failed_requests
+            FROM billing_transactions
+            WHERE ($1::varchar IS NULL OR tenant_id = $1)
+              AND ($2::uuid IS NULL OR api_key_id = $2)
+              AND created_at >= $3
+              AND created_at < $4
+            "#,
+            tenant_id,
+            api_key_id,
+            start,
+            end
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(CostSummary {
+            total_requests: row.total_requests.unwrap_or(0),
+            successful_requests: row.successful_requests.unwrap_or(0),
+            failed_requests: row.failed_requests.unwrap_or(0),
+            total_tokens: row.total_tokens.unwrap_or(0),
+            upstream_cost: row.upstream_cost,
+            billed_cost: row.billed_cost,
+        })
+    }
 }
 
 #[async_trait]
@@ -66,9 +258,13 @@ impl BillingStore for PgBillingStore {
             INSERT INTO billing_transactions (
                 id, tenant_id, api_key_id, request_id, logical_model, provider, provider_model_id,
                 prompt_tokens, completion_tokens, reasoning_tokens, cached_prompt_tokens, total_tokens,
-                prompt_cost, completion_cost, reasoning_cost, cache_read_cost, request_cost, total_cost,
-                pricing_snapshot, response_time_ms, status, error_message, created_at
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23)
+                prompt_cost, completion_cost, reasoning_cost, cache_read_cost, request_cost, upstream_cost,
+                billed_cost, pricing_snapshot, response_time_ms, status, error_message, created_at,
+                attempts, time_to_first_token_ms, usage_source
+            ) VALUES (
+                $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12,
+                $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27
+            )
             ON CONFLICT (request_id) DO NOTHING
             "#,
             tx.id,
@@ -88,12 +284,16 @@ impl BillingStore for PgBillingStore {
             tx.reasoning_cost,
             tx.cache_read_cost,
             tx.request_cost,
-            tx.total_cost,
+            tx.upstream_cost,
+            tx.billed_cost,
             tx.pricing_snapshot,
             tx.response_time_ms,
             tx.status,
             tx.error_message,
-            tx.created_at
+            tx.created_at,
+            tx.attempts,
+            tx.time_to_first_token_ms,
+            tx.usage_source
         )
         .execute(&self.pool)
         .await?;
@@ -101,32 +301,55 @@ impl BillingStore for PgBillingStore {
         Ok(())
     }
 
-    async fn get_transactions_by_tenant(
+    async fn query_transactions(
         &self,
-        tenant_id: &str,
+        filter: &TransactionFilter,
+        cursor: Option<TransactionCursor>,
         limit: i64,
-        offset: i64,
-    ) -> Result<Vec<BillingTransaction>, sqlx::Error> {
+    ) -> Result<TransactionPage, sqlx::Error> {
         let rows = sqlx::query!(
             r#"
             SELECT
                 id, tenant_id, api_key_id, request_id, logical_model, provider, provider_model_id,
                 prompt_tokens, completion_tokens, reasoning_tokens, cached_prompt_tokens, total_tokens,
-                prompt_cost, completion_cost, reasoning_cost, cache_read_cost, request_cost, total_cost,
-                pricing_snapshot, response_time_ms, status, error_message, created_at
+                prompt_cost,
+                completion_cost,
+                reasoning_cost,
+                cache_read_cost,
+                request_cost,
+                upstream_cost,
+                billed_cost,
+                pricing_snapshot, response_time_ms, status, error_message, created_at, attempts,
+                time_to_first_token_ms, usage_source
             FROM billing_transactions
-            WHERE tenant_id = $1
-            ORDER BY created_at DESC
-            LIMIT $2 OFFSET $3
+            WHERE ($1::varchar IS NULL OR tenant_id = $1)
+              AND ($2::uuid IS NULL OR api_key_id = $2)
+              AND ($3::timestamptz IS NULL OR created_at >= $3)
+              AND ($4::timestamptz IS NULL OR created_at < $4)
+              AND ($5::varchar IS NULL OR logical_model = $5)
+              AND ($6::varchar IS NULL OR provider = $6)
+              AND ($7::varchar IS NULL OR status = $7)
+              AND ($8::numeric IS NULL OR billed_cost >= $8)
+              AND ($9::timestamptz IS NULL OR (created_at, id) < ($9, $10::uuid))
+            ORDER BY created_at DESC, id DESC
+            LIMIT $11
             "#,
-            tenant_id,
-            limit,
-            offset
+            filter.tenant_id,
+            filter.api_key_id,
+            filter.start,
+            filter.end,
+            filter.logical_model,
+            filter.provider,
+            filter.status,
+            filter.min_cost,
+            cursor.map(|c| c.created_at),
+            cursor.map(|c| c.id),
+            limit + 1
         )
         .fetch_all(&self.pool)
         .await?;
 
-        let transactions = rows
+        let mut transactions: Vec<BillingTransaction> = rows
             .into_iter()
             .map(|row| BillingTransaction {
                 id: row.id,
@@ -146,16 +369,33 @@ impl BillingStore for PgBillingStore {
                 reasoning_cost: row.reasoning_cost,
                 cache_read_cost: row.cache_read_cost,
                 request_cost: row.request_cost,
-                total_cost: row.total_cost,
+                upstream_cost: row.upstream_cost,
+                billed_cost: row.billed_cost,
                 pricing_snapshot: row.pricing_snapshot,
                 response_time_ms: row.response_time_ms.unwrap_or(0),
                 status: row.status,
                 error_message: row.error_message,
                 created_at: row.created_at,
+                attempts: row.attempts,
+                time_to_first_token_ms: row.time_to_first_token_ms,
+                usage_source: row.usage_source,
             })
             .collect();
 
-        Ok(transactions)
+        // One extra row tells whether another page follows
+        let next_cursor = if transactions.len() as i64 > limit {
+            transactions.truncate(limit as usize);
+            transactions.last().map(|tx| TransactionCursor {
+                created_at: tx.created_at,
+                id: tx.id,
+            })
+        } else {
+            None
+        };
+        Ok(TransactionPage {
+            transactions,
+            next_cursor,
+        })
     }
 
     async fn get_cost_summary(
@@ -164,89 +404,248 @@ impl BillingStore for PgBillingStore {
         start: time::OffsetDateTime,
         end: time::OffsetDateTime,
     ) -> Result<CostSummary, sqlx::Error> {
-        let row = sqlx::query!(
+        self.cost_summary(Some(tenant_id), None, start, end).await
+    }
+
+    async fn get_api_key_cost_summary(
+        &self,
+        api_key_id: Uuid,
+        start: time::OffsetDateTime,
+        end: time::OffsetDateTime,
+    ) -> Result<CostSummary, sqlx::Error> {
+        self.cost_summary(None, Some(api_key_id), start, end).await
+    }
+
+    async fn rollup_period(
+        &self,
+        period: SummaryPeriod,
+        start: time::OffsetDateTime,
+        end: time::OffsetDateTime,
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
             r#"
+            INSERT INTO tenant_billing_summary (
+                tenant_id, api_key_id, period_start, period_end, period_type,
+                total_requests, successful_requests, failed_requests, total_tokens,
+                upstream_cost, billed_cost, model_breakdown, provider_breakdown
+            )
             SELECT
-                COUNT(*) as total_requests,
-                COALESCE(SUM(total_tokens), 0) as total_tokens,
-                COALESCE(SUM(total_cost), 0) as total_cost,
-                COALESCE(SUM(CASE WHEN status = 'success' THEN 1 ELSE 0 END), 0) as successful_requests,
-                COALESCE(SUM(CASE WHEN status != 'success' THEN 1 ELSE 0 END), 0) as failed_requests
-            FROM billing_transactions
-            WHERE tenant_id = $1
-              AND created_at >= $2
-              AND created_at < $3
+                t.tenant_id, t.api_key_id, $1, $2, $3,
+                COUNT(*),
+                COUNT(*) FILTER (WHERE t.status = 'success'),
+                COUNT(*) FILTER (WHERE t.status != 'success'),
+                COALESCE(SUM(t.total_tokens), 0),
+                COALESCE(SUM(t.upstream_cost), 0),
+                COALESCE(SUM(t.billed_cost), 0),
+                (
+                    SELECT jsonb_object_agg(m.logical_model, jsonb_build_object(
+                        'requests', m.requests, 'tokens', m.tokens,
+                        'upstream_cost', m.upstream_cost::text, 'billed_cost', m.billed_cost::text
+                    ))
+                    FROM (
+                        SELECT logical_model, COUNT(*) as requests, COALESCE(SUM(total_tokens), 0) as tokens,
+                               SUM(upstream_cost) as upstream_cost, SUM(billed_cost) as billed_cost
+                        FROM billing_transactions
+                        WHERE tenant_id = t.tenant_id AND api_key_id = t.api_key_id
+                          AND created_at >= $1 AND created_at < $2
+                        GROUP BY logical_model
+                    ) m
+                ),
+                (
+                    SELECT jsonb_object_agg(p.provider, jsonb_build_object(
+                        'requests', p.requests, 'tokens', p.tokens,
+                        'upstream_cost', p.upstream_cost::text, 'billed_cost', p.billed_cost::text
+                    ))
+                    FROM (
+                        SELECT provider, COUNT(*) as requests, COALESCE(SUM(total_tokens), 0) as tokens,
+                               SUM(upstream_cost) as upstream_cost, SUM(billed_cost) as billed_cost
+                        FROM billing_transactions
+                        WHERE tenant_id = t.tenant_id AND api_key_id = t.api_key_id
+                          AND created_at >= $1 AND created_at < $2
+                        GROUP BY provider
+                    ) p
+                )
+            FROM billing_transactions t
+            WHERE t.created_at >= $1 AND t.created_at < $2
+            GROUP BY t.tenant_id, t.api_key_id
+            ON CONFLICT (tenant_id, api_key_id, period_type, period_start) DO UPDATE SET
+                period_end = EXCLUDED.period_end,
+                total_requests = EXCLUDED.total_requests,
+                successful_requests = EXCLUDED.successful_requests,
+                failed_requests = EXCLUDED.failed_requests,
+                total_tokens = EXCLUDED.total_tokens,
+                upstream_cost = EXCLUDED.upstream_cost,
+                billed_cost = EXCLUDED.billed_cost,
+                model_breakdown = EXCLUDED.model_breakdown,
+                provider_breakdown = EXCLUDED.provider_breakdown
             "#,
-            tenant_id,
             start,
-            end
+            end,
+            period.as_str()
         )
-        .fetch_one(&self.pool)
+        .execute(&self.pool)
         .await?;
 
-        Ok(CostSummary {
-            total_requests: row.total_requests.unwrap_or(0),
-            successful_requests: row.successful_requests.unwrap_or(0),
-            failed_requests: row.failed_requests.unwrap_or(0),
-            total_tokens: row.total_tokens.unwrap_or(0),
-            total_cost: row.total_cost.unwrap_or(0.0),
-        })
+        Ok(result.rows_affected())
     }
 
-    async fn get_transactions_by_api_key(
+    async fn days_recorded_between(
         &self,
-        api_key_id: Uuid,
-        limit: i64,
-        offset: i64,
-    ) -> Result<Vec<BillingTransaction>, sqlx::Error> {
+        since: time::OffsetDateTime,
+        until: time::OffsetDateTime,
+    ) -> Result<Vec<time::OffsetDateTime>, sqlx::Error> {
         let rows = sqlx::query!(
             r#"
-            SELECT
-                id, tenant_id, api_key_id, request_id, logical_model, provider, provider_model_id,
-                prompt_tokens, completion_tokens, reasoning_tokens, cached_prompt_tokens, total_tokens,
-                prompt_cost, completion_cost, reasoning_cost, cache_read_cost, request_cost, total_cost,
-                pricing_snapshot, response_time_ms, status, error_message, created_at
+            SELECT DISTINCT date_trunc('day', created_at, 'UTC') as "day!"
             FROM billing_transactions
-            WHERE api_key_id = $1
-            ORDER BY created_at DESC
-            LIMIT $2 OFFSET $3
+            WHERE recorded_at >= $1 AND recorded_at < $2
+            ORDER BY 1
             "#,
+            since,
+            until
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| row.day).collect())
+    }
+
+    async fn rollup_watermark(&self) -> Result<Option<time::OffsetDateTime>, sqlx::Error> {
+        let row = sqlx::query!(
+            "SELECT watermark FROM billing_rollup_state WHERE name = 'tenant_billing_summary'"
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| row.watermark))
+    }
+
+    async fn set_rollup_watermark(&self, watermark: time::OffsetDateTime) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO billing_rollup_state (name, watermark)
+            VALUES ('tenant_billing_summary', $1)
+            ON CONFLICT (name) DO UPDATE SET watermark = EXCLUDED.watermark
+            "#,
+            watermark
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_billing_summaries(
+        &self,
+        tenant_id: &str,
+        api_key_id: Option<Uuid>,
+        period: SummaryPeriod,
+        start: time::OffsetDateTime,
+        end: time::OffsetDateTime,
+    ) -> Result<Vec<BillingSummary>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                tenant_id, api_key_id, period_start, period_end,
+                total_requests, successful_requests, failed_requests, total_tokens,
+                upstream_cost, billed_cost, model_breakdown, provider_breakdown
+            FROM tenant_billing_summary
+            WHERE tenant_id = $1
+              AND ($2::uuid IS NULL OR api_key_id = $2)
+              AND period_type = $3
+              AND period_start >= $4
+              AND period_start < $5
+            ORDER BY period_start
+            "#,
+            tenant_id,
             api_key_id,
-            limit,
-            offset
+            period.as_str(),
+            start,
+            end
         )
         .fetch_all(&self.pool)
         .await?;
 
-        let transactions = rows
-            .into_iter()
-            .map(|row| BillingTransaction {
-                id: row.id,
+        let mut summaries: Vec<BillingSummary> = Vec::new();
+        for row in rows {
+            let mut summary = BillingSummary {
                 tenant_id: row.tenant_id,
-                api_key_id: row.api_key_id,
-                request_id: row.request_id,
-                logical_model: row.logical_model,
-                provider: row.provider,
-                provider_model_id: row.provider_model_id,
-                prompt_tokens: row.prompt_tokens,
-                completion_tokens: row.completion_tokens,
-                reasoning_tokens: row.reasoning_tokens,
-                cached_prompt_tokens: row.cached_prompt_tokens,
+                api_key_id: Some(row.api_key_id),
+                period_type: period,
+                period_start: row.period_start,
+                period_end: row.period_end,
+                total_requests: row.total_requests,
+                successful_requests: row.successful_requests,
+                failed_requests: row.failed_requests,
                 total_tokens: row.total_tokens,
-                prompt_cost: row.prompt_cost,
-                completion_cost: row.completion_cost,
-                reasoning_cost: row.reasoning_cost,
-                cache_read_cost: row.cache_read_cost,
-                request_cost: row.request_cost,
-                total_cost: row.total_cost,
-                pricing_snapshot: row.pricing_snapshot,
-                response_time_ms: row.response_time_ms.unwrap_or(0),
-                status: row.status,
-                error_message: row.error_message,
-                created_at: row.created_at,
-            })
-            .collect();
+                upstream_cost: row.upstream_cost,
+                billed_cost: row.billed_cost,
+                model_breakdown: breakdown(row.model_breakdown)?,
+                provider_breakdown: breakdown(row.provider_breakdown)?,
+            };
+            if api_key_id.is_none() {
+                if let Some(last) = summaries.last_mut().filter(|last| last.period_start == summary.period_start) {
+                    last.absorb(summary);
+                    continue;
+                }
+                summary.api_key_id = None;
+            }
+            summaries.push(summary);
+        }
+        Ok(summaries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(api_key_id: Uuid, model: &str, requests: i64, cost: Decimal) -> BillingSummary {
+        let usage = UsageBreakdown {
+            requests,
+            tokens: requests * 100,
+            upstream_cost: cost,
+            billed_cost: cost,
+        };
+        BillingSummary {
+            tenant_id: "acme".into(),
+            api_key_id: Some(api_key_id),
+            period_type: SummaryPeriod::Daily,
+            period_start: time::OffsetDateTime::UNIX_EPOCH,
+            period_end: time::OffsetDateTime::UNIX_EPOCH + time::Duration::days(1),
+            total_requests: requests,
+            successful_requests: requests,
+            failed_requests: 0,
+            total_tokens: usage.tokens,
+            upstream_cost: cost,
+            billed_cost: cost,
+            model_breakdown: HashMap::from([(model.to_string(), usage.clone())]),
+            provider_breakdown: HashMap::from([("OpenRouter".to_string(), usage)]),
+        }
+    }
+
+    #[test]
+    fn test_absorb_combines_breakdowns() {
+        let mut combined = summary(Uuid::new_v4(), "claude-sonnet-4.5", 2, Decimal::new(3, 2));
+        combined.absorb(summary(Uuid::new_v4(), "gpt-4o", 1, Decimal::new(1, 2)));
+        combined.absorb(summary(Uuid::new_v4(), "gpt-4o", 3, Decimal::new(2, 2)));
+
+        assert_eq!(combined.total_requests, 6);
+        assert_eq!(combined.billed_cost, Decimal::new(6, 2));
+        assert_eq!(combined.model_breakdown["gpt-4o"].requests, 4);
+        assert_eq!(combined.model_breakdown["gpt-4o"].upstream_cost, Decimal::new(3, 2));
+        assert_eq!(combined.provider_breakdown["OpenRouter"].tokens, 600);
+    }
 
-        Ok(transactions)
+    #[test]
+    fn test_cursor_round_trip() {
+        let cursor = TransactionCursor {
+            created_at: time::OffsetDateTime::from_unix_timestamp_nanos(1_750_000_000_123_456_789).unwrap(),
+            id: Uuid::new_v4(),
+        };
+        let token = cursor.encode();
+        assert!(token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(TransactionCursor::decode(&token), Some(cursor));
+        assert_eq!(TransactionCursor::decode("not a cursor"), None);
+        assert_eq!(TransactionCursor::decode(""), None);
     }
 }
@@ -0,0 +1,196 @@
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::billing::budget::{Budget, BudgetPeriod};
+
+/// Budget to create, or to replace for the same scope and period
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewBudget {
+    pub tenant_id: String,
+    #[serde(default)]
+    pub api_key_id: Option<Uuid>,
+    pub period: BudgetPeriod,
+    pub amount: Decimal,
+    /// Defaults to 50, 80 and 100
+    #[serde(default)]
+    pub thresholds: Option<Vec<i32>>,
+}
+
+/// Trait for budget storage
+#[async_trait]
+pub trait BudgetStore: Send + Sync {
+    /// Budgets a transaction of this key counts towards: the tenant's and the key's own
+    async fn budgets_for(&self, tenant_id: &str, api_key_id: Uuid) -> Result<Vec<Budget>, sqlx::Error>;
+
+    /// All budgets of a tenant, or only those of one key
+    async fn list_budgets(&self, tenant_id: &str, api_key_id: Option<Uuid>) -> Result<Vec<Budget>, sqlx::Error>;
+
+    /// Create or replace the budget of this scope and period
+    async fn upsert_budget(&self, budget: NewBudget) -> Result<Budget, sqlx::Error>;
+
+    /// Claim the alert for a crossed threshold; None when it was already delivered
+    /// this period or another attempt is under way
+    async fn claim_alert(
+        &self,
+        budget_id: Uuid,
+        period_start: time::OffsetDateTime,
+        threshold: i32,
+        spend: Decimal,
+        request_id: &str,
+    ) -> Result<Option<Uuid>, sqlx::Error>;
+
+    /// Mark a claimed alert as sent so it is not sent again
+    async fn mark_alert_delivered(&self, alert_id: Uuid) -> Result<(), sqlx::Error>;
+}
+
+/// PostgreSQL implementation of BudgetStore
+pub struct PgBudgetStore {
+    pool: PgPool,
+}
+
+impl PgBudgetStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+fn period(s: &str) -> Result<BudgetPeriod, sqlx::Error> {
+    BudgetPeriod::parse(s).ok_or_else(|| sqlx::Error::Decode(format!("unknown budget period '{}'", s).into()))
+}
+
+#[async_trait]
+impl BudgetStore for PgBudgetStore {
+    async fn budgets_for(&self, tenant_id: &str, api_key_id: Uuid) -> Result<Vec<Budget>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, tenant_id, api_key_id, period, amount, thresholds
+            FROM budgets
+            WHERE tenant_id = $1
+              AND (api_key_id = $2 OR api_key_id IS NULL)
+            "#,
+            tenant_id,
+            api_key_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(Budget {
+                    id: row.id,
+                    tenant_id: row.tenant_id,
+                    api_key_id: row.api_key_id,
+                    period: period(&row.period)?,
+                    amount: row.amount,
+                    thresholds: row.thresholds,
+                })
+            })
+            .collect()
+    }
+
+    async fn list_budgets(&self, tenant_id: &str, api_key_id: Option<Uuid>) -> Result<Vec<Budget>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, tenant_id, api_key_id, period, amount, thresholds
+            FROM budgets
+            WHERE tenant_id = $1
+              AND ($2::uuid IS NULL OR api_key_id = $2)
+            ORDER BY api_key_id NULLS FIRST, period
+            "#,
+            tenant_id,
+            api_key_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(Budget {
+                    id: row.id,
+                    tenant_id: row.tenant_id,
+                    api_key_id: row.api_key_id,
+                    period: period(&row.period)?,
+                    amount: row.amount,
+                    thresholds: row.thresholds,
+                })
+            })
+            .collect()
+    }
+
+    async fn upsert_budget(&self, budget: NewBudget) -> Result<Budget, sqlx::Error> {
+        let mut thresholds = budget.thresholds.unwrap_or_else(|| vec![50, 80, 100]);
+        thresholds.sort_unstable();
+        thresholds.dedup();
+
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO budgets (tenant_id, api_key_id, period, amount, thresholds)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (tenant_id, COALESCE(api_key_id, '00000000-0000-0000-0000-000000000000'::uuid), period)
+            DO UPDATE SET amount = EXCLUDED.amount, thresholds = EXCLUDED.thresholds
+            RETURNING id
+            "#,
+            budget.tenant_id,
+            budget.api_key_id,
+            budget.period.as_str(),
+            budget.amount,
+            &thresholds
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(Budget {
+            id: row.id,
+            tenant_id: budget.tenant_id,
+            api_key_id: budget.api_key_id,
+            period: budget.period,
+            amount: budget.amount,
+            thresholds,
+        })
+    }
+
+    async fn claim_alert(
+        &self,
+        budget_id: Uuid,
+        period_start: time::OffsetDateTime,
+        threshold: i32,
+        spend: Decimal,
+        request_id: &str,
+    ) -> Result<Option<Uuid>, sqlx::Error> {
+        // An undelivered alert is retried once its last attempt is a minute old
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO budget_alerts (budget_id, period_start, threshold, spend, request_id)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (budget_id, period_start, threshold) DO UPDATE
+            SET spend = EXCLUDED.spend, request_id = EXCLUDED.request_id, attempted_at = NOW()
+            WHERE budget_alerts.delivered_at IS NULL
+              AND budget_alerts.attempted_at < NOW() - INTERVAL '1 minute'
+            RETURNING id
+            "#,
+            budget_id,
+            period_start,
+            threshold,
+            spend,
+            request_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| r.id))
+    }
+
+    async fn mark_alert_delivered(&self, alert_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE budget_alerts SET delivered_at = NOW() WHERE id = $1",
+            alert_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
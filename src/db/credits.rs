@@ -0,0 +1,390 @@
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Prepaid balance of a tenant, or of one of its API keys
+#[derive(Debug, Clone, Serialize)]
+pub struct CreditAccount {
+    pub id: Uuid,
+    pub tenant_id: String,
+    /// Account pays for this key only; `None` for the tenant-wide account
+    pub api_key_id: Option<Uuid>,
+    pub balance: Decimal,
+    /// Held for requests still in flight
+    pub reserved: Decimal,
+}
+
+impl CreditAccount {
+    pub fn available(&self) -> Decimal {
+        self.balance - self.reserved
+    }
+}
+
+/// Credit held for one in-flight request until its cost is known
+#[derive(Debug, Clone)]
+pub struct Reservation {
+    pub account_id: Uuid,
+    pub request_id: String,
+    pub amount: Decimal,
+}
+
+/// Result of trying to hold credit for a request
+#[derive(Debug, Clone)]
+pub enum ReserveOutcome {
+    /// Neither the key nor its tenant has a prepaid account
+    NoAccount,
+    Reserved(Reservation),
+    Insufficient { available: Decimal },
+}
+
+/// Manual balance changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CreditEntryType {
+    TopUp,
+    Adjustment,
+}
+
+impl CreditEntryType {
+    fn as_str(self) -> &'static str {
+        match self {
+            CreditEntryType::TopUp => "top_up",
+            CreditEntryType::Adjustment => "adjustment",
+        }
+    }
+}
+
+/// One balance change
+#[derive(Debug, Clone, Serialize)]
+pub struct LedgerEntry {
+    pub id: Uuid,
+    pub entry_type: String,
+    /// Signed: top-ups are positive, debits negative
+    pub amount: Decimal,
+    pub balance_after: Decimal,
+    pub request_id: Option<String>,
+    pub note: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: time::OffsetDateTime,
+}
+
+/// Trait for prepaid balance storage
+#[async_trait]
+pub trait CreditStore: Send + Sync {
+    /// Hold `amount` on the account paying for this key (its own account,
+    /// else the tenant's). Fails when less than `amount` is available, or
+    /// when nothing is left at all.
+    async fn reserve(
+        &self,
+        tenant_id: &str,
+        api_key_id: Uuid,
+        request_id: &str,
+        amount: Decimal,
+    ) -> Result<ReserveOutcome, sqlx::Error>;
+
+    /// Release a reservation and debit the real cost (idempotent per request)
+    async fn settle(&self, reservation: &Reservation, cost: Decimal) -> Result<(), sqlx::Error>;
+
+    /// Release reservations whose request never settled, e.g. after a crash
+    async fn release_stale(&self, older_than: time::OffsetDateTime) -> Result<u64, sqlx::Error>;
+
+    /// Top up or adjust an account, creating it on first use
+    async fn credit(
+        &self,
+        tenant_id: &str,
+        api_key_id: Option<Uuid>,
+        entry_type: CreditEntryType,
+        amount: Decimal,
+        note: Option<String>,
+    ) -> Result<CreditAccount, sqlx::Error>;
+
+    /// Account of exactly this scope
+    async fn account(&self, tenant_id: &str, api_key_id: Option<Uuid>) -> Result<Option<CreditAccount>, sqlx::Error>;
+
+    /// Most recent ledger entries of an account
+    async fn ledger(&self, account_id: Uuid, limit: i64) -> Result<Vec<LedgerEntry>, sqlx::Error>;
+}
+
+/// PostgreSQL implementation of CreditStore
+pub struct PgCreditStore {
+    pool: PgPool,
+}
+
+impl PgCreditStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl CreditStore for PgCreditStore {
+    async fn reserve(
+        &self,
+        tenant_id: &str,
+        api_key_id: Uuid,
+        request_id: &str,
+        amount: Decimal,
+    ) -> Result<ReserveOutcome, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let Some(account) = sqlx::query!(
+            r#"
+            SELECT id, balance - reserved as "available!"
+            FROM credit_accounts
+            WHERE tenant_id = $1
+              AND (api_key_id = $2 OR api_key_id IS NULL)
+            ORDER BY api_key_id IS NULL
+            LIMIT 1
+            FOR UPDATE
+            "#,
+            tenant_id,
+            api_key_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(ReserveOutcome::NoAccount);
+        };
+
+        if account.available <= Decimal::ZERO || account.available < amount {
+            return Ok(ReserveOutcome::Insufficient {
+                available: account.available,
+            });
+        }
+
+        sqlx::query!(
+            "UPDATE credit_accounts SET reserved = reserved + $2 WHERE id = $1",
+            account.id,
+            amount
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "INSERT INTO credit_reservations (request_id, account_id, amount) VALUES ($1, $2, $3)",
+            request_id,
+            account.id,
+            amount
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(ReserveOutcome::Reserved(Reservation {
+            account_id: account.id,
+            request_id: request_id.to_string(),
+            amount,
+        }))
+    }
+
+    async fn settle(&self, reservation: &Reservation, cost: Decimal) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Already settled, or released as stale: only the debit is left to record
+        let held = sqlx::query!(
+            "DELETE FROM credit_reservations WHERE request_id = $1 RETURNING amount",
+            reservation.request_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .map(|row| row.amount)
+        .unwrap_or(Decimal::ZERO);
+
+        let balance = sqlx::query!(
+            r#"
+            UPDATE credit_accounts
+            SET reserved = GREATEST(reserved - $2, 0), balance = balance - $3
+            WHERE id = $1
+            RETURNING balance
+            "#,
+            reservation.account_id,
+            held,
+            cost
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .balance;
+
+        if cost > Decimal::ZERO {
+            let inserted = sqlx::query!(
+                r#"
+                INSERT INTO credit_ledger (account_id, entry_type, amount, balance_after, request_id)
+                VALUES ($1, 'debit', $2, $3, $4)
+                ON CONFLICT (request_id) WHERE entry_type = 'debit' DO NOTHING
+                "#,
+                reservation.account_id,
+                -cost,
+                balance,
+                reservation.request_id
+            )
+            .execute(&mut *tx)
+            .await?;
+            if inserted.rows_affected() == 0 {
+                // Debited before: keep the balance as it was
+                tx.rollback().await?;
+                return Ok(());
+            }
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn release_stale(&self, older_than: time::OffsetDateTime) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            WITH released AS (
+                DELETE FROM credit_reservations
+                WHERE created_at < $1
+                RETURNING account_id, amount
+            ), totals AS (
+                SELECT account_id, SUM(amount) as amount
+                FROM released
+                GROUP BY account_id
+            )
+            UPDATE credit_accounts a
+            SET reserved = GREATEST(a.reserved - totals.amount, 0)
+            FROM totals
+            WHERE a.id = totals.account_id
+            "#,
+            older_than
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn credit(
+        &self,
+        tenant_id: &str,
+        api_key_id: Option<Uuid>,
+        entry_type: CreditEntryType,
+        amount: Decimal,
+        note: Option<String>,
+    ) -> Result<CreditAccount, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO credit_accounts (tenant_id, api_key_id)
+            VALUES ($1, $2)
+            ON CONFLICT (tenant_id, COALESCE(api_key_id, '00000000-0000-0000-0000-000000000000'::uuid))
+            DO NOTHING
+            "#,
+            tenant_id,
+            api_key_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let row = sqlx::query!(
+            r#"
+            UPDATE credit_accounts
+            SET balance = balance + $3
+            WHERE tenant_id = $1 AND api_key_id IS NOT DISTINCT FROM $2
+            RETURNING id, balance, reserved
+            "#,
+            tenant_id,
+            api_key_id,
+            amount
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO credit_ledger (account_id, entry_type, amount, balance_after, note)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            row.id,
+            entry_type.as_str(),
+            amount,
+            row.balance,
+            note
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(CreditAccount {
+            id: row.id,
+            tenant_id: tenant_id.to_string(),
+            api_key_id,
+            balance: row.balance,
+            reserved: row.reserved,
+        })
+    }
+
+    async fn account(&self, tenant_id: &str, api_key_id: Option<Uuid>) -> Result<Option<CreditAccount>, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT id, tenant_id, api_key_id, balance, reserved
+            FROM credit_accounts
+            WHERE tenant_id = $1 AND api_key_id IS NOT DISTINCT FROM $2
+            "#,
+            tenant_id,
+            api_key_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| CreditAccount {
+            id: row.id,
+            tenant_id: row.tenant_id,
+            api_key_id: row.api_key_id,
+            balance: row.balance,
+            reserved: row.reserved,
+        }))
+    }
+
+    async fn ledger(&self, account_id: Uuid, limit: i64) -> Result<Vec<LedgerEntry>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, entry_type, amount, balance_after, request_id, note, created_at
+            FROM credit_ledger
+            WHERE account_id = $1
+            ORDER BY created_at DESC
+            LIMIT $2
+            "#,
+            account_id,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| LedgerEntry {
+                id: row.id,
+                entry_type: row.entry_type,
+                amount: row.amount,
+                balance_after: row.balance_after,
+                request_id: row.request_id,
+                note: row.note,
+                created_at: row.created_at,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ledger_timestamps_are_rfc3339() {
+        let entry = LedgerEntry {
+            id: Uuid::nil(),
+            entry_type: "debit".into(),
+            amount: Decimal::new(-125, 4),
+            balance_after: Decimal::new(499875, 4),
+            request_id: Some("req_1".into()),
+            note: None,
+            created_at: time::OffsetDateTime::from_unix_timestamp(1_750_150_800).unwrap(),
+        };
+        let json = serde_json::to_value(&entry).unwrap();
+        assert_eq!(json["created_at"], "2025-06-17T09:00:00Z");
+        assert_eq!(json["amount"], "-0.0125");
+    }
+}
@@ -0,0 +1,244 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::billing::invoice::{Invoice, InvoiceLine, InvoiceTotals};
+
+/// Trait for invoice storage
+#[async_trait]
+pub trait InvoiceStore: Send + Sync {
+    /// Billed usage of the tenant within `[start, end)` by API key and logical model
+    async fn invoice_lines(
+        &self,
+        tenant_id: &str,
+        start: time::OffsetDateTime,
+        end: time::OffsetDateTime,
+    ) -> Result<Vec<InvoiceLine>, sqlx::Error>;
+
+    /// Store a draft as the final invoice of its period. A period is only
+    /// finalized once: later calls return the invoice stored first.
+    async fn finalize(&self, draft: &Invoice) -> Result<Invoice, sqlx::Error>;
+
+    async fn finalized_invoice(
+        &self,
+        tenant_id: &str,
+        start: time::OffsetDateTime,
+        end: time::OffsetDateTime,
+    ) -> Result<Option<Invoice>, sqlx::Error>;
+
+    async fn get_invoice(&self, invoice_number: &str) -> Result<Option<Invoice>, sqlx::Error>;
+
+    /// Finalized invoices of a tenant, newest period first
+    async fn list_invoices(&self, tenant_id: &str) -> Result<Vec<Invoice>, sqlx::Error>;
+}
+
+/// PostgreSQL implementation of InvoiceStore
+pub struct PgInvoiceStore {
+    pool: PgPool,
+}
+
+impl PgInvoiceStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    async fn line_items(&self, invoice_id: Uuid) -> Result<Vec<InvoiceLine>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                api_key_id, logical_model, requests, prompt_tokens, completion_tokens,
+                total_tokens, upstream_cost, billed_cost
+            FROM invoice_line_items
+            WHERE invoice_id = $1
+            ORDER BY api_key_id, logical_model
+            "#,
+            invoice_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| InvoiceLine {
+                api_key_id: row.api_key_id,
+                logical_model: row.logical_model,
+                requests: row.requests,
+                prompt_tokens: row.prompt_tokens,
+                completion_tokens: row.completion_tokens,
+                total_tokens: row.total_tokens,
+                upstream_cost: row.upstream_cost,
+                billed_cost: row.billed_cost,
+            })
+            .collect())
+    }
+
+    /// Invoices matching the filters, with their line items
+    async fn invoices(
+        &self,
+        invoice_number: Option<&str>,
+        tenant_id: Option<&str>,
+        period: Option<(time::OffsetDateTime, time::OffsetDateTime)>,
+    ) -> Result<Vec<Invoice>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                id, invoice_number, tenant_id, period_start, period_end, total_requests,
+                total_tokens, upstream_cost, billed_cost, finalized_at
+            FROM invoices
+            WHERE ($1::varchar IS NULL OR invoice_number = $1)
+              AND ($2::varchar IS NULL OR tenant_id = $2)
+              AND ($3::timestamptz IS NULL OR (period_start = $3 AND period_end = $4))
+            ORDER BY period_start DESC
+            "#,
+            invoice_number,
+            tenant_id,
+            period.map(|(start, _)| start),
+            period.map(|(_, end)| end)
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut invoices = Vec::with_capacity(rows.len());
+        for row in rows {
+            invoices.push(Invoice {
+                invoice_number: row.invoice_number,
+                tenant_id: row.tenant_id,
+                period_start: row.period_start,
+                period_end: row.period_end,
+                finalized_at: Some(row.finalized_at),
+                totals: InvoiceTotals {
+                    requests: row.total_requests,
+                    total_tokens: row.total_tokens,
+                    upstream_cost: row.upstream_cost,
+                    billed_cost: row.billed_cost,
+                },
+                line_items: self.line_items(row.id).await?,
+            });
+        }
+        Ok(invoices)
+    }
+}
+
+#[async_trait]
+impl InvoiceStore for PgInvoiceStore {
+    async fn invoice_lines(
+        &self,
+        tenant_id: &str,
+        start: time::OffsetDateTime,
+        end: time::OffsetDateTime,
+    ) -> Result<Vec<InvoiceLine>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                api_key_id, logical_model,
+                COUNT(*) as "requests!",
+                COALESCE(SUM(prompt_tokens), 0)::bigint as "prompt_tokens!",
+                COALESCE(SUM(completion_tokens), 0)::bigint as "completion_tokens!",
+                COALESCE(SUM(total_tokens), 0)::bigint as "total_tokens!",
+                COALESCE(SUM(upstream_cost), 0) as "upstream_cost!",
+                COALESCE(SUM(billed_cost), 0) as "billed_cost!"
+            FROM billing_transactions
+            WHERE tenant_id = $1
+              AND created_at >= $2
+              AND created_at < $3
+            GROUP BY api_key_id, logical_model
+            ORDER BY api_key_id, logical_model
+            "#,
+            tenant_id,
+            start,
+            end
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| InvoiceLine {
+                api_key_id: row.api_key_id,
+                logical_model: row.logical_model,
+                requests: row.requests,
+                prompt_tokens: row.prompt_tokens,
+                completion_tokens: row.completion_tokens,
+                total_tokens: row.total_tokens,
+                upstream_cost: row.upstream_cost,
+                billed_cost: row.billed_cost,
+            })
+            .collect())
+    }
+
+    async fn finalize(&self, draft: &Invoice) -> Result<Invoice, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO invoices (
+                invoice_number, tenant_id, period_start, period_end,
+                total_requests, total_tokens, upstream_cost, billed_cost
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (tenant_id, period_start, period_end) DO NOTHING
+            RETURNING id
+            "#,
+            draft.invoice_number,
+            draft.tenant_id,
+            draft.period_start,
+            draft.period_end,
+            draft.totals.requests,
+            draft.totals.total_tokens,
+            draft.totals.upstream_cost,
+            draft.totals.billed_cost
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(row) = inserted {
+            for line in &draft.line_items {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO invoice_line_items (
+                        invoice_id, api_key_id, logical_model, requests, prompt_tokens,
+                        completion_tokens, total_tokens, upstream_cost, billed_cost
+                    )
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                    "#,
+                    row.id,
+                    line.api_key_id,
+                    line.logical_model,
+                    line.requests,
+                    line.prompt_tokens,
+                    line.completion_tokens,
+                    line.total_tokens,
+                    line.upstream_cost,
+                    line.billed_cost
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+        tx.commit().await?;
+
+        self.finalized_invoice(&draft.tenant_id, draft.period_start, draft.period_end)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    async fn finalized_invoice(
+        &self,
+        tenant_id: &str,
+        start: time::OffsetDateTime,
+        end: time::OffsetDateTime,
+    ) -> Result<Option<Invoice>, sqlx::Error> {
+        Ok(self
+            .invoices(None, Some(tenant_id), Some((start, end)))
+            .await?
+            .pop())
+    }
+
+    async fn get_invoice(&self, invoice_number: &str) -> Result<Option<Invoice>, sqlx::Error> {
+        Ok(self.invoices(Some(invoice_number), None, None).await?.pop())
+    }
+
+    async fn list_invoices(&self, tenant_id: &str) -> Result<Vec<Invoice>, sqlx::Error> {
+        self.invoices(None, Some(tenant_id), None).await
+    }
+}
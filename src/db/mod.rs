@@ -1,6 +1,18 @@
 pub mod keys;
 pub mod usage;
 pub mod billing;
+pub mod rate_cards;
+pub mod credits;
+pub mod budgets;
+pub mod invoices;
+pub mod quotas;
+pub mod rate_limits;
 
 pub use keys::{KeyInfo, KeyStore, PgKeyStore};
-pub use billing::{BillingStore, PgBillingStore, CostSummary};
+pub use billing::{BillingStore, BillingSummary, PgBillingStore, CostSummary, TransactionCursor, TransactionFilter};
+pub use rate_cards::{NewRateCard, PgRateCardStore, RateCardStore};
+pub use credits::{CreditEntryType, CreditStore, PgCreditStore, Reservation, ReserveOutcome};
+pub use budgets::{BudgetStore, NewBudget, PgBudgetStore};
+pub use invoices::{InvoiceStore, PgInvoiceStore};
+pub use quotas::{Admission, PgQuotaStore, Quota, QuotaKind, QuotaLimit, QuotaStore, QuotaUsage};
+pub use rate_limits::{PgRateLimitStore, RateLimitStore};
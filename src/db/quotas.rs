@@ -0,0 +1,371 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::billing::SummaryPeriod;
+
+/// Quotas of a tenant (`api_key_id` unset) or of one API key; unset limits are unlimited
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Quota {
+    pub tenant_id: String,
+    #[serde(default)]
+    pub api_key_id: Option<Uuid>,
+    #[serde(default)]
+    pub requests_per_day: Option<i64>,
+    #[serde(default)]
+    pub tokens_per_day: Option<i64>,
+    #[serde(default)]
+    pub tokens_per_month: Option<i64>,
+    /// Enforced in memory by each gateway instance, not counted in `quota_usage`
+    #[serde(default)]
+    pub tokens_per_minute: Option<i64>,
+    /// Requests in flight at once per gateway instance
+    #[serde(default)]
+    pub max_concurrent_requests: Option<i32>,
+    /// Tenant's share of capacity while requests queue for it; tenant quotas only
+    #[serde(default)]
+    pub scheduling_weight: Option<i32>,
+}
+
+/// What a quota limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaKind {
+    RequestsPerDay,
+    TokensPerDay,
+    TokensPerMonth,
+}
+
+impl QuotaKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QuotaKind::RequestsPerDay => "requests_per_day",
+            QuotaKind::TokensPerDay => "tokens_per_day",
+            QuotaKind::TokensPerMonth => "tokens_per_month",
+        }
+    }
+
+    /// Window the quota resets on (UTC)
+    pub fn period(self) -> SummaryPeriod {
+        match self {
+            QuotaKind::RequestsPerDay | QuotaKind::TokensPerDay => SummaryPeriod::Daily,
+            QuotaKind::TokensPerMonth => SummaryPeriod::Monthly,
+        }
+    }
+}
+
+/// One limit a request is checked against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaLimit {
+    /// `None` for the tenant-wide limit
+    pub api_key_id: Option<Uuid>,
+    pub kind: QuotaKind,
+    pub limit: i64,
+}
+
+/// Usage of a limit in its current window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub limit: QuotaLimit,
+    pub used: i64,
+    pub period_end: time::OffsetDateTime,
+}
+
+impl QuotaUsage {
+    pub fn remaining(&self) -> i64 {
+        (self.limit.limit - self.used).max(0)
+    }
+}
+
+/// Outcome of checking a request against its limits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Admission {
+    /// Counted against every request limit
+    Admitted(Vec<QuotaUsage>),
+    /// Nothing was counted
+    Exhausted(QuotaUsage),
+}
+
+/// A quota with the usage of its current windows
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaStatus {
+    #[serde(flatten)]
+    pub quota: Quota,
+    pub requests_today: i64,
+    pub tokens_today: i64,
+    pub tokens_this_month: i64,
+}
+
+/// Trait for quota storage
+#[async_trait]
+pub trait QuotaStore: Send + Sync {
+    /// Quotas a request of this key is subject to: the tenant's and the key's own
+    async fn quotas_for(&self, tenant_id: &str, api_key_id: Uuid) -> Result<Vec<Quota>, sqlx::Error>;
+
+    /// Count a request against the request limits unless one of them, or a
+    /// token limit, is already used up. Tokens are counted when transactions
+    /// are stored, so a request admitted just below a token limit may overshoot it.
+    async fn admit(
+        &self,
+        tenant_id: &str,
+        limits: &[QuotaLimit],
+        now: time::OffsetDateTime,
+    ) -> Result<Admission, sqlx::Error>;
+
+    /// Give back a request counted by `admit` at `now`
+    async fn refund(
+        &self,
+        tenant_id: &str,
+        limits: &[QuotaLimit],
+        now: time::OffsetDateTime,
+    ) -> Result<(), sqlx::Error>;
+
+    /// All quotas of a tenant, or only those of one key
+    async fn list_quotas(
+        &self,
+        tenant_id: &str,
+        api_key_id: Option<Uuid>,
+        now: time::OffsetDateTime,
+    ) -> Result<Vec<QuotaStatus>, sqlx::Error>;
+
+    /// Create or replace the quotas of this scope
+    async fn upsert_quota(&self, quota: Quota) -> Result<Quota, sqlx::Error>;
+}
+
+/// PostgreSQL implementation of QuotaStore
+pub struct PgQuotaStore {
+    pool: PgPool,
+}
+
+impl PgQuotaStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl QuotaStore for PgQuotaStore {
+    async fn quotas_for(&self, tenant_id: &str, api_key_id: Uuid) -> Result<Vec<Quota>, sqlx::Error> {
+        sqlx::query_as!(
+            Quota,
+            r#"
+            SELECT
+                tenant_id, api_key_id, requests_per_day, tokens_per_day, tokens_per_month,
+                tokens_per_minute, max_concurrent_requests, scheduling_weight
+            FROM quotas
+            WHERE tenant_id = $1
+              AND (api_key_id = $2 OR api_key_id IS NULL)
+            "#,
+            tenant_id,
+            api_key_id
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn admit(
+        &self,
+        tenant_id: &str,
+        limits: &[QuotaLimit],
+        now: time::OffsetDateTime,
+    ) -> Result<Admission, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let mut usage = Vec::with_capacity(limits.len());
+
+        for limit in limits {
+            let (period_start, period_end) = limit.kind.period().bounds(now);
+            let exhausted = QuotaUsage {
+                limit: *limit,
+                used: limit.limit,
+                period_end,
+            };
+
+            let used = match limit.kind {
+                QuotaKind::RequestsPerDay => {
+                    if limit.limit <= 0 {
+                        return Ok(Admission::Exhausted(exhausted));
+                    }
+                    // The update is skipped once the limit is reached, so
+                    // concurrent requests cannot push the counter past it
+                    let counted = sqlx::query_scalar!(
+                        r#"
+                        INSERT INTO quota_usage (tenant_id, api_key_id, period, period_start, requests)
+                        VALUES ($1, $2, $3, $4, 1)
+                        ON CONFLICT (tenant_id, COALESCE(api_key_id, '00000000-0000-0000-0000-000000000000'::uuid), period, period_start)
+                        DO UPDATE SET requests = quota_usage.requests + 1, updated_at = NOW()
+                        WHERE quota_usage.requests < $5
+                        RETURNING requests
+                        "#,
+                        tenant_id,
+                        limit.api_key_id,
+                        limit.kind.period().as_str(),
+                        period_start,
+                        limit.limit
+                    )
+                    .fetch_optional(&mut *tx)
+                    .await?;
+
+                    match counted {
+                        Some(requests) => requests,
+                        None => return Ok(Admission::Exhausted(exhausted)),
+                    }
+                }
+                QuotaKind::TokensPerDay | QuotaKind::TokensPerMonth => {
+                    let tokens = sqlx::query_scalar!(
+                        r#"
+                        SELECT tokens
+                        FROM quota_usage
+                        WHERE tenant_id = $1
+                          AND api_key_id IS NOT DISTINCT FROM $2
+                          AND period = $3
+                          AND period_start = $4
+                        "#,
+                        tenant_id,
+                        limit.api_key_id,
+                        limit.kind.period().as_str(),
+                        period_start
+                    )
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .unwrap_or(0);
+
+                    if tokens >= limit.limit {
+                        return Ok(Admission::Exhausted(exhausted));
+                    }
+                    tokens
+                }
+            };
+
+            usage.push(QuotaUsage {
+                limit: *limit,
+                used,
+                period_end,
+            });
+        }
+
+        tx.commit().await?;
+        Ok(Admission::Admitted(usage))
+    }
+
+    async fn refund(
+        &self,
+        tenant_id: &str,
+        limits: &[QuotaLimit],
+        now: time::OffsetDateTime,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        for limit in limits.iter().filter(|l| l.kind == QuotaKind::RequestsPerDay) {
+            let (period_start, _) = limit.kind.period().bounds(now);
+            sqlx::query!(
+                r#"
+                UPDATE quota_usage
+                SET requests = GREATEST(requests - 1, 0), updated_at = NOW()
+                WHERE tenant_id = $1
+                  AND api_key_id IS NOT DISTINCT FROM $2
+                  AND period = $3
+                  AND period_start = $4
+                "#,
+                tenant_id,
+                limit.api_key_id,
+                limit.kind.period().as_str(),
+                period_start
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await
+    }
+
+    async fn list_quotas(
+        &self,
+        tenant_id: &str,
+        api_key_id: Option<Uuid>,
+        now: time::OffsetDateTime,
+    ) -> Result<Vec<QuotaStatus>, sqlx::Error> {
+        let (day, _) = SummaryPeriod::Daily.bounds(now);
+        let (month, _) = SummaryPeriod::Monthly.bounds(now);
+
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                q.tenant_id, q.api_key_id, q.requests_per_day, q.tokens_per_day, q.tokens_per_month,
+                q.tokens_per_minute, q.max_concurrent_requests, q.scheduling_weight,
+                COALESCE(d.requests, 0) as "requests_today!",
+                COALESCE(d.tokens, 0) as "tokens_today!",
+                COALESCE(m.tokens, 0) as "tokens_this_month!"
+            FROM quotas q
+            LEFT JOIN quota_usage d
+                ON d.tenant_id = q.tenant_id
+               AND d.api_key_id IS NOT DISTINCT FROM q.api_key_id
+               AND d.period = 'daily'
+               AND d.period_start = $3
+            LEFT JOIN quota_usage m
+                ON m.tenant_id = q.tenant_id
+               AND m.api_key_id IS NOT DISTINCT FROM q.api_key_id
+               AND m.period = 'monthly'
+               AND m.period_start = $4
+            WHERE q.tenant_id = $1
+              AND ($2::uuid IS NULL OR q.api_key_id = $2)
+            ORDER BY q.api_key_id NULLS FIRST
+            "#,
+            tenant_id,
+            api_key_id,
+            day,
+            month
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| QuotaStatus {
+                quota: Quota {
+                    tenant_id: row.tenant_id,
+                    api_key_id: row.api_key_id,
+                    requests_per_day: row.requests_per_day,
+                    tokens_per_day: row.tokens_per_day,
+                    tokens_per_month: row.tokens_per_month,
+                    tokens_per_minute: row.tokens_per_minute,
+                    max_concurrent_requests: row.max_concurrent_requests,
+                    scheduling_weight: row.scheduling_weight,
+                },
+                requests_today: row.requests_today,
+                tokens_today: row.tokens_today,
+                tokens_this_month: row.tokens_this_month,
+            })
+            .collect())
+    }
+
+    async fn upsert_quota(&self, quota: Quota) -> Result<Quota, sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO quotas (
+                tenant_id, api_key_id, requests_per_day, tokens_per_day, tokens_per_month, tokens_per_minute,
+                max_concurrent_requests, scheduling_weight
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (tenant_id, COALESCE(api_key_id, '00000000-0000-0000-0000-000000000000'::uuid))
+            DO UPDATE SET
+                requests_per_day = EXCLUDED.requests_per_day,
+                tokens_per_day = EXCLUDED.tokens_per_day,
+                tokens_per_month = EXCLUDED.tokens_per_month,
+                tokens_per_minute = EXCLUDED.tokens_per_minute,
+                max_concurrent_requests = EXCLUDED.max_concurrent_requests,
+                scheduling_weight = EXCLUDED.scheduling_weight
+            "#,
+            quota.tenant_id,
+            quota.api_key_id,
+            quota.requests_per_day,
+            quota.tokens_per_day,
+            quota.tokens_per_month,
+            quota.tokens_per_minute,
+            quota.max_concurrent_requests,
+            quota.scheduling_weight
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(quota)
+    }
+}
@@ -0,0 +1,141 @@
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::billing::{ModelPricing, RateCard};
+
+/// Terms for a new rate card version
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewRateCard {
+    pub tenant_id: String,
+    #[serde(default)]
+    pub api_key_id: Option<Uuid>,
+    #[serde(default)]
+    pub markup_percent: Decimal,
+    #[serde(default)]
+    pub request_fee: Decimal,
+    #[serde(default)]
+    pub model_prices: HashMap<String, ModelPricing>,
+    #[serde(default)]
+    pub free_tokens_per_month: i64,
+}
+
+/// Trait for rate card storage
+#[async_trait]
+pub trait RateCardStore: Send + Sync {
+    /// Latest card for the API key, falling back to the tenant-wide card
+    async fn active_card(&self, tenant_id: &str, api_key_id: Uuid) -> Result<Option<RateCard>, sqlx::Error>;
+
+    /// Tokens billed within the card's scope since `since`
+    async fn tokens_used_since(&self, card: &RateCard, since: time::OffsetDateTime) -> Result<i64, sqlx::Error>;
+
+    /// Store the next version of the card for this scope
+    async fn create_card(&self, card: NewRateCard) -> Result<RateCard, sqlx::Error>;
+}
+
+/// PostgreSQL implementation of RateCardStore
+pub struct PgRateCardStore {
+    pool: PgPool,
+}
+
+impl PgRateCardStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+fn model_prices(value: serde_json::Value) -> Result<HashMap<String, ModelPricing>, sqlx::Error> {
+    serde_json::from_value(value).map_err(|e| sqlx::Error::Decode(Box::new(e)))
+}
+
+#[async_trait]
+impl RateCardStore for PgRateCardStore {
+    async fn active_card(&self, tenant_id: &str, api_key_id: Uuid) -> Result<Option<RateCard>, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT
+                id, tenant_id, api_key_id, version,
+                markup_percent, request_fee, model_prices, free_tokens_per_month
+            FROM rate_cards
+            WHERE tenant_id = $1
+              AND (api_key_id = $2 OR api_key_id IS NULL)
+            ORDER BY api_key_id IS NULL, version DESC
+            LIMIT 1
+            "#,
+            tenant_id,
+            api_key_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(|row| {
+            Ok(RateCard {
+                id: row.id,
+                tenant_id: row.tenant_id,
+                api_key_id: row.api_key_id,
+                version: row.version,
+                markup_percent: row.markup_percent,
+                request_fee: row.request_fee,
+                model_prices: model_prices(row.model_prices)?,
+                free_tokens_per_month: row.free_tokens_per_month,
+            })
+        })
+        .transpose()
+    }
+
+    async fn tokens_used_since(&self, card: &RateCard, since: time::OffsetDateTime) -> Result<i64, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT COALESCE(SUM(total_tokens), 0)::bigint as "used!"
+            FROM billing_transactions
+            WHERE tenant_id = $1
+              AND ($2::uuid IS NULL OR api_key_id = $2)
+              AND created_at >= $3
+            "#,
+            card.tenant_id,
+            card.api_key_id,
+            since
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.used)
+    }
+
+    async fn create_card(&self, card: NewRateCard) -> Result<RateCard, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO rate_cards (
+                tenant_id, api_key_id, version, markup_percent, request_fee, model_prices,
+                free_tokens_per_month
+            )
+            SELECT $1::varchar, $2::uuid, COALESCE(MAX(version), 0) + 1, $3, $4, $5, $6
+            FROM rate_cards
+            WHERE tenant_id = $1::varchar AND api_key_id IS NOT DISTINCT FROM $2::uuid
+            RETURNING id, version
+            "#,
+            card.tenant_id,
+            card.api_key_id,
+            card.markup_percent,
+            card.request_fee,
+            sqlx::types::Json(&card.model_prices) as _,
+            card.free_tokens_per_month
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(RateCard {
+            id: row.id,
+            tenant_id: card.tenant_id,
+            api_key_id: card.api_key_id,
+            version: row.version,
+            markup_percent: card.markup_percent,
+            request_fee: card.request_fee,
+            model_prices: card.model_prices,
+            free_tokens_per_month: card.free_tokens_per_month,
+        })
+    }
+}
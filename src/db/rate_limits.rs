@@ -0,0 +1,119 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Trait for rate limit counters shared between gateway replicas
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Count a request in the window starting at `window_start` unless the
+    /// sliding count, `requests of the previous window * previous_weight +
+    /// requests of this window`, has reached `limit`. Returns the sliding
+    /// count including the request, or `None` when it was not counted.
+    async fn count_request(
+        &self,
+        api_key_id: Uuid,
+        window_start: time::OffsetDateTime,
+        previous_start: time::OffsetDateTime,
+        previous_weight: f64,
+        limit: i64,
+    ) -> Result<Option<f64>, sqlx::Error>;
+
+    /// Requests counted in the previous and the current window
+    async fn window_requests(
+        &self,
+        api_key_id: Uuid,
+        window_start: time::OffsetDateTime,
+        previous_start: time::OffsetDateTime,
+    ) -> Result<(i64, i64), sqlx::Error>;
+
+    /// Delete windows that started before `before`
+    async fn prune(&self, before: time::OffsetDateTime) -> Result<u64, sqlx::Error>;
+}
+
+/// PostgreSQL implementation of RateLimitStore
+pub struct PgRateLimitStore {
+    pool: PgPool,
+}
+
+impl PgRateLimitStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for PgRateLimitStore {
+    async fn count_request(
+        &self,
+        api_key_id: Uuid,
+        window_start: time::OffsetDateTime,
+        previous_start: time::OffsetDateTime,
+        previous_weight: f64,
+        limit: i64,
+    ) -> Result<Option<f64>, sqlx::Error> {
+        // One statement, so replicas racing on the same key serialize on the
+        // row lock of the current window instead of an explicit lock
+        let counted = sqlx::query_scalar!(
+            r#"
+            INSERT INTO rate_limit_windows (api_key_id, window_start, requests)
+            SELECT $1, $2, 1
+            WHERE COALESCE(
+                (SELECT requests FROM rate_limit_windows WHERE api_key_id = $1 AND window_start = $3), 0
+            ) * $4::float8 < $5::bigint
+            ON CONFLICT (api_key_id, window_start) DO UPDATE
+            SET requests = rate_limit_windows.requests + 1
+            WHERE rate_limit_windows.requests + COALESCE(
+                (SELECT p.requests FROM rate_limit_windows p WHERE p.api_key_id = $1 AND p.window_start = $3), 0
+            ) * $4::float8 < $5::bigint
+            RETURNING (requests + COALESCE(
+                (SELECT p.requests FROM rate_limit_windows p WHERE p.api_key_id = $1 AND p.window_start = $3), 0
+            ) * $4::float8)::float8 as "sliding!"
+            "#,
+            api_key_id,
+            window_start,
+            previous_start,
+            previous_weight,
+            limit
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(counted)
+    }
+
+    async fn window_requests(
+        &self,
+        api_key_id: Uuid,
+        window_start: time::OffsetDateTime,
+        previous_start: time::OffsetDateTime,
+    ) -> Result<(i64, i64), sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT
+                COALESCE(SUM(requests) FILTER (WHERE window_start = $3), 0)::bigint as "previous!",
+                COALESCE(SUM(requests) FILTER (WHERE window_start = $2), 0)::bigint as "current!"
+            FROM rate_limit_windows
+            WHERE api_key_id = $1
+              AND window_start IN ($2, $3)
+            "#,
+            api_key_id,
+            window_start,
+            previous_start
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok((row.previous, row.current))
+    }
+
+    async fn prune(&self, before: time::OffsetDateTime) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM rate_limit_windows WHERE window_start < $1",
+            before
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
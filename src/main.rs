@@ -1,6 +1,9 @@
 mod api;
 mod auth;
+mod balancer;
 mod billing;
+mod circuit_breaker;
+mod concurrency;
 mod connectors;
 mod core;
 mod db;
@@ -8,6 +11,7 @@ mod metrics;
 mod observability;
 mod ratelimit;
 mod registry;
+mod reload;
 mod routing;
 mod secret_store;
 mod sse;
@@ -17,6 +21,7 @@ use sqlx::postgres::PgPoolOptions;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use secret_store::{
     preload_secrets, EnvSecretProvider, HybridSecretProvider, SdkSecretProvider, SecretProvider,
@@ -24,11 +29,20 @@ use secret_store::{
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cfg_path = std::env::var("XJP_CONFIG").unwrap_or_else(|_| "config/xjp.toml".into());
+
+    // `xjp-gateway check-config [path]`: validate a routing config and exit
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("check-config") {
+        let path = args.get(2).cloned().unwrap_or(cfg_path);
+        std::process::exit(check_config(&path).await);
+    }
+
     observability::init_tracing();
 
     // Load configuration
-    let cfg_path = std::env::var("XJP_CONFIG").unwrap_or_else(|_| "config/xjp.toml".into());
-    let registry = registry::load_from_toml(&cfg_path).await?;
+    let registry =
+        registry::load_from_toml(&cfg_path, &registry::ConnectorDefaults::from_env()).await?;
 
     // Initialize SecretProvider
     tracing::info!("Initializing secret provider...");
@@ -119,24 +133,130 @@ async fn main() -> anyhow::Result<()> {
     // Create KeyStore instance
     let key_store: Arc<dyn db::KeyStore> = Arc::new(db::PgKeyStore::new(pool.clone()));
 
-    // Create BillingStore instance
-    let billing_store: Arc<dyn db::BillingStore> = Arc::new(db::PgBillingStore::new(pool.clone()));
+    // Create BillingStore instance; budgets are checked after each stored transaction
+    let pg_billing_store: Arc<dyn db::BillingStore> = Arc::new(db::PgBillingStore::new(pool.clone()));
+    let budget_monitor = Arc::new(billing::BudgetMonitor::new(
+        pg_billing_store.clone(),
+        Arc::new(db::PgBudgetStore::new(pool.clone())),
+        Arc::new(billing::Notifiers::from_env()?),
+    ));
+    let billing_store: Arc<dyn db::BillingStore> = Arc::new(billing::AlertingBillingStore::new(
+        pg_billing_store,
+        budget_monitor.clone(),
+    ));
+    let rate_card_store: Arc<dyn db::RateCardStore> = Arc::new(db::PgRateCardStore::new(pool.clone()));
+    let credit_store: Arc<dyn db::CreditStore> = Arc::new(db::PgCreditStore::new(pool.clone()));
+
+    // Credit held by requests that never settled (e.g. the gateway restarted mid-request)
+    // is released after an hour
+    {
+        let credit_store = credit_store.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(300));
+            loop {
+                ticker.tick().await;
+                let cutoff = time::OffsetDateTime::now_utc() - time::Duration::hours(1);
+                match credit_store.release_stale(cutoff).await {
+                    Ok(0) => {}
+                    Ok(n) => tracing::warn!("Released stale credit reservations on {} accounts", n),
+                    Err(e) => tracing::error!("Failed to release stale credit reservations: {}", e),
+                }
+            }
+        });
+    }
+
+    // Aggregate transactions into tenant_billing_summary
+    let rollup_secs = std::env::var("XJP_ROLLUP_INTERVAL_SECS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(300);
+    if rollup_secs > 0 {
+        billing::rollup::spawn(billing_store.clone(), Duration::from_secs(rollup_secs));
+    }
+
+    // Per-minute limits; unlike routes, the backend only changes on restart
+    let rate_limiter = ratelimit::backend_from_config(&registry.rate_limit_config, &pool)?;
+
+    // Routing table can be swapped at runtime: SIGHUP, POST /internal/registry/reload,
+    // or (when XJP_CONFIG_WATCH_SECS is set) polling the file for changes
+    let registry = Arc::new(reload::RegistryHandle::new(cfg_path, registry));
+    reload::spawn_sighup_reloader(registry.clone())?;
+    if let Some(secs) = std::env::var("XJP_CONFIG_WATCH_SECS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .filter(|s| *s > 0)
+    {
+        tracing::info!("Watching routing config for changes every {}s", secs);
+        reload::spawn_file_watcher(registry.clone(), Duration::from_secs(secs));
+    }
 
     let app_state = routing::AppState::new(
         registry,
         key_store,
         secret_provider,
         preloaded_secrets,
-        billing_store,
+        routing::BillingServices {
+            store: billing_store,
+            rate_cards: rate_card_store,
+            credits: credit_store,
+            budgets: budget_monitor,
+            invoices: Arc::new(db::PgInvoiceStore::new(pool.clone())),
+            quotas: Arc::new(db::PgQuotaStore::new(pool.clone())),
+        },
+        rate_limiter,
     )
     .await?;
 
-    let app = Router::new()
+    // Client API: authenticate once, then per-minute limits and quotas.
+    // The layer added last runs first.
+    let client_api = Router::new()
         .route("/v1/chat/completions", post(api::openai::chat_completions))
         .route("/v1/messages", post(api::anthropic::messages))
+        .route_layer(axum::middleware::from_fn_with_state(
+            app_state.clone(),
+            ratelimit::rate_limit_middleware,
+        ))
+        .route_layer(axum::middleware::from_fn_with_state(
+            app_state.clone(),
+            auth::auth_middleware,
+        ));
+
+    let app = Router::new()
+        .merge(client_api)
         .route("/internal/billing/quote", post(api::billing::quote))
         .route("/internal/billing/transactions", axum::routing::get(api::billing::get_transactions))
         .route("/internal/billing/summary", axum::routing::get(api::billing::get_summary))
+        .route("/internal/billing/summary/backfill", post(api::billing::backfill_summary))
+        .route(
+            "/internal/billing/rate-cards",
+            axum::routing::get(api::billing::get_rate_card).post(api::billing::create_rate_card),
+        )
+        .route(
+            "/internal/billing/credits",
+            axum::routing::get(api::billing::get_credits).post(api::billing::add_credits),
+        )
+        .route(
+            "/internal/billing/invoices",
+            axum::routing::get(api::billing::get_invoices).post(api::billing::finalize_invoice),
+        )
+        .route(
+            "/internal/billing/invoices/:invoice_number",
+            axum::routing::get(api::billing::get_invoice),
+        )
+        .route(
+            "/internal/billing/budgets",
+            axum::routing::get(api::billing::get_budgets).post(api::billing::set_budget),
+        )
+        .route(
+            "/internal/billing/quotas",
+            axum::routing::get(api::billing::get_quotas).post(api::billing::set_quota),
+        )
+        .route("/internal/registry", axum::routing::get(api::internal::registry_status))
+        .route("/internal/registry/reload", post(api::internal::reload_registry))
+        .route(
+            "/internal/circuit-breakers",
+            axum::routing::get(api::internal::circuit_breakers),
+        )
         .route("/healthz", axum::routing::get(|| async { "ok" }))
         .route("/metrics", axum::routing::get(metrics::metrics_handler))
         .with_state(app_state)
@@ -152,3 +272,27 @@ async fn main() -> anyhow::Result<()> {
     axum::serve(listener, app).await?;
     Ok(())
 }
+
+/// Print every problem in a routing config; returns the process exit code
+async fn check_config(path: &str) -> i32 {
+    let text = match tokio::fs::read_to_string(path).await {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("{}: cannot read: {}", path, e);
+            return 1;
+        }
+    };
+    match registry::from_toml_str(&text, &registry::ConnectorDefaults::from_env()) {
+        Ok(_) => {
+            println!("{}: OK", path);
+            0
+        }
+        Err(errors) => {
+            for problem in &errors.0 {
+                eprintln!("{}: {}", path, problem);
+            }
+            eprintln!("{} problem(s) found", errors.0.len());
+            1
+        }
+    }
+}
@@ -1,8 +1,8 @@
 use axum::response::IntoResponse;
 use lazy_static::lazy_static;
 use prometheus::{
-    register_histogram_vec, register_int_counter_vec, register_int_gauge, Encoder, HistogramVec,
-    IntCounterVec, IntGauge, TextEncoder,
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    Encoder, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
 };
 
 lazy_static! {
@@ -31,9 +31,21 @@ lazy_static! {
     )
     .unwrap();
 
-    /// Number of active connections
-    pub static ref ACTIVE_CONNECTIONS: IntGauge =
-        register_int_gauge!("xjp_active_connections", "Number of active connections").unwrap();
+    /// Requests in flight per tenant
+    pub static ref ACTIVE_CONNECTIONS: IntGaugeVec = register_int_gauge_vec!(
+        "xjp_active_connections",
+        "Number of requests in flight",
+        &["tenant_id"]
+    )
+    .unwrap();
+
+    /// Requests rejected while waiting for a concurrency slot
+    pub static ref CONCURRENCY_REJECTIONS: IntCounterVec = register_int_counter_vec!(
+        "xjp_concurrency_rejections_total",
+        "Total number of requests rejected by concurrency limits",
+        &["tenant_id", "reason"]
+    )
+    .unwrap();
 
     /// Rate limit hits
     pub static ref RATE_LIMIT_HITS: IntCounterVec = register_int_counter_vec!(
@@ -43,6 +55,14 @@ lazy_static! {
     )
     .unwrap();
 
+    /// Requests rejected because a daily or monthly quota is used up
+    pub static ref QUOTA_EXHAUSTED: IntCounterVec = register_int_counter_vec!(
+        "xjp_quota_exhausted_total",
+        "Total number of requests rejected by an exhausted quota",
+        &["tenant_id", "quota"]
+    )
+    .unwrap();
+
     /// Authentication errors
     pub static ref AUTH_ERRORS: IntCounterVec = register_int_counter_vec!(
         "xjp_auth_errors_total",
@@ -50,6 +70,29 @@ lazy_static! {
         &["type"]
     )
     .unwrap();
+
+    /// Generation of the routing config currently active (1 = loaded at startup)
+    pub static ref CONFIG_GENERATION: IntGauge = register_int_gauge!(
+        "xjp_config_generation",
+        "Generation of the routing config currently active"
+    )
+    .unwrap();
+
+    /// Routing config reload attempts
+    pub static ref CONFIG_RELOADS_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "xjp_config_reloads_total",
+        "Total number of routing config reload attempts",
+        &["result"]
+    )
+    .unwrap();
+
+    /// Circuit breaker state per route (0 = closed, 1 = half-open, 2 = open)
+    pub static ref CIRCUIT_BREAKER_STATE: IntGaugeVec = register_int_gauge_vec!(
+        "xjp_circuit_breaker_state",
+        "Circuit breaker state per route (0 = closed, 1 = half-open, 2 = open)",
+        &["route", "provider", "provider_model_id"]
+    )
+    .unwrap();
 }
 
 /// Export metrics in Prometheus text format
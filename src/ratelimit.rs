@@ -1,6 +1,6 @@
 use axum::{
     body::Body,
-    extract::Request,
+    extract::{Request, State},
     http::{HeaderValue, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
@@ -9,15 +9,77 @@ use axum::{
 use dashmap::DashMap;
 use governor::{
     clock::{Clock, DefaultClock},
+    middleware::StateInformationMiddleware,
     state::{InMemoryState, NotKeyed},
     Quota, RateLimiter as GovernorRateLimiter,
 };
 use std::{num::NonZeroU32, sync::Arc};
 use uuid::Uuid;
 
-/// Per-tenant rate limiter
+use crate::billing::TpmLimits;
+use crate::concurrency::{ConcurrencyLimits, PermitBody};
+use crate::connectors::TokenRateLimitedResponse;
+use crate::db::{self, Admission, PgRateLimitStore, RateLimitStore, KeyInfo, QuotaKind, QuotaLimit, QuotaStore, QuotaUsage};
+use crate::registry::{RateLimitBackendKind, RateLimitConfig};
+use crate::routing::AppState;
+
+/// Per-minute limit of a key, as left after counting a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinuteWindow {
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the whole limit is available again, rounded up
+    pub reset_after: u64,
+}
+
+/// Where per-minute request limits are counted
+#[async_trait::async_trait]
+pub trait RateLimitBackend: Send + Sync {
+    /// Count a request of the key, or reject it when it is over `rpm`
+    async fn check(&self, key_id: Uuid, rpm: u32) -> Result<MinuteWindow, RateLimitError>;
+}
+
+/// A key's `rate_limit_rpm`, with 60 for keys that do not set one
+fn effective_rpm(rpm: u32) -> NonZeroU32 {
+    NonZeroU32::new(rpm).unwrap_or(NonZeroU32::new(60).unwrap())
+}
+
+/// Per-minute limiter selected by `backend` in `[rate_limit]` of the routing
+/// config, or by `XJP_RATE_LIMIT_BACKEND` when set: `memory` (default) keeps
+/// limits per process, `postgres` shares them between all replicas using the database
+pub fn backend_from_config(config: &RateLimitConfig, pool: &sqlx::PgPool) -> anyhow::Result<Arc<dyn RateLimitBackend>> {
+    let backend = match std::env::var("XJP_RATE_LIMIT_BACKEND").unwrap_or_default().as_str() {
+        "" => config.backend,
+        "memory" => RateLimitBackendKind::Memory,
+        "postgres" => RateLimitBackendKind::Postgres,
+        other => anyhow::bail!("unknown XJP_RATE_LIMIT_BACKEND '{}', expected memory or postgres", other),
+    };
+    match backend {
+        RateLimitBackendKind::Memory => Ok(Arc::new(RateLimiter::new())),
+        RateLimitBackendKind::Postgres => {
+            let store: Arc<dyn RateLimitStore> = Arc::new(PgRateLimitStore::new(pool.clone()));
+            // Only the current and the previous minute are ever read
+            let pruned = store.clone();
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(std::time::Duration::from_secs(60));
+                loop {
+                    ticker.tick().await;
+                    let before = window_start(time::OffsetDateTime::now_utc()) - WINDOW;
+                    if let Err(e) = pruned.prune(before).await {
+                        tracing::error!("Failed to prune rate limit windows: {}", e);
+                    }
+                }
+            });
+            Ok(Arc::new(PostgresRateLimiter::new(store)))
+        }
+    }
+}
+
+type KeyLimiter = GovernorRateLimiter<NotKeyed, InMemoryState, DefaultClock, StateInformationMiddleware>;
+
+/// In-process rate limiter per API key, for single-node deployments
 pub struct RateLimiter {
-    limiters: Arc<DashMap<Uuid, Arc<GovernorRateLimiter<NotKeyed, InMemoryState, DefaultClock>>>>,
+    limiters: Arc<DashMap<Uuid, Arc<KeyLimiter>>>,
 }
 
 impl RateLimiter {
@@ -32,27 +94,35 @@ impl RateLimiter {
         &self,
         key_id: Uuid,
         rpm: u32,
-    ) -> Arc<GovernorRateLimiter<NotKeyed, InMemoryState, DefaultClock>> {
+    ) -> Arc<KeyLimiter> {
         self.limiters
             .entry(key_id)
             .or_insert_with(|| {
-                let quota =
-                    Quota::per_minute(NonZeroU32::new(rpm).unwrap_or(NonZeroU32::new(60).unwrap()));
-                Arc::new(GovernorRateLimiter::direct(quota))
+                let quota = Quota::per_minute(effective_rpm(rpm));
+                Arc::new(GovernorRateLimiter::direct(quota).with_middleware::<StateInformationMiddleware>())
             })
             .clone()
     }
 
     /// Check if a request is allowed for a specific key
-    pub fn check(&self, key_id: Uuid, rpm: u32) -> Result<(), RateLimitError> {
+    pub fn check(&self, key_id: Uuid, rpm: u32) -> Result<MinuteWindow, RateLimitError> {
         let limiter = self.get_or_create_limiter(key_id, rpm);
         match limiter.check() {
-            Ok(_) => Ok(()),
+            Ok(snapshot) => {
+                let limit = snapshot.quota().burst_size().get();
+                let remaining = snapshot.remaining_burst_capacity();
+                // The bucket regains one request every minute / limit
+                let reset_after = (f64::from(limit - remaining) * 60.0 / f64::from(limit)).ceil() as u64;
+                Ok(MinuteWindow { limit, remaining, reset_after })
+            }
             Err(not_until) => {
+                // Round up so clients never retry before the next slot
                 let wait_time = not_until
                     .wait_time_from(DefaultClock::default().now())
-                    .as_secs();
+                    .as_secs_f64()
+                    .ceil() as u64;
                 Err(RateLimitError::Exceeded {
+                    limit: not_until.quota().burst_size().get(),
                     retry_after: wait_time,
                 })
             }
@@ -66,17 +136,296 @@ impl Default for RateLimiter {
     }
 }
 
+#[async_trait::async_trait]
+impl RateLimitBackend for RateLimiter {
+    async fn check(&self, key_id: Uuid, rpm: u32) -> Result<MinuteWindow, RateLimitError> {
+        RateLimiter::check(self, key_id, rpm)
+    }
+}
+
+const WINDOW: time::Duration = time::Duration::MINUTE;
+
+/// Start of the minute containing `t`
+fn window_start(t: time::OffsetDateTime) -> time::OffsetDateTime {
+    let secs = t.unix_timestamp();
+    time::OffsetDateTime::from_unix_timestamp(secs - secs.rem_euclid(60))
+        .expect("whole minutes of a valid timestamp are valid")
+}
+
+/// Time until the sliding count of a key, `previous` weighted by how much of
+/// the previous window still overlaps the last minute plus `current`, drops
+/// below `limit`. `elapsed` is the time since the current window started.
+fn sliding_window_wait(previous: i64, current: i64, limit: i64, elapsed: f64) -> std::time::Duration {
+    let window = WINDOW.as_seconds_f64();
+    let secs = if current >= limit {
+        // Wait for the next window, where this one's requests fade out
+        (window - elapsed) + window * (1.0 - limit as f64 / current as f64)
+    } else if previous > 0 {
+        window * (1.0 - (limit - current) as f64 / previous as f64) - elapsed
+    } else {
+        0.0
+    };
+    std::time::Duration::from_secs_f64(secs.max(0.0))
+}
+
+/// Rate limiter whose counters live in Postgres, so every replica enforces
+/// the same limit. Counts per minute and smooths the window boundary with
+/// the previous minute's count, close to a true sliding window.
+pub struct PostgresRateLimiter {
+    store: Arc<dyn RateLimitStore>,
+}
+
+impl PostgresRateLimiter {
+    pub fn new(store: Arc<dyn RateLimitStore>) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitBackend for PostgresRateLimiter {
+    /// Requests are let through when the database cannot be reached
+    async fn check(&self, key_id: Uuid, rpm: u32) -> Result<MinuteWindow, RateLimitError> {
+        // Same default as the in-memory limiter
+        let rpm = effective_rpm(rpm).get();
+        let limit = i64::from(rpm);
+        let now = time::OffsetDateTime::now_utc();
+        let start = window_start(now);
+        let previous_start = start - WINDOW;
+        let elapsed = (now - start).as_seconds_f64();
+        let previous_weight = 1.0 - elapsed / WINDOW.as_seconds_f64();
+        // Requests of this window count until the end of the next one
+        let reset_after = (2.0 * WINDOW.as_seconds_f64() - elapsed).ceil() as u64;
+
+        match self
+            .store
+            .count_request(key_id, start, previous_start, previous_weight, limit)
+            .await
+        {
+            Ok(Some(sliding)) => Ok(MinuteWindow {
+                limit: rpm,
+                remaining: (f64::from(rpm) - sliding).floor().max(0.0) as u32,
+                reset_after,
+            }),
+            Ok(None) => {
+                let wait = match self.store.window_requests(key_id, start, previous_start).await {
+                    Ok((previous, current)) => sliding_window_wait(previous, current, limit, elapsed),
+                    Err(_) => std::time::Duration::from_secs(1),
+                };
+                Err(RateLimitError::Exceeded {
+                    limit: rpm,
+                    retry_after: (wait.as_secs_f64().ceil() as u64).max(1),
+                })
+            }
+            Err(e) => {
+                tracing::error!("Rate limit check for key {} failed: {}", key_id, e);
+                Ok(MinuteWindow { limit: rpm, remaining: rpm, reset_after: 0 })
+            }
+        }
+    }
+}
+
+/// Limits a request of this key counts against: the key's own quotas, with
+/// the key's `rate_limit_rpd` as its daily request limit unless a quota sets
+/// one, then the tenant's quotas. A non-positive `rate_limit_rpd` is unlimited.
+pub fn quota_limits(key: &KeyInfo, quotas: &[db::Quota]) -> Vec<QuotaLimit> {
+    let key_quota = quotas.iter().find(|q| q.api_key_id == Some(key.id));
+    let tenant_quota = quotas.iter().find(|q| q.api_key_id.is_none());
+
+    let mut limits = Vec::new();
+    let key_rpd = key_quota
+        .and_then(|q| q.requests_per_day)
+        .or((key.rate_limit_rpd > 0).then_some(key.rate_limit_rpd as i64));
+    for (api_key_id, quota, requests_per_day) in [
+        (Some(key.id), key_quota, key_rpd),
+        (None, tenant_quota, tenant_quota.and_then(|q| q.requests_per_day)),
+    ] {
+        let kinds = [
+            (QuotaKind::RequestsPerDay, requests_per_day),
+            (QuotaKind::TokensPerDay, quota.and_then(|q| q.tokens_per_day)),
+            (QuotaKind::TokensPerMonth, quota.and_then(|q| q.tokens_per_month)),
+        ];
+        for (kind, limit) in kinds {
+            if let Some(limit) = limit {
+                limits.push(QuotaLimit { api_key_id, kind, limit });
+            }
+        }
+    }
+    limits
+}
+
+/// Tokens-per-minute limits of the key's and the tenant's quotas
+pub fn tpm_limits(key: &KeyInfo, quotas: &[db::Quota]) -> TpmLimits {
+    let per_minute = |api_key_id: Option<Uuid>| {
+        quotas
+            .iter()
+            .find(|q| q.api_key_id == api_key_id)
+            .and_then(|q| q.tokens_per_minute)
+            .map(|limit| limit.max(0) as u64)
+    };
+    TpmLimits {
+        api_key: per_minute(Some(key.id)),
+        tenant: per_minute(None),
+    }
+}
+
+/// Seconds until the window of a quota resets, rounded up
+fn reset_after(usage: &QuotaUsage) -> u64 {
+    let left = usage.period_end - time::OffsetDateTime::now_utc();
+    left.whole_seconds().max(0) as u64 + u64::from(left.subsec_nanoseconds() > 0)
+}
+
+fn set_header(response: &mut Response, name: &'static str, value: impl ToString) {
+    if let Ok(value) = HeaderValue::from_str(&value.to_string()) {
+        response.headers_mut().insert(name, value);
+    }
+}
+
+/// `X-RateLimit-*` headers describing the daily request quota closest to
+/// exhaustion, or the per-minute limit when no daily quota applies
+#[derive(Debug, Clone, Default)]
+pub struct QuotaHeaders {
+    daily: Option<QuotaUsage>,
+    minute: Option<MinuteWindow>,
+}
+
+impl QuotaHeaders {
+    fn tightest(usage: &[QuotaUsage]) -> Self {
+        Self {
+            daily: usage
+                .iter()
+                .filter(|u| u.limit.kind == QuotaKind::RequestsPerDay)
+                .min_by_key(|u| u.remaining())
+                .copied(),
+            minute: None,
+        }
+    }
+
+    /// Describe the per-minute limit when there is no daily quota to describe
+    pub fn or_minute(self, window: MinuteWindow) -> Self {
+        Self { minute: Some(window), ..self }
+    }
+
+    pub fn apply(&self, response: &mut Response) {
+        if let Some(usage) = &self.daily {
+            set_header(response, "X-RateLimit-Limit", usage.limit.limit);
+            set_header(response, "X-RateLimit-Remaining", usage.remaining());
+            set_header(response, "X-RateLimit-Reset", reset_after(usage));
+        } else if let Some(window) = &self.minute {
+            set_header(response, "X-RateLimit-Limit", window.limit);
+            set_header(response, "X-RateLimit-Remaining", window.remaining);
+            set_header(response, "X-RateLimit-Reset", window.reset_after);
+        }
+    }
+}
+
+/// Daily and monthly quotas of keys and tenants, counted in Postgres so they
+/// hold across restarts and gateway instances
+pub struct QuotaEnforcer {
+    store: Arc<dyn QuotaStore>,
+}
+
+impl QuotaEnforcer {
+    pub fn new(store: Arc<dyn QuotaStore>) -> Self {
+        Self { store }
+    }
+
+    pub fn store(&self) -> Arc<dyn QuotaStore> {
+        Arc::clone(&self.store)
+    }
+
+    /// Quotas of the key and its tenant. None are enforced when the quota
+    /// store cannot be reached.
+    pub async fn quotas(&self, key: &KeyInfo) -> Vec<db::Quota> {
+        match self.store.quotas_for(&key.tenant_id, key.id).await {
+            Ok(quotas) => quotas,
+            Err(e) => {
+                tracing::error!("Failed to load quotas of key {}: {}", key.id, e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Count a request against the quotas of its key and tenant, returning
+    /// the headers to describe them and the tokens-per-minute limits the
+    /// request is charged against later. Requests are let through when the
+    /// quota store cannot be reached.
+    pub async fn admit(
+        &self,
+        key: &KeyInfo,
+        quotas: &[db::Quota],
+        now: time::OffsetDateTime,
+    ) -> Result<(QuotaHeaders, TpmLimits), RateLimitError> {
+        let tpm = tpm_limits(key, quotas);
+        let limits = quota_limits(key, quotas);
+        if limits.is_empty() {
+            return Ok((QuotaHeaders::default(), tpm));
+        }
+
+        match self
+            .store
+            .admit(&key.tenant_id, &limits, now)
+            .await
+        {
+            Ok(Admission::Admitted(usage)) => Ok((QuotaHeaders::tightest(&usage), tpm)),
+            Ok(Admission::Exhausted(usage)) => {
+                crate::metrics::QUOTA_EXHAUSTED
+                    .with_label_values(&[&key.tenant_id, usage.limit.kind.as_str()])
+                    .inc();
+                Err(RateLimitError::QuotaExceeded {
+                    retry_after: reset_after(&usage),
+                    usage,
+                })
+            }
+            Err(e) => {
+                tracing::error!("Failed to count quota usage of key {}: {}", key.id, e);
+                Ok((QuotaHeaders::default(), tpm))
+            }
+        }
+    }
+
+    /// Give back the daily request a request admitted at `now` was counted
+    /// against, when it was refused before reaching any route
+    pub async fn refund(&self, key: &KeyInfo, quotas: &[db::Quota], now: time::OffsetDateTime) {
+        let limits = quota_limits(key, quotas);
+        if limits.is_empty() {
+            return;
+        }
+        if let Err(e) = self.store.refund(&key.tenant_id, &limits, now).await {
+            tracing::error!("Failed to refund quota usage of key {}: {}", key.id, e);
+        }
+    }
+}
+
 /// Rate limit errors
 #[derive(Debug, thiserror::Error)]
 pub enum RateLimitError {
     #[error("rate limit exceeded, retry after {retry_after} seconds")]
-    Exceeded { retry_after: u64 },
+    Exceeded { limit: u32, retry_after: u64 },
+
+    /// A daily or monthly quota is used up; distinct from short bursts
+    #[error("quota exhausted, resets in {retry_after} seconds")]
+    QuotaExceeded { usage: QuotaUsage, retry_after: u64 },
+
+    /// Too many requests in flight, and the request could not wait for a slot
+    #[error("too many concurrent requests, retry after {retry_after} seconds")]
+    ConcurrencyExceeded { queue_full: bool, retry_after: u64 },
+}
+
+impl RateLimitError {
+    /// Reason label of `CONCURRENCY_REJECTIONS`
+    pub fn concurrency_reason(&self) -> Option<&'static str> {
+        match self {
+            RateLimitError::ConcurrencyExceeded { queue_full: true, .. } => Some("queue_full"),
+            RateLimitError::ConcurrencyExceeded { queue_full: false, .. } => Some("queue_timeout"),
+            _ => None,
+        }
+    }
 }
 
 impl IntoResponse for RateLimitError {
     fn into_response(self) -> Response {
         match self {
-            RateLimitError::Exceeded { retry_after } => {
+            RateLimitError::Exceeded { limit, retry_after } => {
                 let mut response = (
                     StatusCode::TOO_MANY_REQUESTS,
                     Json(serde_json::json!({
@@ -89,14 +438,64 @@ impl IntoResponse for RateLimitError {
                 )
                     .into_response();
 
-                response.headers_mut().insert(
-                    "Retry-After",
-                    HeaderValue::from_str(&retry_after.to_string()).unwrap(),
-                );
-                response.headers_mut().insert(
-                    "X-RateLimit-Reset",
-                    HeaderValue::from_str(&retry_after.to_string()).unwrap(),
-                );
+                set_header(&mut response, "Retry-After", retry_after);
+                set_header(&mut response, "X-RateLimit-Limit", limit);
+                set_header(&mut response, "X-RateLimit-Remaining", 0);
+                set_header(&mut response, "X-RateLimit-Reset", retry_after);
+
+                response
+            }
+            RateLimitError::QuotaExceeded { usage, retry_after } => {
+                let scope = match usage.limit.api_key_id {
+                    Some(_) => "api_key",
+                    None => "tenant",
+                };
+                let mut response = (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    Json(serde_json::json!({
+                        "error": {
+                            "message": format!(
+                                "Quota {} of the {} is exhausted. Resets in {} seconds",
+                                usage.limit.kind.as_str(),
+                                scope.replace('_', " "),
+                                retry_after
+                            ),
+                            "type": "rate_limit_error",
+                            "code": "quota_exceeded",
+                            "quota": usage.limit.kind.as_str(),
+                            "scope": scope,
+                            "limit": usage.limit.limit
+                        }
+                    })),
+                )
+                    .into_response();
+
+                set_header(&mut response, "Retry-After", retry_after);
+                set_header(&mut response, "X-RateLimit-Limit", usage.limit.limit);
+                set_header(&mut response, "X-RateLimit-Remaining", 0);
+                set_header(&mut response, "X-RateLimit-Reset", retry_after);
+
+                response
+            }
+            RateLimitError::ConcurrencyExceeded { queue_full, retry_after } => {
+                let message = if queue_full {
+                    "Too many concurrent requests and the wait queue is full".to_string()
+                } else {
+                    format!("Too many concurrent requests. Retry after {} seconds", retry_after)
+                };
+                let mut response = (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    Json(serde_json::json!({
+                        "error": {
+                            "message": message,
+                            "type": "rate_limit_error",
+                            "code": "concurrency_limit_exceeded"
+                        }
+                    })),
+                )
+                    .into_response();
+
+                set_header(&mut response, "Retry-After", retry_after);
 
                 response
             }
@@ -104,26 +503,188 @@ impl IntoResponse for RateLimitError {
     }
 }
 
-/// Middleware to apply rate limiting based on authenticated key
+/// Enforce the per-minute limit, the concurrency limits and the quotas of the
+/// key authenticated by `auth::auth_middleware`, and describe the daily request
+/// quota, else the per-minute limit, in the headers of every response. Token
+/// limits are charged once the handler has the request body, from the
+/// `TpmLimits` added to the request extensions; a request they refuse is not
+/// counted against the daily request quota. The request's concurrency slot is
+/// held until the response body has been sent.
 pub async fn rate_limit_middleware(
-    request: Request<Body>,
+    State(app): State<AppState>,
+    mut request: Request<Body>,
     next: Next,
-) -> Result<Response, Response> {
-    // Extract key_info from request extensions
-    // The auth middleware should have already set this
-    let key_info = request.extensions().get::<crate::db::KeyInfo>().cloned();
-
-    if let Some(key_info) = key_info {
-        // Get rate limiter from request extensions
-        let rate_limiter = request.extensions().get::<Arc<RateLimiter>>().cloned();
-
-        if let Some(rate_limiter) = rate_limiter {
-            // Check rate limit
-            if let Err(e) = rate_limiter.check(key_info.id, key_info.rate_limit_rpm as u32) {
-                return Err(e.into_response());
+) -> Response {
+    let Some(key_info) = request.extensions().get::<KeyInfo>().cloned() else {
+        tracing::error!("Rate limiting skipped: request was not authenticated");
+        return next.run(request).await;
+    };
+
+    let minute = match app
+        .rate_limiter()
+        .check(key_info.id, key_info.rate_limit_rpm.max(0) as u32)
+        .await
+    {
+        Ok(window) => window,
+        Err(e) => {
+            crate::metrics::RATE_LIMIT_HITS
+                .with_label_values(&[&key_info.tenant_id])
+                .inc();
+            return e.into_response();
+        }
+    };
+
+    let quota_enforcer = app.quota_enforcer();
+    let quotas = quota_enforcer.quotas(&key_info).await;
+
+    // Wait for a slot before counting quotas, so requests rejected here are not counted
+    let permit = match app
+        .concurrency_limiter()
+        .acquire(
+            &key_info.tenant_id,
+            key_info.id,
+            ConcurrencyLimits::from_quotas(&key_info, &quotas),
+        )
+        .await
+    {
+        Ok(permit) => permit,
+        Err(e) => {
+            if let Some(reason) = e.concurrency_reason() {
+                crate::metrics::CONCURRENCY_REJECTIONS
+                    .with_label_values(&[&key_info.tenant_id, reason])
+                    .inc();
             }
+            let mut response = e.into_response();
+            QuotaHeaders::default().or_minute(minute).apply(&mut response);
+            return response;
+        }
+    };
+
+    let admitted_at = time::OffsetDateTime::now_utc();
+    let quota_headers = match quota_enforcer.admit(&key_info, &quotas, admitted_at).await {
+        Ok((headers, tpm_limits)) => {
+            request.extensions_mut().insert(tpm_limits);
+            headers.or_minute(minute)
+        }
+        Err(e) => return e.into_response(),
+    };
+
+    let mut response = next.run(request).await;
+    // A request over its token budget never ran, so it does not use up the daily quota
+    if response.extensions().get::<TokenRateLimitedResponse>().is_some() {
+        quota_enforcer.refund(&key_info, &quotas, admitted_at).await;
+    }
+    quota_headers.apply(&mut response);
+    response.map(|body| Body::new(PermitBody::new(body, permit)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(rate_limit_rpd: i32) -> KeyInfo {
+        KeyInfo {
+            id: Uuid::new_v4(),
+            tenant_id: "acme".to_string(),
+            description: None,
+            rate_limit_rpm: 60,
+            rate_limit_rpd,
+            is_active: true,
+        }
+    }
+
+    fn quota(api_key_id: Option<Uuid>, requests_per_day: Option<i64>, tokens_per_month: Option<i64>) -> db::Quota {
+        db::Quota {
+            tenant_id: "acme".to_string(),
+            api_key_id,
+            requests_per_day,
+            tokens_per_day: None,
+            tokens_per_month,
+            tokens_per_minute: None,
+            max_concurrent_requests: None,
+            scheduling_weight: None,
         }
     }
 
-    Ok(next.run(request).await)
+    #[test]
+    fn test_window_start() {
+        let t = time::OffsetDateTime::from_unix_timestamp(1_750_000_000).unwrap();
+        assert_eq!(window_start(t).unix_timestamp(), 1_749_999_960);
+        assert_eq!(window_start(window_start(t)), window_start(t));
+    }
+
+    #[test]
+    fn test_sliding_window_wait() {
+        let wait = |previous, current, elapsed| sliding_window_wait(previous, current, 100, elapsed).as_secs_f64();
+        // 40 * (1 - 45 / 60) + 90 drops below 100 at 45s into the window
+        assert!((wait(40, 90, 15.0) - 30.0).abs() < 1e-9);
+        assert_eq!(wait(40, 90, 50.0), 0.0);
+        assert_eq!(wait(0, 10, 30.0), 0.0);
+        // Window full: 30s until the next one, then 120 * (1 - 10 / 60) < 100
+        assert!((wait(0, 120, 30.0) - 40.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_key_rpd_is_the_default_daily_limit() {
+        let key = key(1000);
+        assert_eq!(
+            quota_limits(&key, &[]),
+            vec![QuotaLimit { api_key_id: Some(key.id), kind: QuotaKind::RequestsPerDay, limit: 1000 }]
+        );
+        assert!(quota_limits(&KeyInfo { rate_limit_rpd: 0, ..key }, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_quotas_override_key_rpd_and_add_tenant_limits() {
+        let key = key(1000);
+        let quotas = [quota(None, Some(5000), Some(1_000_000)), quota(Some(key.id), Some(50), None)];
+        assert_eq!(
+            quota_limits(&key, &quotas),
+            vec![
+                QuotaLimit { api_key_id: Some(key.id), kind: QuotaKind::RequestsPerDay, limit: 50 },
+                QuotaLimit { api_key_id: None, kind: QuotaKind::RequestsPerDay, limit: 5000 },
+                QuotaLimit { api_key_id: None, kind: QuotaKind::TokensPerMonth, limit: 1_000_000 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_headers_describe_the_tightest_request_quota() {
+        let period_end = time::OffsetDateTime::now_utc() + time::Duration::hours(1);
+        let usage = |api_key_id, kind, limit, used| QuotaUsage {
+            limit: QuotaLimit { api_key_id, kind, limit },
+            used,
+            period_end,
+        };
+        let mut response = StatusCode::OK.into_response();
+        QuotaHeaders::tightest(&[
+            usage(None, QuotaKind::RequestsPerDay, 5000, 4990),
+            usage(Some(Uuid::nil()), QuotaKind::RequestsPerDay, 50, 20),
+            usage(None, QuotaKind::TokensPerDay, 100, 99),
+        ])
+        .apply(&mut response);
+
+        let header = |name| response.headers()[name].to_str().unwrap().to_string();
+        assert_eq!(header("X-RateLimit-Limit"), "5000");
+        assert_eq!(header("X-RateLimit-Remaining"), "10");
+        assert_eq!(header("X-RateLimit-Reset"), "3600");
+    }
+
+    #[test]
+    fn test_headers_fall_back_to_the_minute_limit() {
+        let limiter = RateLimiter::new();
+        let key_id = Uuid::new_v4();
+        let first = limiter.check(key_id, 3).unwrap();
+        assert_eq!(first, MinuteWindow { limit: 3, remaining: 2, reset_after: 20 });
+        limiter.check(key_id, 3).unwrap();
+        assert_eq!(limiter.check(key_id, 3).unwrap().remaining, 0);
+        assert!(matches!(limiter.check(key_id, 3), Err(RateLimitError::Exceeded { limit: 3, .. })));
+
+        let mut response = StatusCode::TOO_MANY_REQUESTS.into_response();
+        QuotaHeaders::default().or_minute(first).apply(&mut response);
+        let header = |name| response.headers()[name].to_str().unwrap().to_string();
+        assert_eq!(header("X-RateLimit-Limit"), "3");
+        assert_eq!(header("X-RateLimit-Remaining"), "2");
+        assert_eq!(header("X-RateLimit-Reset"), "20");
+    }
 }
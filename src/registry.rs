@@ -1,16 +1,34 @@
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::time::Duration;
+use crate::billing::ModelPricing;
 use crate::secret_store::SecretStoreConfig;
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Hash)]
 pub enum ProviderKind {
     OpenRouter,
     Vertex,
     Clewdr,
 }
 
+impl std::fmt::Display for ProviderKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            ProviderKind::OpenRouter => "OpenRouter",
+            ProviderKind::Vertex => "Vertex",
+            ProviderKind::Clewdr => "Clewdr",
+        };
+        f.write_str(s)
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct EgressRoute {
+    /// Identifies the route in load-balancing stats and circuit breakers;
+    /// `<logical model>#<position in the chain>` when not set in the config
+    #[serde(default)]
+    pub id: String,
     pub provider: ProviderKind,
     pub provider_model_id: String,
     #[serde(default)]
@@ -21,48 +39,738 @@ pub struct EgressRoute {
     pub extra: HashMap<String, serde_json::Value>,
     #[serde(default)]
     pub timeouts_ms: Option<u64>,
+    /// Overrides the provider-level retry policy for this route
+    #[serde(default)]
+    pub retry: Option<RetryPolicy>,
+    /// Share of traffic under the `weighted` strategy; 0 keeps the route as a
+    /// failover target only
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    /// Overrides for what the connector reports it can handle on this route
+    #[serde(default)]
+    pub capabilities: CapabilityOverrides,
+    /// Tokenizer used to estimate usage when the provider reports none:
+    /// `claude`, `o200k_base` or `cl100k_base` (inferred from the model id if unset)
+    #[serde(default)]
+    pub tokenizer: Option<String>,
+    /// Price for this route in USD per token, ahead of the price table and OpenRouter
+    #[serde(default)]
+    pub price: Option<ModelPricing>,
+    /// Requests this gateway instance sends to the route at once. Further
+    /// requests queue for a slot; only once the wait times out (or the queue
+    /// is full) does `RouteSaturated` fail over to the next route, and the
+    /// client gets HTTP 429 when no route is left
+    #[serde(default)]
+    pub max_inflight: Option<u32>,
+    /// Secret holding the upstream API key for this route instead of the
+    /// provider's default key, e.g. to spread a model over several OpenRouter keys
+    #[serde(default)]
+    pub api_key_secret: Option<String>,
+}
+
+/// Tokenizers available for local usage estimates
+pub const KNOWN_TOKENIZERS: &[&str] = &["claude", "o200k_base", "cl100k_base"];
+
+impl EgressRoute {
+    /// Tokenizer for local usage estimates on this route
+    pub fn tokenizer(&self) -> &str {
+        if let Some(t) = &self.tokenizer {
+            return t;
+        }
+        let id = self.provider_model_id.to_lowercase();
+        if id.contains("claude") {
+            "claude"
+        } else if ["gpt-4o", "gpt-4.1", "gpt-5", "o1", "o3", "o4"]
+            .iter()
+            .any(|m| id.contains(m))
+        {
+            "o200k_base"
+        } else {
+            "cl100k_base"
+        }
+    }
+}
+
+/// Per-route capability overrides, e.g. `capabilities = { tools = false }` for
+/// a model that does not support function calling behind a tools-capable connector
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CapabilityOverrides {
+    pub text: Option<bool>,
+    pub vision: Option<bool>,
+    pub video: Option<bool>,
+    pub tools: Option<bool>,
+    pub stream: Option<bool>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// How the first route for a logical model is picked; the remaining routes
+/// are kept in configuration order as the failover chain
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LbStrategy {
+    /// Always start with the primary
+    #[default]
+    Priority,
+    Weighted,
+    RoundRobin,
+    LeastLatency,
+    LeastInflight,
+}
+
+/// Classes of transient upstream failures a retry policy may cover
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryClass {
+    /// Request timed out
+    Timeout,
+    /// Upstream answered 429
+    RateLimited,
+    /// Upstream answered 5xx
+    ServerError,
+    /// Connection reset, DNS or other transport failure
+    Connection,
+}
+
+/// Retry policy for a route or provider
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Total attempts per route, including the first one
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_base_delay_ms")]
+    pub base_delay_ms: u64,
+    #[serde(default = "default_max_delay_ms")]
+    pub max_delay_ms: u64,
+    #[serde(default = "default_retry_on")]
+    pub retry_on: Vec<RetryClass>,
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_base_delay_ms() -> u64 {
+    200
+}
+
+fn default_max_delay_ms() -> u64 {
+    5000
+}
+
+fn default_retry_on() -> Vec<RetryClass> {
+    vec![
+        RetryClass::Timeout,
+        RetryClass::RateLimited,
+        RetryClass::ServerError,
+        RetryClass::Connection,
+    ]
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            base_delay_ms: default_base_delay_ms(),
+            max_delay_ms: default_max_delay_ms(),
+            retry_on: default_retry_on(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Single attempt, used when neither the route nor its provider configure retries
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Delay before retry number `attempt` (1-based): exponential backoff with
+    /// full jitter, capped at `max_delay_ms`
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        use rand::Rng;
+        let exp = self
+            .base_delay_ms
+            .saturating_mul(1u64 << attempt.saturating_sub(1).min(32));
+        let cap = exp.min(self.max_delay_ms);
+        Duration::from_millis(rand::thread_rng().gen_range(0..=cap))
+    }
+}
+
+/// Circuit breaker settings, applied to each route of a provider
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failures before the breaker opens (0 disables it)
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// How long the breaker stays open before letting a probe through
+    #[serde(default = "default_open_ms")]
+    pub open_ms: u64,
+}
+
+fn default_failure_threshold() -> u32 {
+    5
+}
+
+fn default_open_ms() -> u64 {
+    30_000
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: default_failure_threshold(),
+            open_ms: default_open_ms(),
+        }
+    }
+}
+
+/// Provider-wide settings from `[providers.<Provider>]`
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProviderSettings {
+    #[serde(default)]
+    pub retry: Option<RetryPolicy>,
+    #[serde(default)]
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+}
+
+/// Where per-minute request limits are counted
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitBackendKind {
+    /// Per gateway process
+    #[default]
+    Memory,
+    /// Shared by all replicas through the database
+    Postgres,
+}
+
+/// `[rate_limit]` settings; read at startup only
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    #[serde(default)]
+    pub backend: RateLimitBackendKind,
 }
 
 #[derive(Default, Clone)]
 pub struct ModelRegistry {
     routes: HashMap<String, Vec<EgressRoute>>,
+    strategies: HashMap<String, LbStrategy>,
+    providers: HashMap<ProviderKind, ProviderSettings>,
     pub secret_store_config: SecretStoreConfig,
+    pub rate_limit_config: RateLimitConfig,
 }
 
 impl ModelRegistry {
-    pub fn resolve(&self, logical_model: &str) -> anyhow::Result<&EgressRoute> {
+    /// Resolve the ordered route chain for a logical model: primary first, then fallbacks
+    pub fn resolve_chain(&self, logical_model: &str) -> anyhow::Result<&[EgressRoute]> {
         self.routes
             .get(logical_model)
-            .and_then(|v| v.first())
+            .filter(|v| !v.is_empty())
+            .map(|v| v.as_slice())
             .ok_or_else(|| anyhow::anyhow!("model '{}' not found", logical_model))
     }
+
+    /// Load-balancing strategy configured for a logical model
+    pub fn strategy(&self, logical_model: &str) -> LbStrategy {
+        self.strategies
+            .get(logical_model)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Effective retry policy for a route: route override, then provider, then none
+    pub fn retry_policy(&self, route: &EgressRoute) -> RetryPolicy {
+        route
+            .retry
+            .clone()
+            .or_else(|| {
+                self.providers
+                    .get(&route.provider)
+                    .and_then(|p| p.retry.clone())
+            })
+            .unwrap_or_else(RetryPolicy::none)
+    }
+
+    /// Circuit breaker settings for a route's provider, falling back to defaults
+    pub fn circuit_breaker_config(&self, route: &EgressRoute) -> CircuitBreakerConfig {
+        self.providers
+            .get(&route.provider)
+            .and_then(|p| p.circuit_breaker.clone())
+            .unwrap_or_default()
+    }
 }
 
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 struct FileModel {
     primary: EgressRoute,
+    /// Tried in order when the primary (or a previous fallback) fails over
+    #[serde(default)]
+    fallbacks: Vec<EgressRoute>,
+    #[serde(default)]
+    strategy: LbStrategy,
 }
 
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 struct FileConfig {
     #[serde(rename = "models")]
     models: HashMap<String, FileModel>,
     #[serde(default)]
+    providers: HashMap<ProviderKind, ProviderSettings>,
+    #[serde(default)]
     secret_store: SecretStoreConfig,
+    #[serde(default)]
+    rate_limit: RateLimitConfig,
+}
+
+/// Connector-level fallbacks a route may rely on instead of setting the field itself
+#[derive(Clone, Debug, Default)]
+pub struct ConnectorDefaults {
+    pub vertex_project: bool,
+    pub vertex_region: bool,
 }
 
-pub async fn load_from_toml(path: &str) -> anyhow::Result<ModelRegistry> {
+impl ConnectorDefaults {
+    /// Defaults the connectors pick up from the environment
+    pub fn from_env() -> Self {
+        let set = |name: &str| std::env::var(name).map(|v| !v.is_empty()).unwrap_or(false);
+        Self {
+            vertex_project: set("VERTEX_PROJECT"),
+            vertex_region: set("VERTEX_REGION"),
+        }
+    }
+}
+
+/// Every problem found in a routing config, reported together
+#[derive(Debug, thiserror::Error)]
+#[error("invalid routing config ({} problem(s)):\n  - {}", .0.len(), .0.join("\n  - "))]
+pub struct ConfigErrors(pub Vec<String>);
+
+/// Route `extra` keys each connector merges into its upstream request body
+pub fn known_extra_keys(provider: &ProviderKind) -> &'static [&'static str] {
+    match provider {
+        ProviderKind::OpenRouter => &["provider", "transforms", "models", "route"],
+        ProviderKind::Vertex => &["safetySettings", "labels", "cachedContent"],
+        ProviderKind::Clewdr => &[],
+    }
+}
+
+/// Load the routing table from `path`. A missing or unreadable file is an
+/// error; set `XJP_ALLOW_EXAMPLE_CONFIG=1` to fall back to the bundled example.
+pub async fn load_from_toml(
+    path: &str,
+    defaults: &ConnectorDefaults,
+) -> anyhow::Result<ModelRegistry> {
     let text = match tokio::fs::read_to_string(path).await {
         Ok(content) => content,
-        Err(_) => tokio::fs::read_to_string("config/xjp.example.toml").await?,
+        Err(e) if std::env::var("XJP_ALLOW_EXAMPLE_CONFIG").as_deref() == Ok("1") => {
+            tracing::warn!(
+                "Cannot read {} ({}), using config/xjp.example.toml because XJP_ALLOW_EXAMPLE_CONFIG=1",
+                path,
+                e
+            );
+            tokio::fs::read_to_string("config/xjp.example.toml").await?
+        }
+        Err(e) => anyhow::bail!("cannot read routing config {}: {}", path, e),
     };
-    let cfg: FileConfig = toml::from_str(&text)?;
+    Ok(from_toml_str(&text, defaults)?)
+}
+
+/// Parse and validate a routing table from TOML text
+pub fn from_toml_str(
+    text: &str,
+    defaults: &ConnectorDefaults,
+) -> Result<ModelRegistry, ConfigErrors> {
+    let cfg: FileConfig = toml::from_str(text).map_err(|e| ConfigErrors(vec![e.to_string()]))?;
+    let problems = validate(&cfg, defaults);
+    if !problems.is_empty() {
+        return Err(ConfigErrors(problems));
+    }
+
     let mut map = HashMap::new();
+    let mut strategies = HashMap::new();
     for (k, v) in cfg.models.into_iter() {
-        map.insert(k, vec![v.primary]);
+        let mut chain = Vec::with_capacity(1 + v.fallbacks.len());
+        chain.push(v.primary);
+        chain.extend(v.fallbacks);
+        for (i, route) in chain.iter_mut().enumerate() {
+            if route.id.is_empty() {
+                route.id = format!("{}#{}", k, i);
+            }
+        }
+        strategies.insert(k.clone(), v.strategy);
+        map.insert(k, chain);
     }
     Ok(ModelRegistry {
         routes: map,
+        strategies,
+        providers: cfg.providers,
         secret_store_config: cfg.secret_store,
+        rate_limit_config: cfg.rate_limit,
     })
 }
+
+fn validate(cfg: &FileConfig, defaults: &ConnectorDefaults) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    // Preloaded secrets are another source of Vertex defaults
+    let preloaded = |key: &str| {
+        cfg.secret_store.enabled && cfg.secret_store.preload_keys.iter().any(|k| k == key)
+    };
+    let vertex_project = defaults.vertex_project || preloaded("providers/vertex/project");
+    let vertex_region = defaults.vertex_region || preloaded("providers/vertex/region");
+
+    let mut names: Vec<&String> = cfg.models.keys().collect();
+    names.sort();
+
+    // TOML already rejects exact duplicates; names that only differ in case
+    // or surrounding whitespace are almost always a copy-paste mistake
+    let mut seen: HashMap<String, &String> = HashMap::new();
+    for name in &names {
+        if name.trim().is_empty() {
+            problems.push("models: empty logical model name".to_string());
+            continue;
+        }
+        if name.trim() != name.as_str() {
+            problems.push(format!(
+                "models.\"{}\": logical name has leading or trailing whitespace",
+                name
+            ));
+        }
+        let normalized = name.trim().to_lowercase();
+        if let Some(other) = seen.insert(normalized, name) {
+            problems.push(format!(
+                "models.\"{}\": duplicate logical name (conflicts with \"{}\")",
+                name, other
+            ));
+        }
+    }
+
+    // Route ids key balancer, breaker and concurrency state, so they are unique across models
+    let mut ids = HashMap::new();
+    for name in names {
+        let model = &cfg.models[name];
+        let routes = std::iter::once(("primary".to_string(), &model.primary)).chain(
+            model
+                .fallbacks
+                .iter()
+                .enumerate()
+                .map(|(i, r)| (format!("fallbacks[{}]", i), r)),
+        );
+        for (slot, route) in routes {
+            let at = format!("models.\"{}\".{}", name, slot);
+            if !route.id.is_empty() {
+                if let Some(other) = ids.insert(route.id.as_str(), at.clone()) {
+                    problems.push(format!(
+                        "{}: duplicate route id \"{}\" (also used by {})",
+                        at, route.id, other
+                    ));
+                }
+            }
+            if route.api_key_secret.is_some() && route.provider != ProviderKind::OpenRouter {
+                problems.push(format!(
+                    "{}: api_key_secret is only supported on OpenRouter routes",
+                    at
+                ));
+            }
+            if route.provider_model_id.trim().is_empty() {
+                problems.push(format!("{}: provider_model_id is empty", at));
+            }
+            if route.provider == ProviderKind::Vertex {
+                if route.project.is_none() && !vertex_project {
+                    problems.push(format!(
+                        "{}: Vertex route has no project and VERTEX_PROJECT is not set",
+                        at
+                    ));
+                }
+                if route.region.is_none() && !vertex_region {
+                    problems.push(format!(
+                        "{}: Vertex route has no region and VERTEX_REGION is not set",
+                        at
+                    ));
+                }
+            }
+            let known = known_extra_keys(&route.provider);
+            let mut unknown: Vec<&String> = route
+                .extra
+                .keys()
+                .filter(|k| !known.contains(&k.as_str()))
+                .collect();
+            unknown.sort();
+            for key in unknown {
+                problems.push(format!(
+                    "{}: unknown extra key \"{}\" for {} (known: {})",
+                    at,
+                    key,
+                    route.provider,
+                    if known.is_empty() {
+                        "none".to_string()
+                    } else {
+                        known.join(", ")
+                    }
+                ));
+            }
+            if route.max_inflight == Some(0) {
+                problems.push(format!("{}: max_inflight must be at least 1", at));
+            }
+            if let Some(t) = route
+                .tokenizer
+                .as_deref()
+                .filter(|t| !KNOWN_TOKENIZERS.contains(t))
+            {
+                problems.push(format!(
+                    "{}: unknown tokenizer \"{}\" (known: {})",
+                    at,
+                    t,
+                    KNOWN_TOKENIZERS.join(", ")
+                ));
+            }
+            if route.retry.as_ref().is_some_and(|r| r.max_attempts == 0) {
+                problems.push(format!("{}: retry.max_attempts must be at least 1", at));
+            }
+        }
+    }
+
+    let mut providers: Vec<_> = cfg.providers.iter().collect();
+    providers.sort_by_key(|(p, _)| p.to_string());
+    for (provider, settings) in providers {
+        if settings.retry.as_ref().is_some_and(|r| r.max_attempts == 0) {
+            problems.push(format!(
+                "providers.{}.retry: max_attempts must be at least 1",
+                provider
+            ));
+        }
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_load_fallback_chain() {
+        let path = std::env::temp_dir().join("xjp_registry_fallbacks.toml");
+        std::fs::write(
+            &path,
+            r#"
+[models."m".primary]
+provider = "OpenRouter"
+provider_model_id = "a/primary"
+
+[[models."m".fallbacks]]
+provider = "Vertex"
+provider_model_id = "publishers/google/models/gemini"
+
+[[models."m".fallbacks]]
+provider = "Clewdr"
+provider_model_id = "clewdr-model"
+"#,
+        )
+        .unwrap();
+
+        let defaults = ConnectorDefaults {
+            vertex_project: true,
+            vertex_region: true,
+        };
+        let registry = load_from_toml(path.to_str().unwrap(), &defaults)
+            .await
+            .unwrap();
+        let chain = registry.resolve_chain("m").unwrap();
+
+        assert_eq!(chain.len(), 3);
+        assert_eq!(chain[0].provider_model_id, "a/primary");
+        assert!(matches!(chain[1].provider, ProviderKind::Vertex));
+        assert!(matches!(chain[2].provider, ProviderKind::Clewdr));
+        assert!(registry.resolve_chain("missing").is_err());
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_retry_backoff_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay_ms: 100,
+            max_delay_ms: 1000,
+            retry_on: default_retry_on(),
+        };
+        for attempt in 1..=10 {
+            let cap = (100u64 << (attempt - 1)).min(1000);
+            assert!(policy.backoff(attempt) <= Duration::from_millis(cap));
+        }
+    }
+
+    #[test]
+    fn test_validation_reports_all_problems() {
+        let text = r#"
+[models."gpt-4o".primary]
+provider = "OpenRouter"
+provider_model_id = "openai/gpt-4o"
+extra = { transforms = ["middle-out"], tempreature = 0.2 }
+
+[models."GPT-4o".primary]
+provider = "OpenRouter"
+provider_model_id = "openai/gpt-4o"
+
+[models."gemini".primary]
+provider = "Vertex"
+provider_model_id = "publishers/google/models/gemini-1.5-pro"
+region = "us-central1"
+"#;
+        let err = match from_toml_str(text, &ConnectorDefaults::default()) {
+            Err(e) => e,
+            Ok(_) => panic!("config should be rejected"),
+        };
+        assert_eq!(err.0.len(), 3, "{:?}", err.0);
+        assert!(err.0.iter().any(|p| p.contains("duplicate logical name")));
+        assert!(err.0.iter().any(|p| p.contains("unknown extra key \"tempreature\"")));
+        assert!(err.0.iter().any(|p| p.contains("Vertex route has no project")));
+
+        let defaults = ConnectorDefaults {
+            vertex_project: true,
+            vertex_region: true,
+        };
+        let fixed = text
+            .replace("GPT-4o", "gpt-4o-mini")
+            .replace(", tempreature = 0.2", "");
+        assert!(from_toml_str(&fixed, &defaults).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_fields_are_rejected() {
+        let text = r#"
+[models."gpt-4o"]
+stratgy = "weighted"
+
+[models."gpt-4o".primary]
+provider = "OpenRouter"
+provider_model_id = "openai/gpt-4o"
+"#;
+        let err = match from_toml_str(text, &ConnectorDefaults::default()) {
+            Err(e) => e,
+            Ok(_) => panic!("config should be rejected"),
+        };
+        assert!(err.0[0].contains("unknown field `stratgy`"), "{:?}", err.0);
+
+        let text = text.replace("stratgy = \"weighted\"", "strategy = \"weighted\"") + "wieght = 3\n";
+        let err = match from_toml_str(&text, &ConnectorDefaults::default()) {
+            Err(e) => e,
+            Ok(_) => panic!("config should be rejected"),
+        };
+        assert!(err.0[0].contains("unknown field `wieght`"), "{:?}", err.0);
+
+        // Misspelled top-level tables
+        let models = text.replace("wieght = 3\n", "");
+        for table in ["[model.\"x\"]", "[ratelimit]"] {
+            let text = format!("{}\nbackend = \"memory\"\n{}", table, models);
+            let err = match from_toml_str(&text, &ConnectorDefaults::default()) {
+                Err(e) => e,
+                Ok(_) => panic!("{} should be rejected", table),
+            };
+            assert!(err.0[0].contains("unknown field"), "{:?}", err.0);
+        }
+    }
+
+    #[test]
+    fn test_rate_limit_backend() {
+        let models = r#"
+[models."gpt-4o".primary]
+provider = "OpenRouter"
+provider_model_id = "openai/gpt-4o"
+"#;
+        let defaults = ConnectorDefaults::default();
+        let registry = from_toml_str(models, &defaults).unwrap();
+        assert_eq!(registry.rate_limit_config.backend, RateLimitBackendKind::Memory);
+
+        let text = format!("[rate_limit]\nbackend = \"postgres\"\n{}", models);
+        let registry = from_toml_str(&text, &defaults).unwrap();
+        assert_eq!(registry.rate_limit_config.backend, RateLimitBackendKind::Postgres);
+
+        let text = format!("[rate_limit]\nbackend = \"redis\"\n{}", models);
+        assert!(from_toml_str(&text, &defaults).is_err());
+    }
+
+    #[test]
+    fn test_route_ids_and_keys() {
+        let text = r#"
+[models."gpt-4o".primary]
+provider = "OpenRouter"
+provider_model_id = "openai/gpt-4o"
+api_key_secret = "providers/openrouter/team-a"
+
+[[models."gpt-4o".fallbacks]]
+id = "gpt-4o-team-b"
+provider = "OpenRouter"
+provider_model_id = "openai/gpt-4o"
+api_key_secret = "providers/openrouter/team-b"
+"#;
+        let registry = from_toml_str(text, &ConnectorDefaults::default()).unwrap();
+        let chain = registry.resolve_chain("gpt-4o").unwrap();
+        assert_eq!(chain[0].id, "gpt-4o#0");
+        assert_eq!(chain[1].id, "gpt-4o-team-b");
+
+        let bad = text.replace(
+            "provider = \"OpenRouter\"\nprovider_model_id = \"openai/gpt-4o\"\napi_key_secret = \"providers/openrouter/team-a\"",
+            "id = \"gpt-4o-team-b\"\nprovider = \"Clewdr\"\nprovider_model_id = \"gpt-4o\"\napi_key_secret = \"clewdr\"",
+        );
+        let err = match from_toml_str(&bad, &ConnectorDefaults::default()) {
+            Err(e) => e,
+            Ok(_) => panic!("config should be rejected"),
+        };
+        assert!(err.0.iter().any(|p| p.contains("duplicate route id \"gpt-4o-team-b\"")), "{:?}", err.0);
+        assert!(err.0.iter().any(|p| p.contains("api_key_secret is only supported on OpenRouter")));
+
+        let other_model = format!(
+            "{}\n[models.\"gpt-4o-mini\".primary]\nid = \"gpt-4o-team-b\"\nprovider = \"OpenRouter\"\nprovider_model_id = \"openai/gpt-4o-mini\"\n",
+            text
+        );
+        let err = match from_toml_str(&other_model, &ConnectorDefaults::default()) {
+            Err(e) => e,
+            Ok(_) => panic!("config should be rejected"),
+        };
+        assert!(
+            err.0.iter().any(|p| p.contains("duplicate route id \"gpt-4o-team-b\" (also used by models.\"gpt-4o\".fallbacks[0])")),
+            "{:?}",
+            err.0
+        );
+    }
+
+    #[test]
+    fn test_tokenizer_inference() {
+        let text = r#"
+[models."sonnet".primary]
+provider = "OpenRouter"
+provider_model_id = "anthropic/claude-3.5-sonnet"
+
+[models."gpt-4o".primary]
+provider = "OpenRouter"
+provider_model_id = "openai/gpt-4o"
+
+[models."local".primary]
+provider = "Clewdr"
+provider_model_id = "llama-3-70b"
+tokenizer = "claude"
+"#;
+        let registry = from_toml_str(text, &ConnectorDefaults::default()).unwrap();
+        let tokenizer = |name: &str| registry.resolve_chain(name).unwrap()[0].tokenizer().to_string();
+        assert_eq!(tokenizer("sonnet"), "claude");
+        assert_eq!(tokenizer("gpt-4o"), "o200k_base");
+        assert_eq!(tokenizer("local"), "claude");
+
+        let bad = text.replace("tokenizer = \"claude\"", "tokenizer = \"llama\"");
+        assert!(from_toml_str(&bad, &ConnectorDefaults::default()).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::metrics::{CONFIG_GENERATION, CONFIG_RELOADS_TOTAL};
+use crate::registry::{self, ConnectorDefaults, ModelRegistry};
+
+/// Swappable routing table. Requests take a snapshot and keep using it until
+/// they finish, so a reload never changes routes under an in-flight request.
+pub struct RegistryHandle {
+    path: String,
+    current: RwLock<Arc<ModelRegistry>>,
+    generation: AtomicU64,
+    /// Serializes reloads triggered from different sources
+    reload_lock: tokio::sync::Mutex<()>,
+}
+
+impl RegistryHandle {
+    pub fn new(path: String, registry: ModelRegistry) -> Self {
+        CONFIG_GENERATION.set(1);
+        Self {
+            path,
+            current: RwLock::new(Arc::new(registry)),
+            generation: AtomicU64::new(1),
+            reload_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Registry snapshot for the lifetime of one request
+    pub fn snapshot(&self) -> Arc<ModelRegistry> {
+        Arc::clone(&self.current.read().unwrap())
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Re-read the config file and swap it in once it has parsed and
+    /// validated. On error the active registry is left untouched.
+    pub async fn reload(&self, trigger: &str) -> anyhow::Result<u64> {
+        let _guard = self.reload_lock.lock().await;
+        let result: anyhow::Result<ModelRegistry> = async {
+            let text = tokio::fs::read_to_string(&self.path).await?;
+            Ok(registry::from_toml_str(&text, &ConnectorDefaults::from_env())?)
+        }
+        .await;
+
+        match result {
+            Ok(next) => {
+                *self.current.write().unwrap() = Arc::new(next);
+                let generation = self.generation.fetch_add(1, Ordering::Relaxed) + 1;
+                CONFIG_GENERATION.set(generation as i64);
+                CONFIG_RELOADS_TOTAL.with_label_values(&["success"]).inc();
+                tracing::info!(
+                    "Registry reloaded from {} ({}), config generation {}",
+                    self.path,
+                    trigger,
+                    generation
+                );
+                Ok(generation)
+            }
+            Err(e) => {
+                CONFIG_RELOADS_TOTAL.with_label_values(&["error"]).inc();
+                tracing::error!(
+                    "Registry reload from {} ({}) rejected, keeping generation {}: {}",
+                    self.path,
+                    trigger,
+                    self.generation(),
+                    e
+                );
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Reload the registry whenever the process receives SIGHUP
+#[cfg(unix)]
+pub fn spawn_sighup_reloader(handle: Arc<RegistryHandle>) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hup.recv().await.is_some() {
+            let _ = handle.reload("SIGHUP").await;
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn spawn_sighup_reloader(_handle: Arc<RegistryHandle>) -> anyhow::Result<()> {
+    Ok(())
+}
+
+/// Poll the config file's modification time and reload when it changes
+pub fn spawn_file_watcher(handle: Arc<RegistryHandle>, interval: Duration) {
+    tokio::spawn(async move {
+        let modified = |path: &str| -> Option<SystemTime> {
+            std::fs::metadata(path).and_then(|m| m.modified()).ok()
+        };
+        let mut last = modified(handle.path());
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let current = modified(handle.path());
+            if current.is_some() && current != last {
+                last = current;
+                let _ = handle.reload("file change").await;
+            }
+        }
+    });
+}
@@ -1,36 +1,66 @@
-use crate::connectors::{self, Connector, ConnectorError, ConnectorResponse};
+use crate::connectors::{
+    self, Connector, ConnectorCapabilities, ConnectorError, ConnectorResponse,
+};
 use crate::core::entities::UnifiedRequest;
-use crate::db::{KeyStore, BillingStore};
-use crate::registry::{ModelRegistry, ProviderKind};
+use crate::db::{KeyStore, BillingStore, CreditStore, InvoiceStore, QuotaStore, RateCardStore};
+use crate::registry::{EgressRoute, ModelRegistry, ProviderKind};
 use crate::secret_store::SecretProvider;
-use crate::billing::{PricingCache, BillingInterceptor};
+use crate::billing::{self, PricingChain, BillingInterceptor, BudgetMonitor, RouteQuote, TokenUsage, TpmLimits};
+use crate::balancer::{InflightGuard, LoadBalancer};
+use crate::circuit_breaker::CircuitBreakers;
+use crate::concurrency::{ConcurrencyConfig, ConcurrencyLimiter, ConcurrencyPermit};
+use crate::ratelimit::{QuotaEnforcer, RateLimitBackend, RateLimitError};
+use crate::reload::RegistryHandle;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use futures_util::StreamExt;
 use uuid::Uuid;
 
+/// Storage and services behind billing
+pub struct BillingServices {
+    pub store: Arc<dyn BillingStore>,
+    pub rate_cards: Arc<dyn RateCardStore>,
+    pub credits: Arc<dyn CreditStore>,
+    pub budgets: Arc<BudgetMonitor>,
+    pub invoices: Arc<dyn InvoiceStore>,
+    /// Request and token quotas, whose token usage is counted from stored transactions
+    pub quotas: Arc<dyn QuotaStore>,
+}
+
 #[derive(Clone)]
 pub struct AppState {
-    registry: Arc<ModelRegistry>,
+    registry: Arc<RegistryHandle>,
     openrouter: Arc<dyn Connector>,
     vertex: Arc<dyn Connector>,
     clewdr: Arc<dyn Connector>,
     key_store: Arc<dyn KeyStore>,
-    pub pricing: Arc<PricingCache>,
+    pub pricing: Arc<PricingChain>,
     billing_store: Arc<dyn BillingStore>,
+    rate_card_store: Arc<dyn RateCardStore>,
+    credit_store: Arc<dyn CreditStore>,
+    budget_monitor: Arc<BudgetMonitor>,
+    invoice_store: Arc<dyn InvoiceStore>,
+    quota_enforcer: Arc<QuotaEnforcer>,
+    rate_limiter: Arc<dyn RateLimitBackend>,
+    concurrency_limiter: ConcurrencyLimiter,
     billing_interceptor: Arc<BillingInterceptor>,
+    breakers: Arc<CircuitBreakers>,
+    balancer: Arc<LoadBalancer>,
 }
 
 impl AppState {
     pub async fn new(
-        registry: ModelRegistry,
+        registry: Arc<RegistryHandle>,
         key_store: Arc<dyn KeyStore>,
         secret_provider: Arc<dyn SecretProvider>,
         preloaded_secrets: HashMap<String, String>,
-        billing_store: Arc<dyn BillingStore>,
+        billing: BillingServices,
+        rate_limiter: Arc<dyn RateLimitBackend>,
     ) -> anyhow::Result<Self> {
-        let pricing = Arc::new(PricingCache::new()?);
+        let pricing = Arc::new(PricingChain::from_env()?);
         Ok(Self {
-            registry: Arc::new(registry),
+            registry,
             openrouter: Arc::new(connectors::openrouter::OpenRouterConnector::new(
                 secret_provider.clone(),
                 &preloaded_secrets,
@@ -45,8 +75,21 @@ impl AppState {
             )?),
             key_store,
             pricing: pricing.clone(),
-            billing_store: billing_store.clone(),
-            billing_interceptor: Arc::new(BillingInterceptor::new(pricing)),
+            billing_store: billing.store,
+            rate_card_store: billing.rate_cards.clone(),
+            credit_store: billing.credits.clone(),
+            budget_monitor: billing.budgets,
+            invoice_store: billing.invoices,
+            quota_enforcer: Arc::new(QuotaEnforcer::new(billing.quotas)),
+            rate_limiter,
+            concurrency_limiter: ConcurrencyLimiter::new(ConcurrencyConfig::from_env()?),
+            billing_interceptor: Arc::new(BillingInterceptor::new(
+                pricing,
+                billing.rate_cards,
+                billing.credits,
+            )),
+            breakers: Arc::new(CircuitBreakers::new()),
+            balancer: Arc::new(LoadBalancer::new()),
         })
     }
 
@@ -58,17 +101,235 @@ impl AppState {
         Arc::clone(&self.billing_store)
     }
 
-    pub async fn invoke(&self, req: UnifiedRequest) -> Result<ConnectorResponse, ConnectorError> {
-        let route = self
-            .registry
-            .resolve(&req.logical_model)
-            .map_err(|e| ConnectorError::Invalid(e.to_string()))?;
-        let connector: &Arc<dyn Connector> = match route.provider {
+    pub fn rate_card_store(&self) -> Arc<dyn RateCardStore> {
+        Arc::clone(&self.rate_card_store)
+    }
+
+    pub fn credit_store(&self) -> Arc<dyn CreditStore> {
+        Arc::clone(&self.credit_store)
+    }
+
+    pub fn budget_monitor(&self) -> Arc<BudgetMonitor> {
+        Arc::clone(&self.budget_monitor)
+    }
+
+    pub fn invoice_store(&self) -> Arc<dyn InvoiceStore> {
+        Arc::clone(&self.invoice_store)
+    }
+
+    pub fn quota_enforcer(&self) -> Arc<QuotaEnforcer> {
+        Arc::clone(&self.quota_enforcer)
+    }
+
+    pub fn rate_limiter(&self) -> Arc<dyn RateLimitBackend> {
+        Arc::clone(&self.rate_limiter)
+    }
+
+    pub fn concurrency_limiter(&self) -> ConcurrencyLimiter {
+        self.concurrency_limiter.clone()
+    }
+
+    pub fn circuit_breakers(&self) -> Arc<CircuitBreakers> {
+        Arc::clone(&self.breakers)
+    }
+
+    pub fn registry(&self) -> Arc<RegistryHandle> {
+        Arc::clone(&self.registry)
+    }
+
+    fn connector_for(&self, provider: &ProviderKind) -> &Arc<dyn Connector> {
+        match provider {
             ProviderKind::OpenRouter => &self.openrouter,
             ProviderKind::Vertex => &self.vertex,
             ProviderKind::Clewdr => &self.clewdr,
+        }
+    }
+
+    /// What a route can serve: its connector's capabilities plus config overrides
+    fn route_capabilities(&self, route: &EgressRoute) -> ConnectorCapabilities {
+        self.connector_for(&route.provider)
+            .capabilities()
+            .with_overrides(&route.capabilities)
+    }
+
+    /// Resolve a model's route chain, rejecting the request up front when no
+    /// route in it can serve the request's content, tools or streaming mode
+    fn resolve<'a>(
+        &self,
+        registry: &'a ModelRegistry,
+        req: &UnifiedRequest,
+    ) -> Result<&'a [EgressRoute], ConnectorError> {
+        let chain = registry
+            .resolve_chain(&req.logical_model)
+            .map_err(|e| ConnectorError::Invalid(e.to_string()))?;
+        let mut problems = Vec::new();
+        for route in chain {
+            let missing = self.route_capabilities(route).missing_for(req);
+            if missing.is_empty() {
+                return Ok(chain);
+            }
+            problems.push(format!(
+                "{}/{} lacks {}",
+                route.provider,
+                route.provider_model_id,
+                missing.join(", ")
+            ));
+        }
+        Err(ConnectorError::Invalid(format!(
+            "no route for model '{}' supports this request ({})",
+            req.logical_model,
+            problems.join("; ")
+        )))
+    }
+
+    /// Wait for one of the route's `max_inflight` slots in the fair queue
+    /// shared with the key and tenant concurrency limits
+    async fn route_slot(
+        &self,
+        route: &EgressRoute,
+        caller: &Caller<'_>,
+    ) -> Result<Option<ConcurrencyPermit>, ConnectorError> {
+        let Some(max_inflight) = route.max_inflight else {
+            return Ok(None);
         };
-        connector.invoke(route, req).await
+        self.concurrency_limiter
+            .acquire_route(caller.tenant_id, caller.api_key_id, &route.id, max_inflight)
+            .await
+            .map(Some)
+            .map_err(|e| {
+                let (reason, retry_after) = match e {
+                    RateLimitError::ConcurrencyExceeded { queue_full: true, retry_after } => ("route_queue_full", retry_after),
+                    RateLimitError::ConcurrencyExceeded { retry_after, .. } => ("route_queue_timeout", retry_after),
+                    _ => ("route_queue_timeout", 1),
+                };
+                crate::metrics::CONCURRENCY_REJECTIONS
+                    .with_label_values(&[caller.tenant_id, reason])
+                    .inc();
+                ConnectorError::RouteSaturated {
+                    route: format!("{}/{}", route.provider, route.provider_model_id),
+                    retry_after: Duration::from_secs(retry_after),
+                }
+            })
+    }
+
+    /// Invoke a single route, retrying transient failures per its retry policy.
+    /// Retries happen before a response is handed back, so a stream that has
+    /// started emitting chunks is never replayed. An open circuit breaker
+    /// rejects immediately so the chain can fall through to the next route; a
+    /// route at its `max_inflight` does so once the request timed out waiting.
+    async fn invoke_route(
+        &self,
+        registry: &ModelRegistry,
+        route: &EgressRoute,
+        req: &UnifiedRequest,
+        caller: &Caller<'_>,
+    ) -> Result<ConnectorResponse, ConnectorError> {
+        let policy = registry.retry_policy(route);
+        let breaker_cfg = registry.circuit_breaker_config(route);
+        let connector = self.connector_for(&route.provider);
+        let mut attempt = 1;
+        loop {
+            // Taken before the breaker so a full route never takes a half-open probe slot
+            let slot = self.route_slot(route, caller).await?;
+            let inflight = self.balancer.start(route);
+            if !self.breakers.try_acquire(route, &breaker_cfg) {
+                return Err(ConnectorError::CircuitOpen(format!(
+                    "{}/{}",
+                    route.provider, route.provider_model_id
+                )));
+            }
+            let started = Instant::now();
+            let err = match connector.invoke(route, req.clone()).await {
+                Ok(response) => {
+                    self.balancer.record_latency(route, started.elapsed());
+                    self.breakers.record_success(route);
+                    return Ok(hold_inflight(response, (inflight, slot)));
+                }
+                Err(e) => e,
+            };
+            self.breakers.record_error(route, &breaker_cfg, &err);
+            // Client errors say nothing about the route's health
+            if err.is_failover() {
+                self.balancer.record_failure(route, started.elapsed());
+            }
+            let retryable = err
+                .retry_class()
+                .is_some_and(|class| policy.retry_on.contains(&class));
+            if !retryable || attempt >= policy.max_attempts {
+                return Err(err);
+            }
+            let delay = match err.retry_after() {
+                // Upstream wants us to back off longer than we are willing to wait
+                Some(hint) if hint > Duration::from_millis(policy.max_delay_ms) => return Err(err),
+                Some(hint) => hint,
+                None => policy.backoff(attempt),
+            };
+            tracing::warn!(
+                "route {}/{} attempt {}/{} failed: {}, retrying in {:?}",
+                route.provider,
+                route.provider_model_id,
+                attempt,
+                policy.max_attempts,
+                err,
+                delay
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Walk a route chain, starting with the route picked by the model's
+    /// load-balancing strategy and failing over to the next route on upstream,
+    /// timeout or rate-limit errors. Returns the final result together with the
+    /// route that produced it and the number of attempts made.
+    async fn invoke_chain<'a>(
+        &self,
+        registry: &ModelRegistry,
+        chain: &'a [EgressRoute],
+        req: UnifiedRequest,
+        caller: &Caller<'_>,
+    ) -> (
+        Result<ConnectorResponse, ConnectorError>,
+        &'a EgressRoute,
+        u32,
+    ) {
+        let strategy = registry.strategy(&req.logical_model);
+        // Routes that cannot serve the request are skipped rather than sent a
+        // degraded version of it; `resolve` guarantees at least one remains
+        let ordered: Vec<&EgressRoute> = self
+            .balancer
+            .order(&req.logical_model, strategy, chain)
+            .into_iter()
+            .filter(|route| {
+                let missing = self.route_capabilities(route).missing_for(&req);
+                if !missing.is_empty() {
+                    tracing::debug!(
+                        "skipping route {}/{} for '{}': lacks {}",
+                        route.provider,
+                        route.provider_model_id,
+                        req.logical_model,
+                        missing.join(", ")
+                    );
+                }
+                missing.is_empty()
+            })
+            .collect();
+        let last = ordered.len().saturating_sub(1);
+        for (i, route) in ordered.into_iter().enumerate() {
+            match self.invoke_route(registry, route, &req, caller).await {
+                Err(e) if e.is_failover() && i < last => {
+                    tracing::warn!(
+                        "route {}/{} failed for '{}': {}, failing over",
+                        route.provider,
+                        route.provider_model_id,
+                        req.logical_model,
+                        e
+                    );
+                }
+                result => return (result, route, i as u32 + 1),
+            }
+        }
+        unreachable!("resolve ensures a capable route")
     }
 
     /// Invoke with billing tracking (for authenticated requests)
@@ -77,74 +338,126 @@ impl AppState {
         req: UnifiedRequest,
         tenant_id: String,
         api_key_id: Uuid,
+        tpm_limits: TpmLimits,
     ) -> Result<ConnectorResponse, ConnectorError> {
-        let route = self
-            .registry
-            .resolve(&req.logical_model)
-            .map_err(|e| ConnectorError::Invalid(e.to_string()))?;
+        let registry = self.registry.snapshot();
+        let chain = self.resolve(&registry, &req)?;
 
         // Create billing context before request
-        let billing_ctx = self.billing_interceptor.before_request(
+        let mut billing_ctx = self.billing_interceptor.before_request(
             &req,
             tenant_id,
             api_key_id,
-            route.provider.to_string(),
-            route.provider_model_id.clone(),
+            chain[0].provider.to_string(),
+            chain[0].provider_model_id.clone(),
+            chain[0].tokenizer().to_string(),
         );
+        billing_ctx.route_price = chain[0].price.clone();
+        billing_ctx.tpm_limits = tpm_limits;
 
-        // Execute actual request
-        let connector: &Arc<dyn Connector> = match route.provider {
-            ProviderKind::OpenRouter => &self.openrouter,
-            ProviderKind::Vertex => &self.vertex,
-            ProviderKind::Clewdr => &self.clewdr,
+        // The request must fit the token budgets of its key and tenant, and
+        // prepaid tenants must be able to cover it on any route, before it runs
+        let quotes: Vec<RouteQuote> = chain
+            .iter()
+            .map(|route| RouteQuote {
+                provider_model_id: &route.provider_model_id,
+                tokenizer: route.tokenizer(),
+                price: route.price.as_ref(),
+            })
+            .collect();
+        if let Err(e) = self
+            .billing_interceptor
+            .reserve(&mut billing_ctx, &quotes, req.max_output_tokens)
+            .await
+        {
+            if matches!(e, ConnectorError::TokenRateLimited { .. }) {
+                crate::metrics::RATE_LIMIT_HITS
+                    .with_label_values(&[&billing_ctx.tenant_id])
+                    .inc();
+            }
+            return Err(e);
+        }
+
+        // Execute actual request, walking the fallback chain
+        let caller = Caller {
+            tenant_id: &billing_ctx.tenant_id,
+            api_key_id,
         };
-        let result = connector.invoke(route, req).await;
+        let (result, route, attempts) = self.invoke_chain(&registry, chain, req, &caller).await;
+
+        // Bill against the route that actually served (or last failed) the request
+        billing_ctx.provider = route.provider.to_string();
+        billing_ctx.provider_model_id = route.provider_model_id.clone();
+        billing_ctx.attempts = attempts;
+        billing_ctx.tokenizer = route.tokenizer().to_string();
+        billing_ctx.route_price = route.price.clone();
 
-        // Record billing (async, non-blocking)
         let interceptor = self.billing_interceptor.clone();
         let billing_store = self.billing_store.clone();
-        match &result {
-            Ok(response) => {
-                tokio::spawn(async move {
-                    match interceptor.after_request(
-                        billing_ctx,
-                        response,
+        match result {
+            // Streams are billed when they end, with the usage from their final chunks
+            Ok(ConnectorResponse::Streaming(stream)) => Ok(ConnectorResponse::Streaming(
+                billing::stream::bill_stream(stream, billing_ctx, interceptor, billing_store),
+            )),
+            result => {
+                billing_ctx.response_time = Some(billing_ctx.start_time.elapsed());
+                let (usage, completion, status, error_message) = match &result {
+                    Ok(response) => (
+                        interceptor.extract_usage(response).unwrap_or_else(|e| {
+                            tracing::warn!("Unparseable provider usage: {}", e);
+                            None
+                        }),
+                        match response {
+                            ConnectorResponse::NonStreaming(chunk) => {
+                                billing::interceptor::completion_text(chunk)
+                            }
+                            ConnectorResponse::Streaming(_) => String::new(),
+                        },
                         "success",
                         None,
-                    ).await {
-                        Ok(transaction) => {
-                            if let Err(e) = billing_store.insert_transaction(transaction).await {
-                                tracing::error!("Failed to insert billing transaction: {}", e);
-                            }
-                        }
-                        Err(e) => {
-                            tracing::error!("Failed to process billing: {}", e);
-                        }
-                    }
-                });
-            }
-            Err(e) => {
-                // Record failed request
-                tokio::spawn(async move {
-                    match interceptor.after_request(
-                        billing_ctx,
-                        &ConnectorResponse::NonStreaming(Default::default()),
-                        "error",
+                    ),
+                    // Every route failed before producing output: nothing to estimate
+                    Err(e) => (
+                        Some(TokenUsage::default()),
+                        String::new(),
+                        billing::stream::error_status(e),
                         Some(e.to_string()),
-                    ).await {
-                        Ok(transaction) => {
-                            if let Err(e) = billing_store.insert_transaction(transaction).await {
-                                tracing::error!("Failed to insert billing transaction: {}", e);
-                            }
-                        }
-                        Err(e) => {
-                            tracing::error!("Failed to process billing for error case: {}", e);
-                        }
-                    }
-                });
+                    ),
+                };
+                // Record billing (async, non-blocking)
+                billing::stream::record(
+                    interceptor,
+                    billing_store,
+                    billing_ctx,
+                    usage,
+                    completion,
+                    status,
+                    error_message,
+                );
+                result
             }
         }
+    }
+}
+
+/// Tenant and key a request is invoked for
+struct Caller<'a> {
+    tenant_id: &'a str,
+    api_key_id: Uuid,
+}
 
-        result
+/// Keep a route counted as in flight until a streaming response is fully consumed
+fn hold_inflight(
+    response: ConnectorResponse,
+    guard: (InflightGuard, Option<ConcurrencyPermit>),
+) -> ConnectorResponse {
+    match response {
+        ConnectorResponse::Streaming(stream) => {
+            ConnectorResponse::Streaming(Box::pin(stream.map(move |item| {
+                let _ = &guard;
+                item
+            })))
+        }
+        ConnectorResponse::NonStreaming(chunk) => ConnectorResponse::NonStreaming(chunk),
     }
 }